@@ -0,0 +1,246 @@
+use crate::warn;
+use anyhow::{ensure, Context, Result};
+use std::path::{Path, PathBuf};
+use stocks::portfolio::StorageFormat;
+
+/// The environment variable with the path of the portfolio, it overrides the named portfolios.
+pub const PORTFOLIO_ENV_VAR: &str = "STOCKS_PORTFOLIO";
+/// The portfolio used until another one is created and switched to.
+pub const DEFAULT_PORTFOLIO: &str = "default";
+/// Older versions kept the portfolio in the current working directory.
+const LEGACY_PORTFOLIO_PATH: &str = "portfolio.json";
+
+/// The directory where the named portfolios and the price cache are kept, it's
+/// `$XDG_DATA_HOME/stocks`, or `~/.local/share/stocks` when the variable is not set.
+///
+/// Each named portfolio is a file in the `portfolios` directory and the `current` file holds the
+/// name of the one in use.
+pub struct DataDir {
+    root: PathBuf,
+}
+
+impl DataDir {
+    pub fn locate() -> Result<Self> {
+        let data_home = match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => std::env::var_os("HOME")
+                .map(|home| Path::new(&home).join(".local").join("share"))
+                .context(
+                    "Could not find the data directory, neither XDG_DATA_HOME nor HOME are set",
+                )?,
+        };
+
+        let root = data_home.join("stocks");
+        std::fs::create_dir_all(root.join("portfolios"))
+            .with_context(|| format!("Could not create the data directory {root:?}"))?;
+
+        Ok(Self { root })
+    }
+
+    pub fn price_cache_path(&self) -> PathBuf {
+        self.root.join("prices.json")
+    }
+
+    /// The file of the named portfolio, it's a JSON file unless it was converted to another format.
+    pub fn portfolio_path(&self, name: &str) -> PathBuf {
+        let path = self.root.join("portfolios").join(name);
+
+        [StorageFormat::Sqlite, StorageFormat::Json]
+            .iter()
+            .map(|format| path.with_extension(format.extension()))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| path.with_extension(StorageFormat::Json.extension()))
+    }
+
+    /// The path of the portfolio in use, it's the `--portfolio` option, the environment variable or
+    /// the current named portfolio, in this order. The named portfolios other than the default one
+    /// must be created first.
+    pub fn resolve(&self, portfolio: Option<PathBuf>) -> Result<PathBuf> {
+        if let Some(path) = portfolio {
+            return given_path(path, "the --portfolio option");
+        }
+
+        if let Some(path) = std::env::var_os(PORTFOLIO_ENV_VAR).filter(|path| !path.is_empty()) {
+            return given_path(
+                PathBuf::from(path),
+                &format!("the {PORTFOLIO_ENV_VAR} environment variable"),
+            );
+        }
+
+        let name = self.current()?;
+        let path = self.portfolio_path(&name);
+
+        let legacy_path = Path::new(LEGACY_PORTFOLIO_PATH);
+        if !path.exists() && legacy_path.exists() {
+            warn!(
+                "Using the {LEGACY_PORTFOLIO_PATH} in the current directory, move it to {path:?} to use it from anywhere."
+            );
+            return Ok(legacy_path.to_path_buf());
+        }
+
+        ensure!(
+            name == DEFAULT_PORTFOLIO || path.exists(),
+            "There is no portfolio named `{name}`, create it with `portfolio create {name}`"
+        );
+
+        Ok(path)
+    }
+
+    /// The name of the portfolio in use.
+    pub fn current(&self) -> Result<String> {
+        match std::fs::read_to_string(self.root.join("current")) {
+            Ok(name) => Ok(name.trim().to_string()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Ok(DEFAULT_PORTFOLIO.to_string())
+            }
+            Err(err) => Err(err).context("Could not read the current portfolio"),
+        }
+    }
+
+    /// The names of the portfolios, sorted alphabetically. The default portfolio is always listed,
+    /// even before it's saved for the first time.
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut names = vec![DEFAULT_PORTFOLIO.to_string()];
+
+        for entry in std::fs::read_dir(self.root.join("portfolios"))? {
+            let path = entry?.path();
+
+            if path.extension().is_some_and(|extension| {
+                extension == StorageFormat::Json.extension()
+                    || extension == StorageFormat::Sqlite.extension()
+            }) {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        names.sort();
+        names.dedup();
+
+        Ok(names)
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        name == DEFAULT_PORTFOLIO || self.portfolio_path(name).exists()
+    }
+
+    pub fn switch(&self, name: &str) -> Result<()> {
+        ensure!(
+            self.exists(name),
+            "There is no portfolio named `{name}`, create it with `portfolio create {name}`"
+        );
+
+        std::fs::write(self.root.join("current"), name)
+            .context("Could not save the current portfolio")
+    }
+}
+
+/// A portfolio given by its path is started when missing, but only in an existing directory, so a
+/// mistyped directory never starts an empty portfolio. The `source` of the path is reported.
+fn given_path(path: PathBuf, source: &str) -> Result<PathBuf> {
+    if path.exists() {
+        return Ok(path);
+    }
+
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    ensure!(
+        directory.is_dir(),
+        "There is no directory {directory:?} for the portfolio, check {source}"
+    );
+
+    Ok(path)
+}
+
+/// The names become file names, so they are restricted to letters, digits, `-` and `_`.
+pub fn validate_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_'),
+        "Invalid portfolio name `{name}`, use only letters, digits, `-` and `_`"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn data_dir(dir: &TempDir) -> DataDir {
+        let root = dir.path().join("stocks");
+        std::fs::create_dir_all(root.join("portfolios")).expect("The directory should be created");
+
+        DataDir { root }
+    }
+
+    #[test]
+    fn resolve_starts_a_new_portfolio_in_an_existing_directory() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let data_dir = data_dir(&dir);
+
+        let path = dir.path().join("new.json");
+        assert_eq!(
+            data_dir
+                .resolve(Some(path.clone()))
+                .expect("The path should be valid"),
+            path
+        );
+
+        let mistyped = dir.path().join("missing").join("new.json");
+        assert!(data_dir.resolve(Some(mistyped)).is_err());
+    }
+
+    #[test]
+    fn switch_requires_a_created_portfolio() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let data_dir = data_dir(&dir);
+
+        assert!(data_dir.switch("spouse").is_err());
+
+        std::fs::write(data_dir.portfolio_path("spouse"), "{}")
+            .expect("The portfolio should be written");
+        data_dir
+            .switch("spouse")
+            .expect("The portfolio should exist");
+
+        assert_eq!(
+            data_dir
+                .current()
+                .expect("The current portfolio should be read"),
+            "spouse"
+        );
+        assert_eq!(
+            data_dir.list().expect("The portfolios should be listed"),
+            ["default", "spouse"]
+        );
+    }
+
+    #[test]
+    fn portfolio_path_finds_the_converted_portfolio() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let data_dir = data_dir(&dir);
+
+        let sqlite = data_dir.root.join("portfolios").join("default.sqlite");
+        std::fs::write(&sqlite, "").expect("The portfolio should be written");
+
+        assert_eq!(data_dir.portfolio_path("default"), sqlite);
+        assert_eq!(
+            data_dir.portfolio_path("other"),
+            data_dir.root.join("portfolios").join("other.json")
+        );
+    }
+
+    #[test]
+    fn validate_name_rejects_the_path_separators() {
+        assert!(validate_name("spouse_2").is_ok());
+        assert!(validate_name("../spouse").is_err());
+        assert!(validate_name("").is_err());
+    }
+}
@@ -1,46 +1,251 @@
+mod data_dir;
 mod log;
 mod render;
 
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 
-use crate::render::{render_profit_by_month, render_summary, ProfitSummaryData, SummaryData};
+use crate::data_dir::{validate_name, DataDir, PORTFOLIO_ENV_VAR};
+use crate::render::{
+    render_backups, render_darf, render_income, render_migrations, render_open_lots,
+    render_portfolios, render_profit_by_month, render_realized_lots, render_summary,
+    render_tax_declaration, render_total_tax, AssetData, BackupData, DarfData, DeclaredIncomeData,
+    IncomeData, OpenLotData, ProfitSummaryData, RealizedLotData, SummaryData, TaxDeclarationData,
+};
 use anyhow::{Context, Result};
-use stocks::portfolio::Portfolio;
+use rust_decimal::Decimal;
+use stocks::calendar::last_day;
+use stocks::import::profile::Profile;
+use stocks::import::{self, b3, brokerage_note, dump, LineError, OperationKind, Statement};
+use stocks::money::{percentage, round_money};
+use stocks::portfolio::{
+    AssetClass, CostBasis, Currency, FeeSchedule, Fees, Income, IncomeKind, IncomeSummary,
+    LockMode, LotId, Portfolio, PortfolioLock, StorageFormat, TradeDetails, JCP_WITHHOLDING_RATE,
+    SCHEMA_VERSION,
+};
 use stocks::stock_market::PricedStock;
-use stocks::stock_market::StockMarket;
-use time::{format_description, Date, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use stocks::stock_market::{
+    Bcb, LocalPriceProvider, PriceCache, StockMarket, DEFAULT_MAX_AGE_MINUTES,
+};
+use stocks::tax::{
+    darf, tax_by_month, Darf, DeclaredIncome, RegimeTax, TaxDeclaration, EXEMPT_SALES_CODE,
+};
+use time::{format_description, Date, Month, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+/// How often the summary is refreshed in the watch mode.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(20 * 60);
+/// How often the watch mode checks whether the portfolio changed.
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
 
 enum Command {
     Buy {
         stock: String,
         quantity: u32,
-        price: f64,
+        price: Decimal,
         datetime: Option<OffsetDateTime>,
+        fees: FeeOptions,
+        details: TradeDetails,
     },
     Sell {
         stock: String,
         quantity: u32,
-        price: f64,
+        price: Decimal,
         datetime: Option<OffsetDateTime>,
+        fees: FeeOptions,
+        details: TradeDetails,
     },
     Summary {
         date: Option<Date>,
         watch: bool,
+        /// Read the prices from a local file instead of the network.
+        prices: Option<PathBuf>,
+        /// Only use the cached prices.
+        offline: bool,
+        /// For how many minutes a cached price is considered fresh.
+        max_age: i64,
+        /// The currency of the report.
+        currency: Currency,
     },
     ProfitSummary {
         year: i32,
+        currency: Currency,
+    },
+    Darf {
+        year: i32,
+        month: Month,
+        /// The default is today when the DARF is overdue, otherwise its due date.
+        payment_date: Option<Date>,
+        /// The accumulated Selic rate, in percentage, fetched from the BCB when not given.
+        selic: Option<Decimal>,
+        json: bool,
     },
     Split {
         stock: String,
-        ratio: f64,
+        ratio: Decimal,
+        fraction_price: Option<Decimal>,
+        date: Option<Date>,
+    },
+    ReverseSplit {
+        stock: String,
+        ratio: Decimal,
+        fraction_price: Option<Decimal>,
+        date: Option<Date>,
+    },
+    Bonus {
+        stock: String,
+        percentage: Decimal,
+        unit_cost: Decimal,
+        fraction_price: Option<Decimal>,
+        date: Option<Date>,
+    },
+    Rename {
+        stock: String,
+        new_stock: String,
+        date: Option<Date>,
+    },
+    Merger {
+        stock: String,
+        into: String,
+        ratio: Decimal,
+        fraction_price: Option<Decimal>,
+        date: Option<Date>,
+    },
+    SpinOff {
+        stock: String,
+        into: String,
+        ratio: Decimal,
+        cost_percentage: Decimal,
+        fraction_price: Option<Decimal>,
         date: Option<Date>,
     },
     DumpTrades {
         path: PathBuf,
     },
+    Broker {
+        name: String,
+        schedule: FeeSchedule,
+    },
+    Dividend {
+        stock: String,
+        kind: IncomeKind,
+        amount: Decimal,
+        withheld_tax: Option<Decimal>,
+        ex_date: Date,
+        payment_date: Date,
+    },
+    Income {
+        year: i32,
+    },
+    Cnpj {
+        stock: String,
+        cnpj: String,
+    },
+    Currency {
+        stock: String,
+        currency: Currency,
+    },
+    CostBasis {
+        cost_basis: CostBasis,
+    },
+    Lots {
+        stock: String,
+        date: Option<Date>,
+        /// Overrides the cost basis of the portfolio.
+        cost_basis: Option<CostBasis>,
+    },
+    AssetClass {
+        stock: String,
+        /// `None` infers the class from the symbol.
+        class: Option<AssetClass>,
+    },
+    TaxDeclaration {
+        year: i32,
+    },
+    Portfolio(PortfolioAction),
+    Restore {
+        backup: Option<String>,
+    },
+    Migrate {
+        check: bool,
+    },
+    Convert {
+        format: StorageFormat,
+    },
+    Import {
+        path: PathBuf,
+        format: ImportFormat,
+        /// The symbols of the stocks named in the brokerage notes.
+        symbols: HashMap<String, String>,
+        dry_run: bool,
+    },
     Help,
 }
 
+/// The files the trades can be imported from.
+enum ImportFormat {
+    /// The file written by the `dump` command.
+    Dump,
+    /// The `Negociação` and `Movimentação` statements from the B3's Área do Investidor.
+    B3,
+    /// The text of the brokerage notes (notas de corretagem) in the SINACOR layout.
+    BrokerageNote,
+    /// Any CSV file or spreadsheet, its columns are mapped by the profile at the path.
+    Profile(PathBuf),
+}
+
+impl Command {
+    /// The commands that change the portfolio hold it exclusively.
+    fn lock_mode(&self) -> LockMode {
+        match self {
+            Command::Buy { .. }
+            | Command::Sell { .. }
+            | Command::Split { .. }
+            | Command::ReverseSplit { .. }
+            | Command::Bonus { .. }
+            | Command::Rename { .. }
+            | Command::Merger { .. }
+            | Command::SpinOff { .. }
+            | Command::Broker { .. }
+            | Command::Dividend { .. }
+            | Command::Cnpj { .. }
+            | Command::Currency { .. }
+            | Command::CostBasis { .. }
+            | Command::AssetClass { .. }
+            | Command::Restore { backup: Some(_) }
+            | Command::Migrate { .. }
+            | Command::Convert { .. }
+            | Command::Import { .. } => LockMode::Exclusive,
+            Command::Summary { .. }
+            | Command::ProfitSummary { .. }
+            | Command::Darf { .. }
+            | Command::DumpTrades { .. }
+            | Command::Income { .. }
+            | Command::Lots { .. }
+            | Command::TaxDeclaration { .. }
+            | Command::Portfolio(_)
+            | Command::Restore { backup: None }
+            | Command::Help => LockMode::Shared,
+        }
+    }
+}
+
+/// The management of the named portfolios.
+enum PortfolioAction {
+    Create { name: String },
+    List,
+    Switch { name: String },
+}
+
+/// The fees of a trade, they come from the broker's fee schedule unless explicitly given.
+#[derive(Default)]
+struct FeeOptions {
+    broker: Option<String>,
+    brokerage: Option<Decimal>,
+    emoluments: Option<Decimal>,
+    settlement: Option<Decimal>,
+    iss: Option<Decimal>,
+}
+
 fn main() -> Result<()> {
     let mut args = std::env::args();
 
@@ -49,8 +254,10 @@ fn main() -> Result<()> {
         std::process::exit(1);
     };
 
-    let command = match parse_command(args) {
-        Ok(command) => command,
+    let (portfolio_path, command) = match take_portfolio_option(args)
+        .and_then(|(path, args)| Ok((path, parse_command(args)?)))
+    {
+        Ok(parsed) => parsed,
         Err(err) => {
             usage(&program);
             println!();
@@ -59,11 +266,33 @@ fn main() -> Result<()> {
         }
     };
 
-    let mut portfolio = Portfolio::load().unwrap_or_else(|err| {
-        warn!("Could not load portfolio: {err}");
-        info!("Creating a new portfolio.");
-        Portfolio::new()
-    });
+    let data_dir = DataDir::locate()?;
+
+    let command = match command {
+        Command::Portfolio(action) => return manage_portfolios(&data_dir, action),
+        command => command,
+    };
+
+    let path = data_dir.resolve(portfolio_path)?;
+    // The SQLite portfolios keep the prices in their own tables.
+    let price_cache_path = match StorageFormat::from_path(&path) {
+        StorageFormat::Json => data_dir.price_cache_path(),
+        StorageFormat::Sqlite => path.clone(),
+    };
+
+    if let Command::Migrate { check } = command {
+        return migrate_portfolio(&path, check);
+    }
+
+    let lock_mode = command.lock_mode();
+    let lock = lock_portfolio(&path, lock_mode)?;
+    let mut portfolio = load_portfolio(&path)?;
+
+    // The readers only need a consistent copy of the portfolio, while the writers keep the lock
+    // until the portfolio is saved.
+    if lock_mode == LockMode::Shared {
+        drop(lock);
+    }
 
     match command {
         Command::Buy {
@@ -71,13 +300,28 @@ fn main() -> Result<()> {
             quantity,
             price,
             datetime,
+            fees,
+            details,
         } => {
             let datetime = datetime.unwrap_or_else(|| {
                 OffsetDateTime::now_local().expect("BUG: Could not get the local time.")
             });
 
-            portfolio.buy(stock.as_str(), quantity, price, datetime);
-            info!("You bought {quantity} {stock} at R${price:10.2}.");
+            let fees = fees.resolve(&portfolio, quantity, price)?;
+            let details = with_fx_rate(
+                &portfolio,
+                stock.as_str(),
+                details,
+                datetime,
+                &price_cache_path,
+            )?;
+
+            portfolio.buy(stock.as_str(), quantity, price, fees, details, datetime);
+            let symbol = portfolio.stocks[&stock].currency.symbol();
+            info!("You bought {quantity} {stock} at {symbol}{price:10.2}.");
+            if let Err(err) = portfolio.stocks[&stock].class() {
+                warn!("{err}");
+            }
             portfolio.save()?;
         }
         Command::Sell {
@@ -85,114 +329,367 @@ fn main() -> Result<()> {
             quantity,
             price,
             datetime,
+            fees,
+            details,
         } => {
             let datetime = datetime.unwrap_or_else(|| {
                 OffsetDateTime::now_local().expect("BUG: Could not get the local time.")
             });
 
-            let profit = portfolio.sell(stock.as_str(), quantity, price, datetime)?;
-            info!("You sold {quantity} {stock} profiting R${profit:10.2}.");
+            let fees = fees.resolve(&portfolio, quantity, price)?;
+            let details = with_fx_rate(
+                &portfolio,
+                stock.as_str(),
+                details,
+                datetime,
+                &price_cache_path,
+            )?;
+
+            let profit =
+                portfolio.sell(stock.as_str(), quantity, price, fees, details, datetime)?;
+            let symbol = portfolio.stocks[&stock].currency.symbol();
+            info!("You sold {quantity} {stock} profiting {symbol}{profit:10.2}.");
             portfolio.save()?;
         }
-        Command::Summary { date, watch } => {
-            let stock_market = StockMarket::new();
-
-            let datetime = date
-                .map(|date| {
-                    date.with_time(
-                        time::Time::from_hms(23, 59, 59).expect("BUG: Should be a valid time"),
-                    )
-                    .assume_offset(
-                        UtcOffset::current_local_offset()
-                            .expect("BUG: Could not get the local offset."),
-                    )
-                })
-                .unwrap_or_else(|| {
-                    OffsetDateTime::now_local().expect("BUG: Could not get the local time.")
-                });
+        Command::Summary {
+            date,
+            watch,
+            prices,
+            offline,
+            max_age,
+            currency,
+        } => {
+            let datetime = end_of_day_or_now(date);
 
-            let stocks: Vec<_> = portfolio
-                .stocks
-                .into_values()
-                // To ensure that we only show stocks that we own
-                .filter(|stock| stock.quantity(datetime) > 0)
-                .collect();
+            let mut stock_market = match prices {
+                Some(path) => {
+                    let provider = LocalPriceProvider::load(&path)?;
+                    StockMarket::with_provider(provider.clone()).with_fx_provider(provider)
+                }
+                None => StockMarket::new().with_cache(
+                    load_price_cache(&price_cache_path),
+                    time::Duration::minutes(max_age),
+                ),
+            };
+
+            if offline {
+                stock_market = stock_market.offline();
+            }
+
+            let datetime_format = format_description::parse_borrowed::<1>(
+                "[year]-[month]-[day] [hour]:[minute]:[second]",
+            )?;
+
+            let mut modified_at = modification_stamp(&path);
 
             loop {
-                let priced_stocks = stock_market.get_stock_prices(&stocks, datetime);
+                let converted =
+                    portfolio.convert(currency, |from, date| stock_market.fx_rate(from, date))?;
+
+                let mut stocks = vec![];
+                for stock in portfolio.stocks.values() {
+                    // To ensure that we only show stocks that we own
+                    if stock.quantity(&portfolio, datetime)? > 0 {
+                        stocks.push(stock);
+                    }
+                }
+
+                // The value of one unit of each stock's currency in the report currency, the stocks
+                // without a rate are skipped as the ones without a price.
+                let today = datetime.date();
+                let base_rate = summary_fx_rate(&mut stock_market, currency, today)?;
+                let mut currency_rates = HashMap::new();
+                for stock_currency in stocks.iter().map(|stock| stock.currency) {
+                    if currency_rates.contains_key(&stock_currency) {
+                        continue;
+                    }
 
-                let stock_count = priced_stocks.len();
-                let data: Vec<SummaryData> = priced_stocks
-                    .into_iter()
-                    .filter_map(|maybe_stock| maybe_stock.map(|stock| stock.into()).ok())
+                    let rate = summary_fx_rate(&mut stock_market, stock_currency, today)
+                        .map_err(|err| {
+                            warn!("{err}, the stocks in {stock_currency} are not shown.");
+                        })
+                        .ok();
+                    currency_rates.insert(stock_currency, rate);
+                }
+                stocks.retain(|stock| currency_rates[&stock.currency].is_some());
+                let fx_rates: HashMap<&str, Decimal> = stocks
+                    .iter()
+                    .filter_map(|stock| {
+                        let rate = currency_rates[&stock.currency]? / base_rate;
+                        Some((stock.symbol.as_str(), rate))
+                    })
                     .collect();
+                let show_fx_gain = stocks.iter().any(|stock| stock.currency != currency);
 
-                if stock_count > data.len() {
-                    warn!("Could not get prices for all stocks");
+                let priced_stocks = stock_market.get_stock_prices(&portfolio, &stocks, datetime);
+
+                let mut data: Vec<SummaryData> = Vec::with_capacity(priced_stocks.len());
+                for priced_stock in priced_stocks {
+                    match priced_stock {
+                        Ok(stock) => {
+                            if stock.stale {
+                                warn!(
+                                    "The price of {} is stale, it was fetched at {}.",
+                                    stock.symbol,
+                                    stock
+                                        .fetched_at
+                                        .to_offset(UtcOffset::current_local_offset()?)
+                                        .format(&datetime_format)?
+                                );
+                            }
+                            let fx_rate = fx_rates[stock.symbol.as_str()];
+
+                            data.push(SummaryData::new(stock, &converted, fx_rate, datetime)?);
+                        }
+                        Err(err) => warn!("{err}: {}", err.root_cause()),
+                    }
+                }
+
+                if let Err(err) = stock_market.save_cache() {
+                    warn!("Could not save the price cache: {err}");
                 }
 
                 // We opt to not clear the screen here, so we are able to see the changes
-                render_summary(data);
+                render_summary(data, currency.symbol(), show_fx_gain);
                 info!(
                     "Summary updated at: {}",
-                    OffsetDateTime::now_local()?.format(&format_description::parse(
-                        "[year]-[month]-[day] [hour]:[minute]:[second]"
-                    )?)?
+                    OffsetDateTime::now_local()?.format(&datetime_format)?
                 );
 
                 if !watch {
                     break;
                 }
 
-                // The API that we currently use updates roughly once every 20 minutes
-                std::thread::sleep(std::time::Duration::from_secs(20 * 60));
+                // The API that we currently use updates roughly once every 20 minutes, the summary
+                // is refreshed earlier when the portfolio is changed by another process.
+                let refresh_at = std::time::Instant::now() + WATCH_INTERVAL;
+                while std::time::Instant::now() < refresh_at {
+                    std::thread::sleep(WATCH_POLL_INTERVAL);
+
+                    let modified = modification_stamp(&path);
+                    if modified != modified_at {
+                        modified_at = modified;
+
+                        let _lock = lock_portfolio(&path, LockMode::Shared)?;
+                        portfolio = load_portfolio(&path)?;
+                        info!("The portfolio changed, refreshing the summary.");
+                        break;
+                    }
+                }
             }
         }
-        Command::ProfitSummary { year } => {
-            let profit_by_month = portfolio.profit_by_month(year).map(|summary| {
-                let tax = if summary.sold_amount > 20000.0 && summary.profit > 0.0 {
-                    summary.profit * 0.15
-                } else {
-                    0.0
-                };
+        Command::ProfitSummary { year, currency } => {
+            let mut stock_market = StockMarket::new().with_cache(
+                load_price_cache(&price_cache_path),
+                time::Duration::minutes(DEFAULT_MAX_AGE_MINUTES),
+            );
+            let mut fx_rate = |from, date| stock_market.fx_rate(from, date);
 
-                ProfitSummaryData {
-                    sold_amount: summary.sold_amount,
-                    profit: summary.profit,
-                    tax,
+            // The taxes are due in BRL, the other currencies only convert the reported amounts.
+            let tax_by_class =
+                tax_by_month(&portfolio.convert(Currency::Brl, &mut fx_rate)?, year)?;
+
+            // The amounts of each month are converted at the rate of its last day, while the
+            // profit at the rates of the trades tells the FX gain.
+            let conversion = if currency == Currency::Brl {
+                None
+            } else {
+                let today = OffsetDateTime::now_utc().date();
+                let mut rates = [Decimal::ONE; 12];
+                for (month, rate) in (1..=12).zip(&mut rates) {
+                    let month = Month::try_from(month).expect("BUG: Should be a valid month");
+                    *rate = fx_rate(currency, last_day(year, month).min(today))?;
                 }
-            });
 
-            render_profit_by_month(&profit_by_month);
-        }
-        Command::Split { stock, ratio, date } => {
-            let datetime = date
-                .map(|date| {
-                    date.with_time(
-                        time::Time::from_hms(23, 59, 59).expect("BUG: Should be a valid time"),
-                    )
-                    .assume_offset(
-                        UtcOffset::current_local_offset()
-                            .expect("BUG: Could not get the local offset."),
-                    )
-                })
-                .unwrap_or_else(|| {
-                    OffsetDateTime::now_local().expect("BUG: Could not get the local time.")
-                });
+                let converted = portfolio.convert(currency, &mut fx_rate)?;
+                Some((rates, converted.profit_by_month(year)?))
+            };
 
-            portfolio.split(stock.as_str(), ratio, datetime);
+            if let Err(err) = stock_market.save_cache() {
+                warn!("Could not save the price cache: {err}");
+            }
+
+            let symbol = currency.symbol();
+            let mut total_tax = Decimal::ZERO;
+
+            for (class, tax_by_month) in &tax_by_class {
+                let mut swing_trade = tax_by_month
+                    .each_ref()
+                    .map(|month| ProfitSummaryData::from(&month.swing_trade));
+                let mut day_trade = tax_by_month
+                    .each_ref()
+                    .map(|month| ProfitSummaryData::from(&month.day_trade));
+
+                if let Some((rates, profit_by_class)) = &conversion {
+                    let no_profit = Default::default();
+                    let profit_by_month = profit_by_class.get(class).unwrap_or(&no_profit);
+
+                    for (index, (rate, month)) in rates.iter().zip(profit_by_month).enumerate() {
+                        swing_trade[index].convert(*rate, month.profit);
+                        day_trade[index].convert(*rate, month.day_trade_profit);
+                    }
+                }
+
+                total_tax += swing_trade
+                    .iter()
+                    .chain(&day_trade)
+                    .map(|month| month.tax)
+                    .sum::<Decimal>();
+
+                let show_fx_gain = conversion.is_some();
+                render_profit_by_month(
+                    &format!("Swing Trade ({class})"),
+                    &swing_trade,
+                    symbol,
+                    show_fx_gain,
+                );
+                println!();
+                render_profit_by_month(
+                    &format!("Day Trade ({class})"),
+                    &day_trade,
+                    symbol,
+                    show_fx_gain,
+                );
+                println!();
+            }
+
+            render_total_tax(total_tax, symbol);
+        }
+        Command::Darf {
+            year,
+            month,
+            payment_date,
+            selic,
+            json,
+        } => {
+            let mut darf = darf(&in_brl(&portfolio, &price_cache_path)?, year, month)?;
+
+            let today = OffsetDateTime::now_local()?.date();
+            let payment_date = payment_date.unwrap_or(today.max(darf.due_date));
 
-            if ratio > 1.0 {
+            let selic = match (selic, darf.selic_period(payment_date)) {
+                (Some(selic), _) => selic,
+                (None, Some((from, to))) => Bcb::new()
+                    .accumulated_selic(from, to)
+                    .context("Could not fetch the Selic rate, try the --selic option")?,
+                (None, None) => Decimal::ZERO,
+            };
+            darf.pay_late(payment_date, selic);
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&darf)?);
+            } else {
+                render_darf(&darf.into());
+            }
+        }
+        Command::Split {
+            stock,
+            ratio,
+            fraction_price,
+            date,
+        } => {
+            let datetime = end_of_day_or_now(date);
+
+            let fraction = portfolio.split(stock.as_str(), ratio, fraction_price, datetime)?;
+
+            if ratio > Decimal::ONE {
                 info!("You performed a {ratio:.2}:1 stock split for {stock}.");
             } else {
-                let ratio = 1.0 / ratio;
+                let ratio = (Decimal::ONE / ratio).round_dp(2);
                 info!("You performed a 1:{ratio:.2} stock split for {stock}.");
             }
+            report_fraction(&stock, fraction, fraction_price);
             portfolio.save().map_err(|err| {
                 error!("Could not save portfolio: {err}");
                 err
             })?;
         }
+        Command::ReverseSplit {
+            stock,
+            ratio,
+            fraction_price,
+            date,
+        } => {
+            let datetime = end_of_day_or_now(date);
+
+            let fraction =
+                portfolio.reverse_split(stock.as_str(), ratio, fraction_price, datetime)?;
+
+            info!("You performed a 1:{ratio:.2} reverse stock split for {stock}.");
+            report_fraction(&stock, fraction, fraction_price);
+            portfolio.save()?;
+        }
+        Command::Bonus {
+            stock,
+            percentage,
+            unit_cost,
+            fraction_price,
+            date,
+        } => {
+            let datetime = end_of_day_or_now(date);
+
+            let fraction = portfolio.bonus(
+                stock.as_str(),
+                percentage / Decimal::ONE_HUNDRED,
+                unit_cost,
+                fraction_price,
+                datetime,
+            )?;
+
+            info!("You received {percentage:.2}% of bonus shares of {stock} at R${unit_cost:.2} per share.");
+            report_fraction(&stock, fraction, fraction_price);
+            portfolio.save()?;
+        }
+        Command::Rename {
+            stock,
+            new_stock,
+            date,
+        } => {
+            portfolio.rename(stock.as_str(), new_stock.as_str(), end_of_day_or_now(date))?;
+
+            info!("{stock} was renamed to {new_stock}.");
+            portfolio.save()?;
+        }
+        Command::Merger {
+            stock,
+            into,
+            ratio,
+            fraction_price,
+            date,
+        } => {
+            let fraction = portfolio.merge(
+                stock.as_str(),
+                into.as_str(),
+                ratio,
+                fraction_price,
+                end_of_day_or_now(date),
+            )?;
+
+            info!("{stock} was merged into {into}, each share became {ratio} {into} shares.");
+            report_fraction(&into, fraction, fraction_price);
+            portfolio.save()?;
+        }
+        Command::SpinOff {
+            stock,
+            into,
+            ratio,
+            cost_percentage,
+            fraction_price,
+            date,
+        } => {
+            let fraction = portfolio.spin_off(
+                stock.as_str(),
+                into.as_str(),
+                ratio,
+                cost_percentage,
+                fraction_price,
+                end_of_day_or_now(date),
+            )?;
+
+            info!("{stock} spun off {into}, each share received {ratio} {into} shares carrying {cost_percentage:.2}% of the cost.");
+            report_fraction(&into, fraction, fraction_price);
+            portfolio.save()?;
+        }
         Command::DumpTrades { path } => {
             let file = std::fs::File::create(&path).map_err(|err| {
                 error!("Could not create file {path:?}: {err}");
@@ -208,6 +705,287 @@ fn main() -> Result<()> {
 
             info!("Trades dumped to {path:?}.");
         }
+        Command::Broker { name, schedule } => {
+            portfolio.set_broker(name.as_str(), schedule);
+            info!(
+                "The broker {name} charges R${:.2} per trade, {:.4}% of emoluments, {:.4}% of settlement and {:.2}% of ISS.",
+                schedule.brokerage,
+                schedule.emoluments_rate,
+                schedule.settlement_rate,
+                schedule.iss_rate,
+            );
+            portfolio.save()?;
+        }
+        Command::Dividend {
+            stock,
+            kind,
+            amount,
+            withheld_tax,
+            ex_date,
+            payment_date,
+        } => {
+            let withheld_tax = withheld_tax.unwrap_or(match kind {
+                IncomeKind::Dividend => Decimal::ZERO,
+                IncomeKind::Jcp => round_money(amount * JCP_WITHHOLDING_RATE),
+            });
+
+            portfolio.add_income(
+                stock.as_str(),
+                Income {
+                    kind,
+                    amount,
+                    withheld_tax,
+                    ex_date,
+                    payment_date,
+                },
+            )?;
+            info!("You received R${amount:10.2} of {kind} from {stock} (R${withheld_tax:.2} withheld).");
+            portfolio.save()?;
+        }
+        Command::Income { year } => {
+            let by_month: Vec<(String, IncomeData)> = portfolio
+                .income_by_month(year)
+                .into_iter()
+                .enumerate()
+                .map(|(month, summary)| ((month + 1).to_string(), summary.into()))
+                .collect();
+
+            let mut by_symbol: Vec<(String, IncomeData)> = portfolio
+                .income_by_symbol(year)
+                .into_iter()
+                .map(|(symbol, summary)| (symbol, summary.into()))
+                .collect();
+            by_symbol.sort_by(|a, b| a.0.cmp(&b.0));
+
+            render_income("Month", &by_month);
+            println!();
+            render_income("Name", &by_symbol);
+        }
+        Command::Currency { stock, currency } => {
+            portfolio.set_currency(stock.as_str(), currency);
+            info!("The prices of {stock} are in {currency}.");
+            portfolio.save()?;
+        }
+        Command::Cnpj { stock, cnpj } => {
+            portfolio.set_cnpj(stock.as_str(), cnpj.as_str())?;
+            info!("The CNPJ of {stock} is {cnpj}.");
+            portfolio.save()?;
+        }
+        Command::CostBasis { cost_basis } => {
+            portfolio.cost_basis = cost_basis;
+            info!("The lots are sold using the {cost_basis} cost basis.");
+            portfolio.save()?;
+        }
+        Command::Lots {
+            stock,
+            date,
+            cost_basis,
+        } => {
+            let datetime = end_of_day_or_now(date);
+            let cost_basis = cost_basis.unwrap_or(portfolio.cost_basis);
+
+            let stock = portfolio
+                .stocks
+                .get(stock.as_str())
+                .with_context(|| format!("There is no {stock} in the portfolio"))?;
+            let lots = stock.lots(&portfolio, datetime, cost_basis)?;
+
+            let open: Vec<OpenLotData> = lots
+                .open
+                .iter()
+                .map(|lot| OpenLotData {
+                    lot: lot.id().to_string(),
+                    quantity: lot.quantity,
+                    average_price: lot.average_price(),
+                    cost: lot.cost,
+                    holding_days: lot.holding_period(datetime).whole_days(),
+                })
+                .collect();
+            let realized: Vec<RealizedLotData> = lots
+                .realized
+                .iter()
+                .map(|lot| RealizedLotData {
+                    acquired_at: lot.acquired_at.date(),
+                    sold_at: lot.sold_at.date(),
+                    quantity: lot.quantity,
+                    cost: lot.cost,
+                    proceeds: lot.proceeds,
+                    profit: lot.profit(),
+                    holding_days: lot.holding_period().whole_days(),
+                })
+                .collect();
+
+            render_open_lots(&open);
+            println!();
+            render_realized_lots(&realized);
+        }
+        Command::AssetClass { stock, class } => {
+            portfolio.set_asset_class(stock.as_str(), class)?;
+            match class {
+                Some(class) => info!("The asset class of {stock} is {class}."),
+                None => info!("The asset class of {stock} is inferred from its symbol."),
+            }
+            if let Err(err) = portfolio.stocks[&stock].class() {
+                warn!("{err}");
+            }
+            portfolio.save()?;
+        }
+        Command::TaxDeclaration { year } => {
+            let declaration = TaxDeclaration::new(&in_brl(&portfolio, &price_cache_path)?, year)?;
+
+            render_tax_declaration(&TaxDeclarationData::new(declaration, year));
+        }
+        Command::Restore { backup: None } => {
+            let backups: Vec<BackupData> = portfolio
+                .backups()?
+                .into_iter()
+                .map(|backup| BackupData {
+                    taken_at: backup
+                        .taken_at
+                        .to_offset(UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC)),
+                    // A damaged backup is still listed, it can be inspected by hand.
+                    trades: Portfolio::load(&backup.path)
+                        .ok()
+                        .map(|backup| backup.stocks.values().map(|stock| stock.trades.len()).sum()),
+                    id: backup.id,
+                })
+                .collect();
+
+            if backups.is_empty() {
+                info!("There are no backups of the portfolio yet.");
+            } else {
+                render_backups(&backups);
+            }
+        }
+        Command::Restore {
+            backup: Some(backup),
+        } => {
+            portfolio.restore(&backup)?;
+            info!("Restored the portfolio from the backup {backup}, the replaced version was backed up as well.");
+        }
+        Command::Convert { format } => {
+            let target = path.with_extension(format.extension());
+            anyhow::ensure!(
+                target != path,
+                "The portfolio is already stored as {}",
+                format.extension()
+            );
+
+            portfolio.move_to(&target)?;
+            info!("Converted the portfolio to {target:?}, the previous file was backed up.");
+        }
+        Command::Import {
+            path: file,
+            format,
+            symbols,
+            dry_run,
+        } => {
+            let Statement {
+                operations,
+                ignored,
+                totals,
+            } = match format {
+                ImportFormat::Dump => {
+                    let reader = std::io::BufReader::new(
+                        std::fs::File::open(&file)
+                            .with_context(|| format!("Could not open {file:?}"))?,
+                    );
+                    dump::parse(reader)
+                }
+                ImportFormat::B3 => b3::parse(&file)?,
+                ImportFormat::BrokerageNote => {
+                    let reader = std::io::BufReader::new(
+                        std::fs::File::open(&file)
+                            .with_context(|| format!("Could not open {file:?}"))?,
+                    );
+                    brokerage_note::parse(reader, &symbols)
+                }
+                ImportFormat::Profile(profile) => Profile::load(&profile)?.parse(&file)?,
+            }
+            .map_err(|errors| invalid_import(&file, errors))?;
+
+            // The operations are applied to a copy of the portfolio, so nothing is imported unless
+            // all of them are valid. The statements overlap when they are downloaded periodically.
+            let mut imported = portfolio.clone();
+            let (mut operations, skipped, updated) =
+                import::skip_existing(operations, &mut imported);
+
+            // The sales are only valid after the purchases before them.
+            import::sort_chronologically(&mut operations);
+
+            let mut errors = vec![];
+            for operation in &operations {
+                let details = match operation.kind {
+                    OperationKind::Trade { .. } => with_fx_rate(
+                        &imported,
+                        &operation.symbol,
+                        TradeDetails::default(),
+                        operation.datetime,
+                        &price_cache_path,
+                    ),
+                    _ => Ok(TradeDetails::default()),
+                };
+                let applied = details.and_then(|details| operation.apply(&mut imported, details));
+
+                if let Err(error) = applied {
+                    errors.push(LineError {
+                        line: operation.line,
+                        error,
+                    });
+                }
+            }
+
+            // The totals are checked once all the trades are in the portfolio.
+            if errors.is_empty() {
+                for totals in &totals {
+                    if let Err(error) = totals.check(&imported) {
+                        errors.push(LineError {
+                            line: totals.line,
+                            error,
+                        });
+                    }
+                }
+            }
+
+            if !errors.is_empty() {
+                errors.sort_by_key(|error| error.line);
+                return Err(invalid_import(&file, errors));
+            }
+            import::check_positions(&portfolio, &imported)
+                .with_context(|| format!("Nothing was imported from {file:?}"))?;
+
+            if skipped > 0 {
+                info!("Skipped {skipped} operations already in the portfolio.");
+            }
+            if updated > 0 {
+                info!("Added the fees of {updated} of them to the trades in the portfolio.");
+            }
+            if ignored > 0 {
+                info!("Ignored {ignored} lines that don't change the portfolio.");
+            }
+
+            let symbols: BTreeSet<&str> = operations
+                .iter()
+                .map(|operation| operation.symbol.as_str())
+                .collect();
+            for symbol in symbols {
+                if let Some(Err(err)) = imported.stocks.get(symbol).map(|stock| stock.class()) {
+                    warn!("{err}");
+                }
+            }
+
+            if dry_run {
+                info!(
+                    "The {} operations in {file:?} are valid, nothing was imported in the dry run.",
+                    operations.len()
+                );
+            } else {
+                imported.save()?;
+                info!("Imported {} operations from {file:?}.", operations.len());
+            }
+        }
+        Command::Portfolio(_) => unreachable!("BUG: The portfolios are managed before loading one"),
+        Command::Migrate { .. } => unreachable!("BUG: The portfolio is migrated before loading it"),
         Command::Help => {
             usage(&program);
         }
@@ -216,6 +994,119 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+fn migrate_portfolio(path: &Path, check: bool) -> Result<()> {
+    let lock_mode = if check {
+        LockMode::Shared
+    } else {
+        LockMode::Exclusive
+    };
+    let _lock = lock_portfolio(path, lock_mode)?;
+
+    let (portfolio, report) = Portfolio::load_and_migrate(path)?;
+
+    if report.is_up_to_date() {
+        info!("The portfolio is already in the schema version {SCHEMA_VERSION}.");
+        return Ok(());
+    }
+
+    render_migrations(&report);
+
+    if check {
+        info!("Nothing was changed, run `migrate` to save the migrated portfolio.");
+    } else {
+        portfolio.save()?;
+        info!("Migrated the portfolio to the schema version {SCHEMA_VERSION}, the previous version was backed up.");
+    }
+
+    Ok(())
+}
+
+/// Logs every invalid line of the imported `file`.
+fn invalid_import(file: &Path, errors: Vec<LineError>) -> anyhow::Error {
+    for error in &errors {
+        error!("{error}");
+    }
+
+    anyhow::anyhow!(
+        "Found {} invalid lines in {file:?}, nothing was imported",
+        errors.len()
+    )
+}
+
+fn load_portfolio(path: &Path) -> Result<Portfolio> {
+    if path.exists() {
+        Portfolio::load(path)
+    } else {
+        info!("Creating a new portfolio at {path:?}.");
+        Ok(Portfolio::new(path))
+    }
+}
+
+fn lock_portfolio(path: &Path, mode: LockMode) -> Result<PortfolioLock> {
+    match PortfolioLock::try_acquire(path, mode)? {
+        Some(lock) => Ok(lock),
+        None => {
+            info!("Waiting for another process to release the portfolio {path:?}.");
+            PortfolioLock::acquire(path, mode)
+        }
+    }
+}
+
+/// Identifies the version of the portfolio on disk, it changes whenever it's saved.
+fn modification_stamp(path: &Path) -> Option<(std::time::SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn manage_portfolios(data_dir: &DataDir, action: PortfolioAction) -> Result<()> {
+    match action {
+        PortfolioAction::Create { name } => {
+            let path = data_dir.portfolio_path(&name);
+            anyhow::ensure!(
+                !path.exists(),
+                "There is already a portfolio named `{name}`"
+            );
+
+            Portfolio::new(&path).save()?;
+            info!("Created the portfolio {name}, use it with `portfolio switch {name}`.");
+        }
+        PortfolioAction::List => {
+            render_portfolios(&data_dir.list()?, &data_dir.current()?);
+        }
+        PortfolioAction::Switch { name } => {
+            data_dir.switch(&name)?;
+            info!("Switched to the portfolio {name}.");
+
+            if std::env::var_os(PORTFOLIO_ENV_VAR).is_some_and(|path| !path.is_empty()) {
+                warn!("The {PORTFOLIO_ENV_VAR} environment variable is set, it takes precedence over the named portfolios.");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Takes the global `--portfolio <PATH>` option out of the arguments, it can be given anywhere.
+fn take_portfolio_option(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(Option<PathBuf>, std::vec::IntoIter<String>)> {
+    let mut path = None;
+    let mut remaining = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--portfolio" => {
+                path = Some(PathBuf::from(
+                    args.next().context("No portfolio path provided")?,
+                ));
+            }
+            _ => remaining.push(arg),
+        }
+    }
+
+    Ok((path, remaining.into_iter()))
+}
+
 fn parse_command(mut args: impl Iterator<Item = String>) -> Result<Command> {
     let command = args.next().context("No subcommand provided")?;
 
@@ -233,77 +1124,465 @@ fn parse_command(mut args: impl Iterator<Item = String>) -> Result<Command> {
             let price = args.next().context("No price provided.")?;
             let price = price.parse().context("Could not parse price")?;
 
-            let datetime = args
-                .next()
-                .map(|arg| parse_datetime(arg.as_str()))
-                .transpose()?;
+            let mut datetime = None;
+            let mut fees = FeeOptions::default();
+            let mut details = TradeDetails::default();
 
-            return Ok(match command.as_str() {
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--broker" => fees.broker = Some(next_value(&mut args, "broker")?),
+                    "--brokerage" => fees.brokerage = Some(next_value(&mut args, "brokerage")?),
+                    "--emoluments" => fees.emoluments = Some(next_value(&mut args, "emoluments")?),
+                    "--settlement" => fees.settlement = Some(next_value(&mut args, "settlement")?),
+                    "--iss" => fees.iss = Some(next_value(&mut args, "ISS")?),
+                    "--withheld" if command == "sell" => {
+                        details.withheld_tax = Some(next_value(&mut args, "withheld tax")?);
+                    }
+                    "--fx-rate" => details.fx_rate = Some(next_value(&mut args, "FX rate")?),
+                    "--lot" if command == "sell" => {
+                        let lot = args.next().context("No lot provided")?;
+                        details.lot = Some(parse_lot(lot.as_str())?);
+                    }
+                    _ => datetime = Some(parse_datetime(arg.as_str())?),
+                }
+            }
+
+            Ok(match command.as_str() {
                 "buy" => Command::Buy {
                     stock,
                     quantity,
                     price,
                     datetime,
+                    fees,
+                    details,
                 },
                 "sell" => Command::Sell {
                     stock,
                     quantity,
                     price,
                     datetime,
+                    fees,
+                    details,
                 },
                 _ => unreachable!(),
-            });
+            })
+        }
+        "broker" => {
+            let name = args.next().context("No broker name provided")?;
+
+            let schedule = FeeSchedule {
+                brokerage: next_value(&mut args, "brokerage")?,
+                emoluments_rate: next_value(&mut args, "emoluments rate")?,
+                settlement_rate: next_value(&mut args, "settlement rate")?,
+                iss_rate: next_value(&mut args, "ISS rate")?,
+            };
+
+            Ok(Command::Broker { name, schedule })
         }
         "summary" => {
-            let date;
-            let watch;
-            match args.next() {
-                Some(s) => match s.as_str() {
-                    "-w" | "--watch" => {
-                        date = None;
-                        watch = true;
-                    }
-                    _ => {
-                        date = Some(parse_date(s.as_str())?);
-                        watch = false;
+            let mut date = None;
+            let mut watch = false;
+            let mut prices = None;
+            let mut offline = false;
+            let mut max_age = DEFAULT_MAX_AGE_MINUTES;
+            let mut currency = Currency::Brl;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-w" | "--watch" => watch = true,
+                    "--offline" => offline = true,
+                    "--max-age" => max_age = next_value(&mut args, "max age")?,
+                    "--currency" => currency = next_currency(&mut args)?,
+                    "--prices" => {
+                        prices = Some(PathBuf::from(
+                            args.next().context("No prices file provided")?,
+                        ));
                     }
-                },
-                None => {
-                    date = None;
-                    watch = false;
+                    _ => date = Some(parse_date(arg.as_str())?),
                 }
             }
 
-            Ok(Command::Summary { date, watch })
+            Ok(Command::Summary {
+                date,
+                watch,
+                prices,
+                offline,
+                max_age,
+                currency,
+            })
         }
         "profit-summary" => {
-            let year = match args.next() {
-                Some(year) => year.parse().context("Could not parse year")?,
+            let mut year = None;
+            let mut currency = Currency::Brl;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--currency" => currency = next_currency(&mut args)?,
+                    _ => year = Some(arg.parse().context("Could not parse year")?),
+                }
+            }
+
+            let year = match year {
+                Some(year) => year,
                 None => OffsetDateTime::now_local()?.year(),
             };
 
-            Ok(Command::ProfitSummary { year })
+            Ok(Command::ProfitSummary { year, currency })
+        }
+        "darf" => {
+            let year = next_value(&mut args, "year")?;
+            let month = Month::try_from(next_value::<u8>(&mut args, "month")?)?;
+
+            let mut payment_date = None;
+            let mut selic = None;
+            let mut json = false;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--payment-date" => {
+                        let date = args.next().context("No payment date provided")?;
+                        payment_date = Some(parse_date(date.as_str())?);
+                    }
+                    "--selic" => selic = Some(next_value(&mut args, "Selic rate")?),
+                    "--json" => json = true,
+                    _ => anyhow::bail!("Unknown option `{arg}`"),
+                }
+            }
+
+            Ok(Command::Darf {
+                year,
+                month,
+                payment_date,
+                selic,
+                json,
+            })
         }
-        "split" => {
+        "split" | "reverse-split" | "bonus" => {
             let stock = args
                 .next()
                 .context("No stock stock provided")?
                 .to_uppercase();
 
-            let ratio = args.next().context("No ratio provided")?.parse()?;
+            let ratio = next_value(&mut args, "ratio")?;
+
+            let unit_cost = if command == "bonus" {
+                next_value(&mut args, "unit cost")?
+            } else {
+                Decimal::ZERO
+            };
+
+            let mut date = None;
+            let mut fraction_price = None;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--fraction-price" => {
+                        fraction_price = Some(next_value(&mut args, "fraction price")?);
+                    }
+                    _ => date = Some(parse_date(arg.as_str())?),
+                }
+            }
+
+            Ok(match command.as_str() {
+                "split" => Command::Split {
+                    stock,
+                    ratio,
+                    fraction_price,
+                    date,
+                },
+                "reverse-split" => Command::ReverseSplit {
+                    stock,
+                    ratio,
+                    fraction_price,
+                    date,
+                },
+                "bonus" => Command::Bonus {
+                    stock,
+                    percentage: ratio,
+                    unit_cost,
+                    fraction_price,
+                    date,
+                },
+                _ => unreachable!(),
+            })
+        }
+        "rename" => {
+            let stock = args
+                .next()
+                .context("No stock symbol provided")?
+                .to_uppercase();
+            let new_stock = args
+                .next()
+                .context("No new stock symbol provided")?
+                .to_uppercase();
 
             let date = args
                 .next()
                 .map(|arg| parse_date(arg.as_ref()))
                 .transpose()?;
 
-            Ok(Command::Split { stock, ratio, date })
+            Ok(Command::Rename {
+                stock,
+                new_stock,
+                date,
+            })
+        }
+        "merger" | "spin-off" => {
+            let stock = args
+                .next()
+                .context("No stock symbol provided")?
+                .to_uppercase();
+            let into = args
+                .next()
+                .context("No target stock symbol provided")?
+                .to_uppercase();
+
+            let ratio = next_value(&mut args, "ratio")?;
+
+            let cost_percentage = if command == "spin-off" {
+                next_value(&mut args, "cost percentage")?
+            } else {
+                Decimal::ONE_HUNDRED
+            };
+
+            let mut date = None;
+            let mut fraction_price = None;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--fraction-price" => {
+                        fraction_price = Some(next_value(&mut args, "fraction price")?);
+                    }
+                    _ => date = Some(parse_date(arg.as_str())?),
+                }
+            }
+
+            Ok(match command.as_str() {
+                "merger" => Command::Merger {
+                    stock,
+                    into,
+                    ratio,
+                    fraction_price,
+                    date,
+                },
+                "spin-off" => Command::SpinOff {
+                    stock,
+                    into,
+                    ratio,
+                    cost_percentage,
+                    fraction_price,
+                    date,
+                },
+                _ => unreachable!(),
+            })
         }
         "dump" => {
             let path = PathBuf::from(args.next().context("No path provided")?);
 
             Ok(Command::DumpTrades { path })
         }
+        "dividend" => {
+            let stock = args
+                .next()
+                .context("No stock symbol provided")?
+                .to_uppercase();
+            let amount = next_value(&mut args, "amount")?;
+            let ex_date = parse_date(args.next().context("No ex-date provided")?.as_str())?;
+            let payment_date =
+                parse_date(args.next().context("No payment date provided")?.as_str())?;
+
+            let mut kind = IncomeKind::Dividend;
+            let mut withheld_tax = None;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--jcp" => kind = IncomeKind::Jcp,
+                    "--withheld" => withheld_tax = Some(next_value(&mut args, "withheld tax")?),
+                    _ => anyhow::bail!("Unknown option `{arg}`"),
+                }
+            }
+
+            Ok(Command::Dividend {
+                stock,
+                kind,
+                amount,
+                withheld_tax,
+                ex_date,
+                payment_date,
+            })
+        }
+        "income" => {
+            let year = match args.next() {
+                Some(year) => year.parse().context("Could not parse year")?,
+                None => OffsetDateTime::now_local()?.year(),
+            };
+
+            Ok(Command::Income { year })
+        }
+        "currency" => {
+            let stock = args
+                .next()
+                .context("No stock symbol provided")?
+                .to_uppercase();
+            let currency = next_currency(&mut args)?;
+
+            Ok(Command::Currency { stock, currency })
+        }
+        "cnpj" => {
+            let stock = args
+                .next()
+                .context("No stock symbol provided")?
+                .to_uppercase();
+            let cnpj = args.next().context("No CNPJ provided")?;
+
+            Ok(Command::Cnpj { stock, cnpj })
+        }
+        "cost-basis" => {
+            let cost_basis = parse_cost_basis(args.next().context("No cost basis provided")?)?;
+
+            Ok(Command::CostBasis { cost_basis })
+        }
+        "lots" => {
+            let stock = args
+                .next()
+                .context("No stock symbol provided")?
+                .to_uppercase();
+
+            let mut date = None;
+            let mut cost_basis = None;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--cost-basis" => {
+                        let value = args.next().context("No cost basis provided")?;
+                        cost_basis = Some(parse_cost_basis(value)?);
+                    }
+                    _ => date = Some(parse_date(arg.as_str())?),
+                }
+            }
+
+            Ok(Command::Lots {
+                stock,
+                date,
+                cost_basis,
+            })
+        }
+        "asset-class" => {
+            let stock = args
+                .next()
+                .context("No stock symbol provided")?
+                .to_uppercase();
+
+            let class = match args.next().context("No asset class provided")?.as_str() {
+                "stock" => Some(AssetClass::Stock),
+                "unit" => Some(AssetClass::Unit),
+                "etf" => Some(AssetClass::Etf),
+                "bdr" => Some(AssetClass::Bdr),
+                "fii" => Some(AssetClass::Fii),
+                "auto" => None,
+                class => anyhow::bail!("Unknown asset class `{class}`"),
+            };
+
+            Ok(Command::AssetClass { stock, class })
+        }
+        "tax-declaration" => {
+            let year = match args.next() {
+                Some(year) => year.parse().context("Could not parse year")?,
+                None => OffsetDateTime::now_local()?.year() - 1,
+            };
+
+            Ok(Command::TaxDeclaration { year })
+        }
+        "migrate" => {
+            let mut check = false;
+
+            for arg in args {
+                match arg.as_str() {
+                    "--check" => check = true,
+                    _ => anyhow::bail!("Unknown option `{arg}`"),
+                }
+            }
+
+            Ok(Command::Migrate { check })
+        }
+        "import" => {
+            let mut path = None;
+            let mut format = ImportFormat::Dump;
+            let mut symbols = HashMap::new();
+            let mut dry_run = false;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--dry-run" => dry_run = true,
+                    "--format" => {
+                        let value = args.next().context("No format provided")?;
+
+                        format = match value.to_lowercase().as_str() {
+                            "dump" => ImportFormat::Dump,
+                            "b3" => ImportFormat::B3,
+                            "note" => ImportFormat::BrokerageNote,
+                            _ => anyhow::bail!("Unknown import format `{value}`"),
+                        };
+                    }
+                    "--symbol" => {
+                        let value = args.next().context("No symbol provided")?;
+                        let (name, symbol) = value
+                            .rsplit_once('=')
+                            .context("The symbol must be given as <NAME>=<SYMBOL>")?;
+
+                        symbols.insert(name.to_string(), symbol.to_uppercase());
+                    }
+                    "--profile" => {
+                        let profile = args.next().context("No profile provided")?;
+                        format = ImportFormat::Profile(PathBuf::from(profile));
+                    }
+                    _ => path = Some(PathBuf::from(arg)),
+                }
+            }
+
+            let path = path.context("No path provided")?;
+
+            Ok(Command::Import {
+                path,
+                format,
+                symbols,
+                dry_run,
+            })
+        }
+        "convert" => {
+            let format = args.next().context("No storage format provided")?;
+
+            let format = match format.to_lowercase().as_str() {
+                "json" => StorageFormat::Json,
+                "sqlite" => StorageFormat::Sqlite,
+                _ => anyhow::bail!("Unknown storage format `{format}`"),
+            };
+
+            Ok(Command::Convert { format })
+        }
+        "restore" => Ok(Command::Restore {
+            backup: args.next(),
+        }),
+        "portfolio" => {
+            let action = args.next().context("No portfolio action provided")?;
+
+            let action = match action.as_str() {
+                "create" | "switch" => {
+                    let name = args.next().context("No portfolio name provided")?;
+                    validate_name(&name)?;
+
+                    if action == "create" {
+                        PortfolioAction::Create { name }
+                    } else {
+                        PortfolioAction::Switch { name }
+                    }
+                }
+                "list" => PortfolioAction::List,
+                _ => anyhow::bail!("Unknown portfolio action `{action}`"),
+            };
+
+            Ok(Command::Portfolio(action))
+        }
         "-h" | "--help" => Ok(Command::Help),
         _ => anyhow::bail!("Unknown subcommand `{command}`"),
     }
@@ -312,48 +1591,396 @@ fn parse_command(mut args: impl Iterator<Item = String>) -> Result<Command> {
 fn usage(program: &str) {
     eprintln!("A simple tool to monitor a stock portfolio directly from terminal.\n");
     eprintln!("\x1b[4;1mUSAGE\x1b[0m: {program} <SUBCOMMAND> [OPTIONS]\n");
+    eprintln!("\x1b[4;1mOPTIONS\x1b[0m:");
+    eprintln!("      --portfolio <PATH>                                  use the portfolio at <PATH> instead of the current named portfolio, it can also be set with {PORTFOLIO_ENV_VAR}\n");
     eprintln!("\x1b[4;1mCOMMANDS\x1b[0m:");
     eprintln!("  \x1b[4mbuy\x1b[0m <STOCK> <QUANTITY> <PRICE> [DATETIME]          add the <STOCK> <QUANTITY> to the portfolio at a given <PRICE>, the default [DATETIME] is now");
     eprintln!("  \x1b[4msell\x1b[0m <STOCK> <QUANTITY> <PRICE> [DATETIME]         remove the <STOCK> <QUANTITY> from the portfolio at a given <PRICE>, the default [DATETIME] is now");
-    eprintln!("  \x1b[4msummary\x1b[0m [DATE] [-w | --watch]                      show the state of the portfolio at a given [DATE], the default [DATE] is now");
-    eprintln!("  \x1b[4mprofit-summary\x1b[0m [YEAR]                              show the month-by-month portfolio profit for a given [YEAR], the default [YEAR] is the current year");
+    eprintln!("      --broker <NAME>                                     calculate the trade fees with the fee schedule of the broker <NAME>");
+    eprintln!("      --brokerage <VALUE>                                 the brokerage (corretagem) paid in the trade");
+    eprintln!("      --emoluments <VALUE>                                the emoluments (emolumentos) paid in the trade");
+    eprintln!("      --settlement <VALUE>                                the settlement fee (taxa de liquidação) paid in the trade");
+    eprintln!("      --iss <VALUE>                                       the ISS paid over the brokerage in the trade");
+    eprintln!("      --fx-rate <VALUE>                                   the BRL value of one unit of the stock's currency, the default is the PTAX of the trade date");
+    eprintln!("      --withheld <VALUE>                                  the income tax withheld at source in the sale, the default is 0.005% of the sold amount");
+    eprintln!("      --lot <LOT>                                         sell from the lot bought at <DATETIME>[#<NUMBER>] when using the specific lot identification");
+    eprintln!("  \x1b[4msummary\x1b[0m [DATE] [-w | --watch] [OPTIONS]            show the state of the portfolio at a given [DATE], the default [DATE] is now");
+    eprintln!("      --prices <FILE>                                     read the prices from a local <FILE> instead of the network");
+    eprintln!("      --offline                                           only use the cached prices, the outdated ones are marked as stale");
+    eprintln!("      --max-age <MINUTES>                                 how long a cached price is considered fresh, the default is 20 minutes");
+    eprintln!("      --currency <CODE>                                   report in the currency <CODE> (BRL, USD, EUR or GBP), the default is BRL");
+    eprintln!("  \x1b[4mprofit-summary\x1b[0m [YEAR]                              show the month-by-month portfolio profit and tax for a given [YEAR], the default [YEAR] is the current year");
+    eprintln!("      --currency <CODE>                                   report in the currency <CODE> (BRL, USD, EUR or GBP), the taxes are still calculated in BRL");
+    eprintln!("  \x1b[4mdarf\x1b[0m <YEAR> <MONTH> [OPTIONS]                   show the DARF of the tax over the profits of a given <MONTH>, including the late payment fine and interest");
+    eprintln!("      --payment-date <DATE>                               the payment date, the default is today when overdue, otherwise the due date");
+    eprintln!("      --selic <PERCENTAGE>                                the accumulated Selic rate since the due date, the default is fetched from the BCB");
+    eprintln!("      --json                                              output the DARF as JSON");
     eprintln!("  \x1b[4msplit\x1b[0m <STOCK> <RATIO> [DATE]                       perform a stock split on a given <STOCK> in a given [DATE] increasing the number of stocks by <RATIO>");
+    eprintln!("  \x1b[4mreverse-split\x1b[0m <STOCK> <RATIO> [DATE]               perform a reverse stock split on a given <STOCK> in a given [DATE] grouping every <RATIO> stocks into one");
+    eprintln!("  \x1b[4mbonus\x1b[0m <STOCK> <PERCENTAGE> <UNIT-COST> [DATE]       receive <PERCENTAGE> of bonus shares of <STOCK> in a given [DATE], each one costing the declared <UNIT-COST>");
+    eprintln!("  \x1b[4mrename\x1b[0m <STOCK> <NEW-STOCK> [DATE]                  rename the <STOCK> to <NEW-STOCK> in a given [DATE] keeping its history");
+    eprintln!("  \x1b[4mmerger\x1b[0m <STOCK> <INTO> <RATIO> [DATE]                merge the <STOCK> into <INTO> in a given [DATE], each share becomes <RATIO> shares of <INTO>");
+    eprintln!("  \x1b[4mspin-off\x1b[0m <STOCK> <INTO> <RATIO> <COST-PERCENTAGE> [DATE]  spin off <INTO> from <STOCK> in a given [DATE], each share receives <RATIO> shares of <INTO> carrying <COST-PERCENTAGE> of the cost");
+    eprintln!("      --fraction-price <PRICE>                            the auction price of the fractions of shares left over by the split, bonus, merger or spin-off");
     eprintln!("  \x1b[4mdump\x1b[0m <FILEPATH>                                    dumps the trade history from all stocks to a given <FILEPATH>");
+    eprintln!("  \x1b[4mimport\x1b[0m <FILEPATH> [--format <FORMAT>] [--dry-run]  import the trades from a <FILEPATH>, the operations already in the portfolio are skipped and nothing is imported unless all of them are valid");
+    eprintln!("      --format <FORMAT>                                   `dump` for the file written by `dump` (default), `b3` for the Negociação and Movimentação statements (CSV or XLSX) from the B3's Área do Investidor, `note` for the text of the brokerage notes (notas de corretagem)");
+    eprintln!("      --symbol <NAME>=<SYMBOL>                            the <SYMBOL> of the stock named <NAME> in the brokerage notes, e.g. \"ITAUSA PN N1=ITSA4\"");
+    eprintln!("      --profile <PATH>                                    import a CSV file or spreadsheet with its columns mapped by the profile at <PATH>, see the README");
+    eprintln!("      --dry-run                                           only validate the trades, nothing is saved");
+    eprintln!("  \x1b[4mdividend\x1b[0m <STOCK> <AMOUNT> <EX-DATE> <PAYMENT-DATE>      record the gross <AMOUNT> of dividends paid by <STOCK>");
+    eprintln!("      --jcp                                               the income is juros sobre capital próprio, 15% is withheld by default");
+    eprintln!("      --withheld <VALUE>                                  the income tax withheld at source");
+    eprintln!("  \x1b[4mincome\x1b[0m [YEAR]                                      show the income received by month and by stock for a given [YEAR], the default [YEAR] is the current year");
+    eprintln!("  \x1b[4mcurrency\x1b[0m <STOCK> <CODE>                          set the currency of <STOCK> (BRL, USD, EUR or GBP), it must be set before trading it");
+    eprintln!("  \x1b[4mcnpj\x1b[0m <STOCK> <CNPJ>                              set the <CNPJ> of the company of <STOCK>, it's used by the tax declaration");
+    eprintln!("  \x1b[4mcost-basis\x1b[0m <METHOD>                             set the cost basis of the lots report (average, fifo, lifo or specific-lot), the taxes always use the average cost");
+    eprintln!("  \x1b[4mlots\x1b[0m <STOCK> [DATE]                               show the open and the sold lots of <STOCK> at a given [DATE], the default [DATE] is now");
+    eprintln!("      --cost-basis <METHOD>                               use <METHOD> instead of the portfolio's cost basis");
+    eprintln!("  \x1b[4masset-class\x1b[0m <STOCK> <CLASS>                      set the asset class of <STOCK> (stock, unit, etf, bdr or fii), `auto` infers it from the symbol");
+    eprintln!("  \x1b[4mtax-declaration\x1b[0m [YEAR]                             show the entries of the annual income tax declaration for a given [YEAR], the default [YEAR] is the previous year");
+    eprintln!("  \x1b[4mbroker\x1b[0m <NAME> <BROKERAGE> <EMOLUMENTS> <SETTLEMENT> <ISS>  register the fee schedule of a broker, the fixed <BROKERAGE> per trade and the rates in percentage");
+    eprintln!("  \x1b[4mrestore\x1b[0m [BACKUP]                                 roll the portfolio back to a [BACKUP], the backups are listed when none is given");
+    eprintln!("  \x1b[4mmigrate\x1b[0m [--check]                                upgrade the portfolio to the current schema version, older portfolios are also upgraded when saved");
+    eprintln!("      --check                                             only report the migrations that would be applied, nothing is saved");
+    eprintln!("  \x1b[4mconvert\x1b[0m <FORMAT>                                 store the portfolio as <FORMAT> (json or sqlite), the SQLite database can be queried directly");
+    eprintln!("  \x1b[4mportfolio create\x1b[0m <NAME>                          create a new named portfolio, e.g. for each person in the family");
+    eprintln!("  \x1b[4mportfolio list\x1b[0m                                   list the named portfolios, the current one is marked with an asterisk");
+    eprintln!("  \x1b[4mportfolio switch\x1b[0m <NAME>                          use the named portfolio <NAME> in the following commands");
+}
+
+impl From<&RegimeTax> for ProfitSummaryData {
+    fn from(tax: &RegimeTax) -> Self {
+        Self {
+            sold_amount: tax.sold_amount,
+            profit: tax.profit,
+            offset_loss: tax.offset_loss,
+            withheld_tax: tax.withheld_tax,
+            tax: tax.tax,
+            loss_balance: tax.loss_balance,
+            withheld_balance: tax.withheld_balance,
+            fx_gain: Decimal::ZERO,
+        }
+    }
+}
+
+impl ProfitSummaryData {
+    /// Converts the BRL amounts at the `rate` of the report currency, the FX gain is what the
+    /// `profit` at the rates of the trades exceeds the converted profit.
+    fn convert(&mut self, rate: Decimal, profit: Decimal) {
+        for amount in [
+            &mut self.profit,
+            &mut self.sold_amount,
+            &mut self.offset_loss,
+            &mut self.withheld_tax,
+            &mut self.tax,
+            &mut self.loss_balance,
+            &mut self.withheld_balance,
+        ] {
+            *amount = round_money(*amount / rate);
+        }
+
+        self.fx_gain = profit - self.profit;
+    }
+}
+
+impl From<Darf> for DarfData {
+    fn from(darf: Darf) -> Self {
+        Self {
+            receita_code: darf.receita_code,
+            period: darf.period,
+            due_date: darf.due_date,
+            payment_date: darf.payment_date,
+            principal: darf.principal,
+            fine: darf.fine,
+            interest: darf.interest,
+            total: darf.total,
+            carried_tax: darf.carried_tax,
+        }
+    }
+}
+
+impl TaxDeclarationData {
+    fn new(declaration: TaxDeclaration, year: i32) -> Self {
+        let unknown_cnpj = |cnpj: Option<String>| cnpj.unwrap_or_else(|| "-".to_string());
+
+        let assets = declaration
+            .assets
+            .into_iter()
+            .map(|asset| AssetData {
+                group: asset.group,
+                code: asset.code,
+                description: asset.description(),
+                name: asset.symbol,
+                cnpj: unknown_cnpj(asset.cnpj),
+                previous_cost: asset.previous_cost,
+                cost: asset.cost,
+            })
+            .collect();
+
+        let declared_income = |income: DeclaredIncome| DeclaredIncomeData {
+            code: income.code,
+            name: income.symbol,
+            cnpj: unknown_cnpj(income.cnpj),
+            amount: income.amount,
+        };
+
+        let mut exempt_income: Vec<DeclaredIncomeData> = declaration
+            .dividends
+            .into_iter()
+            .map(declared_income)
+            .collect();
+        exempt_income.push(DeclaredIncomeData {
+            code: EXEMPT_SALES_CODE,
+            name: String::new(),
+            cnpj: String::new(),
+            amount: declaration.exempt_profit,
+        });
+
+        let exclusive_income = declaration.jcp.into_iter().map(declared_income).collect();
+
+        Self {
+            year,
+            assets,
+            exempt_income,
+            exclusive_income,
+        }
+    }
+}
+
+impl From<IncomeSummary> for IncomeData {
+    fn from(summary: IncomeSummary) -> Self {
+        Self {
+            dividends: summary.dividends,
+            jcp: summary.jcp,
+            withheld_tax: summary.withheld_tax,
+            net_amount: summary.net_amount(),
+        }
+    }
+}
+
+impl FeeOptions {
+    fn resolve(self, portfolio: &Portfolio, quantity: u32, price: Decimal) -> Result<Fees> {
+        let fees = match &self.broker {
+            Some(broker) => portfolio.broker_fees(broker, quantity, price)?,
+            None => Fees::default(),
+        };
+
+        Ok(Fees {
+            brokerage: self.brokerage.unwrap_or(fees.brokerage),
+            emoluments: self.emoluments.unwrap_or(fees.emoluments),
+            settlement: self.settlement.unwrap_or(fees.settlement),
+            iss: self.iss.unwrap_or(fees.iss),
+        })
+    }
+}
+
+/// Parses the next argument, usually the value of an option.
+fn next_value<T>(args: &mut impl Iterator<Item = String>, name: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    args.next()
+        .with_context(|| format!("No {name} provided"))?
+        .parse()
+        .with_context(|| format!("Could not parse {name}"))
+}
+
+/// The end of the given day, so it includes every trade in that day, or now if there is no date.
+fn end_of_day_or_now(date: Option<Date>) -> OffsetDateTime {
+    date.map(|date| {
+        date.with_time(time::Time::from_hms(23, 59, 59).expect("BUG: Should be a valid time"))
+            .assume_offset(
+                UtcOffset::current_local_offset().expect("BUG: Could not get the local offset."),
+            )
+    })
+    .unwrap_or_else(|| OffsetDateTime::now_local().expect("BUG: Could not get the local time."))
+}
+
+/// Tells the user what happened with the fraction of a share left over by a corporate event.
+fn report_fraction(stock: &str, fraction: Decimal, fraction_price: Option<Decimal>) {
+    if fraction <= Decimal::ZERO {
+        return;
+    }
+
+    match fraction_price {
+        Some(price) => info!(
+            "The fraction of {:.4} {stock} shares was sold in auction for R${:.2}.",
+            fraction.round_dp(4),
+            round_money(fraction * price)
+        ),
+        None => warn!(
+            "The event left a fraction of {:.4} {stock} shares, give its auction price with --fraction-price to realize it.",
+            fraction.round_dp(4)
+        ),
+    }
 }
 
 fn parse_datetime(date: &str) -> Result<OffsetDateTime> {
     Ok(PrimitiveDateTime::parse(
         date,
-        &format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]")?,
+        &format_description::parse_borrowed::<1>("[year]-[month]-[day] [hour]:[minute]:[second]")?,
     )?
     .assume_offset(UtcOffset::UTC))
 }
 
+/// Parses a lot as its acquisition datetime, optionally followed by its number (e.g.
+/// `2024-03-04 00:00:00#2`).
+fn parse_lot(lot: &str) -> Result<LotId> {
+    let (acquired_at, number) = match lot.split_once('#') {
+        Some((acquired_at, number)) => (
+            acquired_at,
+            Some(
+                number
+                    .parse()
+                    .with_context(|| format!("Invalid lot number `{number}`"))?,
+            ),
+        ),
+        None => (lot, None),
+    };
+
+    Ok(LotId {
+        acquired_at: parse_datetime(acquired_at)?,
+        number,
+    })
+}
+
+fn next_currency(args: &mut impl Iterator<Item = String>) -> Result<Currency> {
+    let code = args.next().context("No currency provided")?;
+
+    Currency::from_code(code.as_str()).with_context(|| format!("Unknown currency `{code}`"))
+}
+
+/// The trades of the stocks in other currencies store the FX rate of the trade date, it's fetched
+/// when not given.
+fn with_fx_rate(
+    portfolio: &Portfolio,
+    stock: &str,
+    mut details: TradeDetails,
+    datetime: OffsetDateTime,
+    price_cache_path: &Path,
+) -> Result<TradeDetails> {
+    let currency = portfolio
+        .stocks
+        .get(stock)
+        .map_or(Currency::Brl, |stock| stock.currency);
+
+    if currency == Currency::Brl || details.fx_rate.is_some() {
+        return Ok(details);
+    }
+
+    let mut stock_market = StockMarket::new().with_cache(
+        load_price_cache(price_cache_path),
+        time::Duration::minutes(DEFAULT_MAX_AGE_MINUTES),
+    );
+
+    let fx_rate = stock_market
+        .fx_rate(currency, datetime.date())
+        .context("Could not fetch the FX rate, try the --fx-rate option")?;
+    details.fx_rate = Some(fx_rate);
+
+    if let Err(err) = stock_market.save_cache() {
+        warn!("Could not save the price cache: {err}");
+    }
+
+    Ok(details)
+}
+
+/// The price cache is only a copy of the fetched prices, so an unreadable one (e.g. truncated by a
+/// crash) is replaced by an empty cache.
+/// The FX rate of the summary, warning when only an older cached rate is available (e.g. offline).
+fn summary_fx_rate(
+    stock_market: &mut StockMarket,
+    currency: Currency,
+    date: Date,
+) -> Result<Decimal> {
+    let (rate, cached_on) = stock_market.fx_rate_or_cached(currency, date)?;
+    if let Some(cached_on) = cached_on {
+        warn!("The {currency} rate is stale, it's from {cached_on}.");
+    }
+
+    Ok(rate)
+}
+
+fn load_price_cache(path: &Path) -> PriceCache {
+    PriceCache::load(path).unwrap_or_else(|err| {
+        warn!(
+            "{err}: {}, the prices will be fetched again",
+            err.root_cause()
+        );
+        PriceCache::new(path)
+    })
+}
+
+/// The taxes are due in BRL, so they are calculated from the portfolio converted into it, each
+/// trade at the FX rate it stored.
+fn in_brl(portfolio: &Portfolio, price_cache_path: &Path) -> Result<Portfolio> {
+    let mut stock_market = StockMarket::new().with_cache(
+        load_price_cache(price_cache_path),
+        time::Duration::minutes(DEFAULT_MAX_AGE_MINUTES),
+    );
+    let portfolio =
+        portfolio.convert(Currency::Brl, |from, date| stock_market.fx_rate(from, date))?;
+
+    if let Err(err) = stock_market.save_cache() {
+        warn!("Could not save the price cache: {err}");
+    }
+
+    Ok(portfolio)
+}
+
+fn parse_cost_basis(cost_basis: String) -> Result<CostBasis> {
+    Ok(match cost_basis.as_str() {
+        "average" => CostBasis::Average,
+        "fifo" => CostBasis::Fifo,
+        "lifo" => CostBasis::Lifo,
+        "specific-lot" => CostBasis::SpecificLot,
+        _ => anyhow::bail!("Unknown cost basis `{cost_basis}`"),
+    })
+}
+
 fn parse_date(date: &str) -> Result<Date> {
     Ok(Date::parse(
         date,
-        &format_description::parse("[year]-[month]-[day]")?,
+        &format_description::parse_borrowed::<1>("[year]-[month]-[day]")?,
     )?)
 }
 
-impl From<PricedStock> for SummaryData {
-    fn from(stock: PricedStock) -> Self {
-        let current_value = stock.price * f64::from(stock.quantity);
-        let last_value = stock.last_price * f64::from(stock.quantity);
-        let original_cost = f64::from(stock.quantity) * stock.average_price;
+impl SummaryData {
+    /// The `stock` is priced in its own currency, which is worth `fx_rate` in the report currency,
+    /// while the `converted` portfolio is already in the report currency.
+    fn new(
+        stock: PricedStock,
+        converted: &Portfolio,
+        fx_rate: Decimal,
+        datetime: OffsetDateTime,
+    ) -> Result<Self> {
+        let quantity = Decimal::from(stock.quantity);
+        let current_price = stock.price * fx_rate;
+        let current_value = round_money(current_price * quantity);
+        let last_value = round_money(stock.last_price * fx_rate * quantity);
+        let converted_stock = &converted.stocks[&stock.symbol];
+        let position = converted_stock.position(converted, datetime)?;
+        let income = converted_stock.trailing_income(datetime);
 
-        Self {
+        Ok(Self {
             name: stock.symbol,
             quantity: stock.quantity,
-            current_price: stock.price,
+            current_price,
             current_value,
             change: current_value - last_value,
-            change_percentage: (current_value / last_value - 1.0) * 100.0,
-            average_price: stock.average_price,
-            profit: current_value - original_cost,
-            profit_percentage: (current_value / original_cost - 1.0) * 100.0,
+            change_percentage: percentage(current_value - last_value, last_value),
+            average_price: position.average_price(),
+            profit: current_value - position.cost,
+            profit_percentage: percentage(current_value - position.cost, position.cost),
+            // The cost at the current FX rate minus the cost at the FX rates of the purchases.
+            fx_gain: round_money(stock.average_price * quantity * fx_rate) - position.cost,
+            yield_on_cost: percentage(income, position.cost),
+            income,
             last_value,
-            original_cost,
-        }
+            original_cost: position.cost,
+            stale: stock.stale,
+        })
     }
 }
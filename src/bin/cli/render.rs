@@ -1,28 +1,55 @@
+use rust_decimal::Decimal;
+use stocks::money::percentage;
+use stocks::portfolio::MigrationReport;
+use time::{Date, OffsetDateTime};
+
 pub struct SummaryData {
     pub name: String,
     pub quantity: u32,
-    pub current_price: f64,
-    pub current_value: f64,
-    pub change: f64,
-    pub change_percentage: f64,
-    pub average_price: f64,
-    pub profit: f64,
-    pub profit_percentage: f64,
-    pub last_value: f64,
-    pub original_cost: f64,
+    pub current_price: Decimal,
+    pub current_value: Decimal,
+    pub change: Decimal,
+    pub change_percentage: Decimal,
+    pub average_price: Decimal,
+    pub profit: Decimal,
+    pub profit_percentage: Decimal,
+    /// The part of the profit due to the change of the FX rates.
+    pub fx_gain: Decimal,
+    /// The income of the last 12 months relative to the original cost, as a percentage.
+    pub yield_on_cost: Decimal,
+    pub income: Decimal,
+    pub last_value: Decimal,
+    pub original_cost: Decimal,
+    /// The price is outdated.
+    pub stale: bool,
 }
 
 pub struct ProfitSummaryData {
-    pub profit: f64,
-    pub sold_amount: f64,
-    pub tax: f64,
+    pub profit: Decimal,
+    pub sold_amount: Decimal,
+    pub offset_loss: Decimal,
+    pub withheld_tax: Decimal,
+    pub tax: Decimal,
+    pub loss_balance: Decimal,
+    pub withheld_balance: Decimal,
+    /// The part of the profit due to the change of the FX rates.
+    pub fx_gain: Decimal,
+}
+
+pub struct IncomeData {
+    pub dividends: Decimal,
+    pub jcp: Decimal,
+    pub withheld_tax: Decimal,
+    pub net_amount: Decimal,
 }
 
-pub fn render_summary(mut data: Vec<SummaryData>) {
+/// The `currency` is the symbol of the report currency, the FX gain column is only shown when
+/// `show_fx_gain` is set, i.e. some stocks are in other currencies.
+pub fn render_summary(mut data: Vec<SummaryData>, currency: &str, show_fx_gain: bool) {
     data.sort_by(|a, b| a.name.cmp(&b.name));
 
-    let titles = format!(
-        "\x1b[1m{:<6}  {:^8}  {:^13}  {:^13}  {:^13}  {:^13}  {:^13}  {:^13}  {:^11}\x1b[0m",
+    let mut titles = format!(
+        "\x1b[1m{:<6}  {:^8}  {:^13}  {:^13}  {:^13}  {:^13}  {:^13}  {:^13}  {:^11}  {:^10}\x1b[0m",
         "Name",
         "Quantity",
         "Current Price",
@@ -32,45 +59,68 @@ pub fn render_summary(mut data: Vec<SummaryData>) {
         "Average Price",
         "Profit",
         "% Profit",
+        "YoC (12M)",
     );
+    if show_fx_gain {
+        titles.push_str(&format!("  \x1b[1m{:^13}\x1b[0m", "FX Gain"));
+    }
 
-    let contents: Vec<String> = data.iter().map(format_summary_row).collect();
+    let contents: Vec<String> = data
+        .iter()
+        .map(|data| format_summary_row(data, currency, show_fx_gain))
+        .collect();
 
     println!("{titles}");
     for content in contents {
         println!("{content}");
     }
-    println!("{}", format_summary_totals(&data));
+    println!("{}", format_summary_totals(&data, currency, show_fx_gain));
 }
 
-fn format_summary_row(data: &SummaryData) -> String {
-    format!(
-        "{:<6}  {:>8}  R$ {:>10.2}  R$ {:>10.2}  {}R$ {:>10.2}\x1b[0m  {}{:>12.2}%\x1b[0m  R$ {:>10.2}  {}R$ {:>10.2}\x1b[0m  {}{:>10.2}%\x1b[0m",
-        data.name,
+fn format_summary_row(data: &SummaryData, currency: &str, show_fx_gain: bool) -> String {
+    // Stale rows are highlighted and marked with an asterisk.
+    let name = if data.stale {
+        format!("\x1b[33m{:<6}\x1b[0m", format!("{}*", data.name))
+    } else {
+        format!("{:<6}", data.name)
+    };
+
+    let mut row = format!(
+        "{}  {:>8}  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}  {}{currency:<3}{:>10.2}\x1b[0m  {}{:>12.2}%\x1b[0m  {currency:<3}{:>10.2}  {}{currency:<3}{:>10.2}\x1b[0m  {}{:>10.2}%\x1b[0m  {:>9.2}%",
+        name,
         data.quantity,
-        data.current_price,
+        // The decimals are truncated when formatting, so the prices are rounded first.
+        data.current_price.round_dp(2),
         data.current_value,
         get_color(data.change),
         data.change,
         get_color(data.change),
         data.change_percentage,
-        data.average_price,
+        data.average_price.round_dp(2),
         get_color(data.profit),
         data.profit,
         get_color(data.profit),
         data.profit_percentage,
-    )
+        data.yield_on_cost,
+    );
+    if show_fx_gain {
+        row.push_str(&format_fx_gain(data.fx_gain, currency));
+    }
+
+    row
 }
 
-fn format_summary_totals(data: &[SummaryData]) -> String {
-    let current_value: f64 = data.iter().map(|data| data.current_value).sum();
-    let original_cost: f64 = data.iter().map(|data| data.original_cost).sum();
-    let last_value: f64 = data.iter().map(|data| data.last_value).sum();
-    let change: f64 = data.iter().map(|data| data.change).sum();
-    let profit: f64 = data.iter().map(|data| data.profit).sum();
+fn format_summary_totals(data: &[SummaryData], currency: &str, show_fx_gain: bool) -> String {
+    let current_value: Decimal = data.iter().map(|data| data.current_value).sum();
+    let original_cost: Decimal = data.iter().map(|data| data.original_cost).sum();
+    let last_value: Decimal = data.iter().map(|data| data.last_value).sum();
+    let change: Decimal = data.iter().map(|data| data.change).sum();
+    let profit: Decimal = data.iter().map(|data| data.profit).sum();
+    let income: Decimal = data.iter().map(|data| data.income).sum();
+    let fx_gain: Decimal = data.iter().map(|data| data.fx_gain).sum();
 
-    format!(
-        "\x1b[1m{:<6}  {:>8}  {:>13}  R$ {:>10.2}\x1b[0m  {}R$ {:>10.2}\x1b[0m  {}{:>12.2}%\x1b[0m  {:>13}  {}R$ {:>10.2}\x1b[0m  {}{:>10.2}%\x1b[0m",
+    let mut row = format!(
+        "\x1b[1m{:<6}  {:>8}  {:>13}  {currency:<3}{:>10.2}\x1b[0m  {}{currency:<3}{:>10.2}\x1b[0m  {}{:>12.2}%\x1b[0m  {:>13}  {}{currency:<3}{:>10.2}\x1b[0m  {}{:>10.2}%\x1b[0m  \x1b[1m{:>9.2}%\x1b[0m",
         "Total",
         "",
         "",
@@ -78,64 +128,391 @@ fn format_summary_totals(data: &[SummaryData]) -> String {
         get_color(change),
         change,
         get_color(change),
-        (change / last_value) * 100.0,
+        percentage(change, last_value),
         "",
         get_color(profit),
         profit,
         get_color(profit),
-        (profit / original_cost) * 100.0,
+        percentage(profit, original_cost),
+        percentage(income, original_cost),
+    );
+    if show_fx_gain {
+        row.push_str(&format!(
+            "\x1b[1m{}\x1b[0m",
+            format_fx_gain(fx_gain, currency)
+        ));
+    }
+
+    row
+}
+
+fn format_fx_gain(fx_gain: Decimal, currency: &str) -> String {
+    format!(
+        "  {}{currency:<3}{:>10.2}\x1b[0m",
+        get_color(fx_gain),
+        fx_gain
     )
 }
 
-fn get_color(value: f64) -> &'static str {
-    match value.partial_cmp(&0.0).unwrap() {
+fn get_color(value: Decimal) -> &'static str {
+    match value.cmp(&Decimal::ZERO) {
         std::cmp::Ordering::Less => "\x1b[31m",
         std::cmp::Ordering::Equal => "\x1b[0m",
         std::cmp::Ordering::Greater => "\x1b[32m",
     }
 }
 
-pub fn render_profit_by_month(data: &[ProfitSummaryData; 12]) {
-    let titles = format!(
-        "\x1b[1m{:<6}  {:^13}  {:^13}  {:^8}\x1b[0m",
-        "Month", "Sold Amount", "Profit", "Tax",
+/// The FX gain column is only shown when `show_fx_gain` is set, i.e. the report isn't in BRL.
+pub fn render_profit_by_month(
+    title: &str,
+    data: &[ProfitSummaryData; 12],
+    currency: &str,
+    show_fx_gain: bool,
+) {
+    println!("\x1b[1;4m{title}\x1b[0m");
+
+    let mut titles = format!(
+        "\x1b[1m{:<6}  {:^13}  {:^13}  {:^13}  {:^13}  {:^13}  {:^13}  {:^13}\x1b[0m",
+        "Month",
+        "Sold Amount",
+        "Profit",
+        "Offset Loss",
+        "Withheld Tax",
+        "Tax",
+        "Loss Balance",
+        "Tax Credit",
     );
+    if show_fx_gain {
+        titles.push_str(&format!("  \x1b[1m{:^13}\x1b[0m", "FX Gain"));
+    }
 
     let contents: Vec<String> = data
         .iter()
         .enumerate()
-        .map(|(i, data)| format_profit_summary_row(i as u32, data))
+        .map(|(i, data)| format_profit_summary_row(i as u32 + 1, data, currency, show_fx_gain))
         .collect();
 
     println!("{titles}");
     for content in contents {
         println!("{content}");
     }
-    println!("{}", format_profit_summary_totals(data));
+    println!(
+        "{}",
+        format_profit_summary_totals(data, currency, show_fx_gain)
+    );
 }
 
-fn format_profit_summary_row(month: u32, data: &ProfitSummaryData) -> String {
-    format!(
-        "{:<6}  R$ {:>10.2}  {}{:>10.2}\x1b[0m  {:>10.2}",
+fn format_profit_summary_row(
+    month: u32,
+    data: &ProfitSummaryData,
+    currency: &str,
+    show_fx_gain: bool,
+) -> String {
+    let mut row = format!(
+        "{:<6}  {currency:<3}{:>10.2}  {}{currency:<3}{:>10.2}\x1b[0m  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}",
         month,
         data.sold_amount,
         get_color(data.profit),
         data.profit,
+        data.offset_loss,
+        data.withheld_tax,
         data.tax,
-    )
+        data.loss_balance,
+        data.withheld_balance,
+    );
+    if show_fx_gain {
+        row.push_str(&format_fx_gain(data.fx_gain, currency));
+    }
+
+    row
 }
 
-fn format_profit_summary_totals(data: &[ProfitSummaryData]) -> String {
-    let profit_total: f64 = data.iter().map(|data| data.profit).sum();
-    let sold_amount_total: f64 = data.iter().map(|data| data.sold_amount).sum();
-    let tax_total: f64 = data.iter().map(|data| data.tax).sum();
+fn format_profit_summary_totals(
+    data: &[ProfitSummaryData; 12],
+    currency: &str,
+    show_fx_gain: bool,
+) -> String {
+    let profit_total: Decimal = data.iter().map(|data| data.profit).sum();
+    let sold_amount_total: Decimal = data.iter().map(|data| data.sold_amount).sum();
+    let offset_loss_total: Decimal = data.iter().map(|data| data.offset_loss).sum();
+    let withheld_tax_total: Decimal = data.iter().map(|data| data.withheld_tax).sum();
+    let tax_total: Decimal = data.iter().map(|data| data.tax).sum();
+    let fx_gain_total: Decimal = data.iter().map(|data| data.fx_gain).sum();
+    // The balances at the end of the year.
+    let loss_balance = data[11].loss_balance;
+    let withheld_balance = data[11].withheld_balance;
 
-    format!(
-        "{:<6}  R$ {:>10.2}  {}{:>10.2}\x1b[0m  {:>10.2}",
+    let mut row = format!(
+        "{:<6}  {currency:<3}{:>10.2}  {}{currency:<3}{:>10.2}\x1b[0m  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}  {currency:<3}{:>10.2}",
         "Total",
         sold_amount_total,
         get_color(profit_total),
         profit_total,
+        offset_loss_total,
+        withheld_tax_total,
         tax_total,
+        loss_balance,
+        withheld_balance,
+    );
+    if show_fx_gain {
+        row.push_str(&format_fx_gain(fx_gain_total, currency));
+    }
+
+    row
+}
+
+pub fn render_total_tax(tax: Decimal, currency: &str) {
+    println!("\x1b[1m{:<6}  {currency:<3}{:>10.2}\x1b[0m", "Tax", tax);
+}
+
+pub struct DarfData {
+    pub receita_code: &'static str,
+    pub period: Date,
+    pub due_date: Date,
+    pub payment_date: Date,
+    pub principal: Decimal,
+    pub fine: Decimal,
+    pub interest: Decimal,
+    pub total: Decimal,
+    pub carried_tax: Decimal,
+}
+
+pub fn render_darf(darf: &DarfData) {
+    println!("{:<22}  {}", "Receita code", darf.receita_code);
+    println!(
+        "{:<22}  {}",
+        "Período de apuração",
+        format_date(darf.period)
+    );
+    println!("{:<22}  {}", "Due date", format_date(darf.due_date));
+    println!("{:<22}  {}", "Payment date", format_date(darf.payment_date));
+    println!("{:<22}  R$ {:>10.2}", "Principal", darf.principal);
+    println!("{:<22}  R$ {:>10.2}", "Fine", darf.fine);
+    println!("{:<22}  R$ {:>10.2}", "Interest", darf.interest);
+    println!("\x1b[1m{:<22}  R$ {:>10.2}\x1b[0m", "Total", darf.total);
+
+    if darf.carried_tax > Decimal::ZERO {
+        println!();
+        println!(
+            "R$ {:.2} of tax is below the minimum DARF value, it's carried to the next month.",
+            darf.carried_tax
+        );
+    }
+}
+
+/// Formats the date as `DD/MM/YYYY`, the format used in the DARF.
+fn format_date(date: Date) -> String {
+    format!(
+        "{:02}/{:02}/{}",
+        date.day(),
+        date.month() as u8,
+        date.year()
+    )
+}
+
+pub struct TaxDeclarationData {
+    pub year: i32,
+    pub assets: Vec<AssetData>,
+    /// Rendimentos Isentos e Não Tributáveis.
+    pub exempt_income: Vec<DeclaredIncomeData>,
+    /// Rendimentos Sujeitos à Tributação Exclusiva.
+    pub exclusive_income: Vec<DeclaredIncomeData>,
+}
+
+pub struct AssetData {
+    pub group: &'static str,
+    pub code: &'static str,
+    pub name: String,
+    pub cnpj: String,
+    pub description: String,
+    pub previous_cost: Decimal,
+    pub cost: Decimal,
+}
+
+pub struct DeclaredIncomeData {
+    pub code: &'static str,
+    pub name: String,
+    pub cnpj: String,
+    pub amount: Decimal,
+}
+
+pub fn render_tax_declaration(declaration: &TaxDeclarationData) {
+    println!("\x1b[1mBens e Direitos\x1b[0m");
+    println!(
+        "\x1b[1m{:<5}  {:<4}  {:<6}  {:<18}  {:^13}  {:^13}\x1b[0m",
+        "Group",
+        "Code",
+        "Name",
+        "CNPJ",
+        format!("31/12/{}", declaration.year - 1),
+        format!("31/12/{}", declaration.year),
+    );
+    for asset in &declaration.assets {
+        println!(
+            "{:<5}  {:<4}  {:<6}  {:<18}  R$ {:>10.2}  R$ {:>10.2}",
+            asset.group, asset.code, asset.name, asset.cnpj, asset.previous_cost, asset.cost,
+        );
+        println!("  {}", asset.description);
+    }
+
+    println!();
+    println!("\x1b[1mRendimentos Isentos e Não Tributáveis\x1b[0m");
+    render_declared_income(&declaration.exempt_income);
+
+    println!();
+    println!("\x1b[1mRendimentos Sujeitos à Tributação Exclusiva\x1b[0m");
+    render_declared_income(&declaration.exclusive_income);
+}
+
+fn render_declared_income(data: &[DeclaredIncomeData]) {
+    println!(
+        "\x1b[1m{:<4}  {:<6}  {:<18}  {:^13}\x1b[0m",
+        "Code", "Name", "CNPJ", "Value"
+    );
+    for income in data {
+        println!(
+            "{:<4}  {:<6}  {:<18}  R$ {:>10.2}",
+            income.code, income.name, income.cnpj, income.amount
+        );
+    }
+}
+
+pub struct OpenLotData {
+    /// The lot as given to `sell --lot`.
+    pub lot: String,
+    pub quantity: Decimal,
+    pub average_price: Decimal,
+    pub cost: Decimal,
+    pub holding_days: i64,
+}
+
+pub struct RealizedLotData {
+    pub acquired_at: Date,
+    pub sold_at: Date,
+    pub quantity: Decimal,
+    pub cost: Decimal,
+    pub proceeds: Decimal,
+    pub profit: Decimal,
+    pub holding_days: i64,
+}
+
+pub fn render_open_lots(data: &[OpenLotData]) {
+    println!("\x1b[1;4mOpen Lots\x1b[0m");
+    println!(
+        "\x1b[1m{:<22}  {:>10}  {:^13}  {:^13}  {:>12}\x1b[0m",
+        "Lot", "Quantity", "Avg. Price", "Cost", "Holding Days",
+    );
+    for lot in data {
+        println!(
+            "{:<22}  {:>10.2}  R$ {:>10.2}  R$ {:>10.2}  {:>12}",
+            lot.lot,
+            lot.quantity.round_dp(2),
+            lot.average_price.round_dp(2),
+            lot.cost,
+            lot.holding_days,
+        );
+    }
+}
+
+pub fn render_realized_lots(data: &[RealizedLotData]) {
+    println!("\x1b[1;4mSold Lots\x1b[0m");
+    println!(
+        "\x1b[1m{:<10}  {:<10}  {:>10}  {:^13}  {:^13}  {:^13}  {:>12}\x1b[0m",
+        "Acquired", "Sold", "Quantity", "Cost", "Proceeds", "Profit", "Holding Days",
+    );
+    for lot in data {
+        println!(
+            "{:<10}  {:<10}  {:>10.2}  R$ {:>10.2}  R$ {:>10.2}  {}R$ {:>10.2}\x1b[0m  {:>12}",
+            lot.acquired_at,
+            lot.sold_at,
+            lot.quantity.round_dp(2),
+            lot.cost,
+            lot.proceeds,
+            get_color(lot.profit),
+            lot.profit,
+            lot.holding_days,
+        );
+    }
+}
+
+/// Renders the income table, the rows are labeled by the `label` column (e.g. month or name).
+pub fn render_income(label: &str, data: &[(String, IncomeData)]) {
+    let titles = format!(
+        "\x1b[1m{:<6}  {:^13}  {:^13}  {:^13}  {:^13}\x1b[0m",
+        label, "Dividends", "JCP", "Withheld Tax", "Net Income",
+    );
+
+    println!("{titles}");
+    for (label, data) in data {
+        println!("{}", format_income_row(label, data));
+    }
+    println!("{}", format_income_totals(data));
+}
+
+fn format_income_row(label: &str, data: &IncomeData) -> String {
+    format!(
+        "{:<6}  R$ {:>10.2}  R$ {:>10.2}  R$ {:>10.2}  R$ {:>10.2}",
+        label, data.dividends, data.jcp, data.withheld_tax, data.net_amount,
     )
 }
+
+fn format_income_totals(data: &[(String, IncomeData)]) -> String {
+    let dividends: Decimal = data.iter().map(|(_, data)| data.dividends).sum();
+    let jcp: Decimal = data.iter().map(|(_, data)| data.jcp).sum();
+    let withheld_tax: Decimal = data.iter().map(|(_, data)| data.withheld_tax).sum();
+    let net_amount: Decimal = data.iter().map(|(_, data)| data.net_amount).sum();
+
+    format!(
+        "\x1b[1m{:<6}  R$ {:>10.2}  R$ {:>10.2}  R$ {:>10.2}  R$ {:>10.2}\x1b[0m",
+        "Total", dividends, jcp, withheld_tax, net_amount,
+    )
+}
+
+pub fn render_portfolios(names: &[String], current: &str) {
+    for name in names {
+        let marker = if name == current { "*" } else { " " };
+        println!("{marker} {name}");
+    }
+}
+
+pub struct BackupData {
+    pub id: String,
+    pub taken_at: OffsetDateTime,
+    /// It's unknown when the backup can't be read.
+    pub trades: Option<usize>,
+}
+
+pub fn render_backups(data: &[BackupData]) {
+    println!(
+        "\x1b[1m{:<27}  {:<19}  {:>6}\x1b[0m",
+        "Backup", "Taken At", "Trades"
+    );
+    for backup in data {
+        let trades = backup
+            .trades
+            .map_or_else(|| "?".to_string(), |trades| trades.to_string());
+
+        println!(
+            "{:<27}  {} {:02}:{:02}:{:02}  {:>6}",
+            backup.id,
+            backup.taken_at.date(),
+            backup.taken_at.hour(),
+            backup.taken_at.minute(),
+            backup.taken_at.second(),
+            trades,
+        );
+    }
+}
+
+pub fn render_migrations(report: &MigrationReport) {
+    println!(
+        "\x1b[1mMigrating from the schema version {}\x1b[0m",
+        report.from_version
+    );
+    for migration in &report.applied {
+        println!(
+            "{:>3}  {} ({} changes)",
+            migration.version, migration.description, migration.changes
+        );
+    }
+}
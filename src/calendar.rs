@@ -0,0 +1,118 @@
+use time::{Date, Duration, Month, Weekday};
+
+/// Whether the banks are open in the given day, which considers the weekends and the national
+/// holidays (including carnival).
+pub fn is_business_day(date: Date) -> bool {
+    !matches!(date.weekday(), Weekday::Saturday | Weekday::Sunday) && !is_holiday(date)
+}
+
+/// The last business day of the month.
+pub fn last_business_day(year: i32, month: Month) -> Date {
+    let mut date = last_day(year, month);
+
+    while !is_business_day(date) {
+        date = date.previous_day().expect("BUG: Date out of range");
+    }
+
+    date
+}
+
+/// The last day of the month.
+pub fn last_day(year: i32, month: Month) -> Date {
+    Date::from_calendar_date(year, month, time::util::days_in_month(month, year))
+        .expect("BUG: Should be a valid date")
+}
+
+fn is_holiday(date: Date) -> bool {
+    let fixed = [
+        (Month::January, 1),
+        (Month::April, 21),
+        (Month::May, 1),
+        (Month::September, 7),
+        (Month::October, 12),
+        (Month::November, 2),
+        (Month::November, 15),
+        (Month::December, 25),
+    ];
+
+    if fixed.contains(&(date.month(), date.day())) {
+        return true;
+    }
+
+    // Dia Nacional de Zumbi e da Consciência Negra became a national holiday in 2024.
+    if date.year() >= 2024 && (date.month(), date.day()) == (Month::November, 20) {
+        return true;
+    }
+
+    let easter = easter(date.year());
+    let movable = [
+        // Carnival
+        easter - Duration::days(48),
+        easter - Duration::days(47),
+        // Good Friday
+        easter - Duration::days(2),
+        // Corpus Christi
+        easter + Duration::days(60),
+    ];
+
+    movable.contains(&date)
+}
+
+/// The Easter Sunday of the year, according to the anonymous Gregorian algorithm.
+fn easter(year: i32) -> Date {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    Date::from_calendar_date(
+        year,
+        Month::try_from(month as u8).expect("BUG: Should be a valid month"),
+        day as u8,
+    )
+    .expect("BUG: Should be a valid date")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    #[test]
+    fn is_business_day_skips_the_weekends_and_the_holidays() {
+        assert!(is_business_day(date!(2024 - 03 - 04)));
+        assert!(!is_business_day(date!(2024 - 03 - 02)));
+        // Carnival and Good Friday.
+        assert!(!is_business_day(date!(2024 - 02 - 12)));
+        assert!(!is_business_day(date!(2024 - 03 - 29)));
+        // The Consciência Negra holiday only applies since 2024.
+        assert!(is_business_day(date!(2023 - 11 - 20)));
+        assert!(!is_business_day(date!(2024 - 11 - 20)));
+    }
+
+    #[test]
+    fn last_business_day_skips_the_end_of_the_month() {
+        assert_eq!(last_business_day(2024, Month::April), date!(2024 - 04 - 30));
+        assert_eq!(last_business_day(2024, Month::March), date!(2024 - 03 - 28));
+        assert_eq!(
+            last_business_day(2024, Month::November),
+            date!(2024 - 11 - 29)
+        );
+    }
+
+    #[test]
+    fn easter_follows_the_gregorian_calendar() {
+        assert_eq!(easter(2024), date!(2024 - 03 - 31));
+        assert_eq!(easter(2025), date!(2025 - 04 - 20));
+    }
+}
@@ -0,0 +1,455 @@
+//! Reads the trades from the files exported by this program and by other tools, they are validated
+//! as a whole before being applied to the portfolio.
+
+pub mod b3;
+pub mod brokerage_note;
+pub mod dump;
+pub mod profile;
+mod sheet;
+
+use crate::money::round_money;
+use crate::portfolio::{
+    Fees, Income, IncomeKind, Portfolio, Stock, TradeDetails, TradeKind, JCP_WITHHOLDING_RATE,
+};
+use anyhow::{ensure, Context, Result};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use time::{Date, OffsetDateTime, Time};
+
+/// The operations read from a file.
+pub struct Statement {
+    pub operations: Vec<ImportedOperation>,
+    /// How many lines don't change the portfolio (e.g. the settlement of the trades).
+    pub ignored: usize,
+    /// The totals stated in the file (e.g. in the brokerage notes), they are checked against the
+    /// portfolio's trades once imported.
+    pub totals: Vec<Totals>,
+}
+
+/// The totals of a group of trades in a file, e.g. the trades of a brokerage note.
+pub struct Totals {
+    /// The line where the group starts.
+    pub line: usize,
+    /// Names the group in the errors, e.g. `The note 12345`.
+    name: String,
+    /// The symbols and the keys of the group's trades.
+    trades: Vec<(String, String)>,
+    sales: Decimal,
+    purchases: Decimal,
+    fees: Decimal,
+    withheld_tax: Decimal,
+}
+
+/// An operation read from a file, it's only applied to the portfolio when the whole file is valid.
+pub struct ImportedOperation {
+    /// The line of the file where the operation is, starting at 1.
+    pub line: usize,
+    pub symbol: String,
+    pub datetime: OffsetDateTime,
+    pub kind: OperationKind,
+}
+
+pub enum OperationKind {
+    Trade {
+        kind: TradeKind,
+        quantity: u32,
+        price: Decimal,
+        fees: Fees,
+        /// The income tax withheld in a sale, it's estimated when absent.
+        withheld_tax: Option<Decimal>,
+    },
+    Income {
+        kind: IncomeKind,
+        /// The gross amount, before the withheld tax.
+        amount: Decimal,
+        withheld_tax: Decimal,
+    },
+    /// Desdobramento, the `shares` received, the ratio is derived from the shares held.
+    Split { shares: u32 },
+    /// Grupamento, the `shares` taken from the position, the ratio is derived from the shares
+    /// held.
+    ReverseSplit { shares: u32 },
+    /// Bonificação, the `shares` received, each one costing `unit_cost`.
+    Bonus { shares: u32, unit_cost: Decimal },
+}
+
+/// A problem found in a line of the imported file.
+pub struct LineError {
+    pub line: usize,
+    pub error: anyhow::Error,
+}
+
+impl ImportedOperation {
+    /// Applies the operation, the `details` are only used by the trades.
+    pub fn apply(&self, portfolio: &mut Portfolio, details: TradeDetails) -> Result<()> {
+        let symbol = self.symbol.as_str();
+
+        match self.kind {
+            OperationKind::Trade {
+                kind: TradeKind::Buy,
+                quantity,
+                price,
+                fees,
+                ..
+            } => portfolio.buy(symbol, quantity, price, fees, details, self.datetime),
+            OperationKind::Trade {
+                kind: TradeKind::Sell,
+                quantity,
+                price,
+                fees,
+                withheld_tax,
+            } => {
+                let details = TradeDetails {
+                    withheld_tax: withheld_tax.or(details.withheld_tax),
+                    ..details
+                };
+
+                portfolio.sell(symbol, quantity, price, fees, details, self.datetime)?;
+            }
+            OperationKind::Income {
+                kind,
+                amount,
+                withheld_tax,
+            } => {
+                let date = self.datetime.date();
+
+                // The statements only have the payment date.
+                portfolio.add_income(
+                    symbol,
+                    Income {
+                        kind,
+                        amount,
+                        withheld_tax,
+                        ex_date: date,
+                        payment_date: date,
+                    },
+                )?;
+            }
+            OperationKind::Split { shares } => {
+                let held = self.shares_held(portfolio)?;
+                let ratio = (held + Decimal::from(shares)) / held;
+
+                portfolio.split(symbol, ratio, None, self.datetime)?;
+            }
+            OperationKind::ReverseSplit { shares } => {
+                let held = self.shares_held(portfolio)?;
+                ensure!(
+                    Decimal::from(shares) < held,
+                    "The reverse split takes more shares of {symbol} than the {held} held"
+                );
+                let ratio = held / (held - Decimal::from(shares));
+
+                portfolio.reverse_split(symbol, ratio, None, self.datetime)?;
+            }
+            OperationKind::Bonus { shares, unit_cost } => {
+                let held = self.shares_held(portfolio)?;
+                let ratio = Decimal::from(shares) / held;
+
+                portfolio.bonus(symbol, ratio, unit_cost, None, self.datetime)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The shares held right before the operation, the corporate events are given by the shares
+    /// they add or remove.
+    fn shares_held(&self, portfolio: &Portfolio) -> Result<Decimal> {
+        let held = match portfolio.stocks.get(&self.symbol) {
+            Some(stock) => stock.quantity(portfolio, self.datetime)?,
+            None => 0,
+        };
+        ensure!(held > 0, "There are no shares of {} held", self.symbol);
+
+        Ok(Decimal::from(held))
+    }
+
+    /// Identifies the operation among the ones already in the portfolio, the statements have no
+    /// time, so the trades are compared by their day.
+    fn key(&self) -> String {
+        let date = self.datetime.date();
+
+        match &self.kind {
+            OperationKind::Trade {
+                kind,
+                quantity,
+                price,
+                ..
+            } => trade_key(&self.symbol, date, *kind, *quantity, *price),
+            OperationKind::Income { kind, amount, .. } => {
+                income_key(&self.symbol, date, *kind, *amount)
+            }
+            OperationKind::Split { .. } => event_key(&self.symbol, date, "Split"),
+            OperationKind::ReverseSplit { .. } => event_key(&self.symbol, date, "ReverseSplit"),
+            OperationKind::Bonus { .. } => event_key(&self.symbol, date, "Bonus"),
+        }
+    }
+}
+
+impl Totals {
+    /// Checks the totals against the group's trades in the `portfolio`, so none of the fees and
+    /// the withheld taxes are lost, also when the trades were already in the portfolio.
+    pub fn check(&self, portfolio: &Portfolio) -> Result<()> {
+        // Identical trades in the same day are matched in their order, as in `skip_existing`.
+        let mut matched: HashSet<(&str, usize)> = HashSet::new();
+        let mut sales = Decimal::ZERO;
+        let mut purchases = Decimal::ZERO;
+        let mut fees = Decimal::ZERO;
+        let mut withheld_tax = Decimal::ZERO;
+
+        for (symbol, key) in &self.trades {
+            let index = portfolio.stocks.get(symbol).and_then(|stock| {
+                stock.trades.iter().enumerate().position(|(index, trade)| {
+                    !matched.contains(&(symbol.as_str(), index))
+                        && trade_key(
+                            symbol,
+                            trade.datetime.date(),
+                            trade.kind,
+                            trade.quantity,
+                            trade.price,
+                        ) == *key
+                })
+            });
+            let Some(index) = index else {
+                anyhow::bail!(
+                    "{} has a trade of {symbol} that isn't in the portfolio",
+                    self.name
+                );
+            };
+            matched.insert((symbol, index));
+
+            let trade = &portfolio.stocks[symbol].trades[index];
+            let value = round_money(trade.price * Decimal::from(trade.quantity));
+            match trade.kind {
+                TradeKind::Buy => purchases += value,
+                TradeKind::Sell => sales += value,
+            }
+            fees += trade.fees.total();
+            withheld_tax += trade.withheld_tax.unwrap_or_default();
+        }
+
+        let check = |label: &str, expected: Decimal, actual: Decimal| {
+            ensure!(
+                expected == actual,
+                "{} has R$ {expected} in {label}, but its trades in the portfolio have R$ {actual}",
+                self.name
+            );
+            Ok(())
+        };
+        check("sales", self.sales, sales)?;
+        check("purchases", self.purchases, purchases)?;
+        check("fees", self.fees, fees)?;
+        check("withheld tax", self.withheld_tax, withheld_tax)
+    }
+}
+
+/// Drops the operations already in the portfolio, so overlapping statements can be imported. The
+/// fees and the withheld tax of the dropped trades (e.g. from a brokerage note) are given to the
+/// matching trades in the portfolio, as the other statements don't have them. Returns the
+/// remaining operations, how many were dropped and how many trades got their fees.
+pub fn skip_existing(
+    operations: Vec<ImportedOperation>,
+    portfolio: &mut Portfolio,
+) -> (Vec<ImportedOperation>, usize, usize) {
+    // Identical operations in the same day are common (e.g. buying in two batches), so each one in
+    // the portfolio only matches a single imported operation, in their order. The trades are kept
+    // along with their index, so their fees can be updated.
+    let mut existing: HashMap<String, VecDeque<Option<usize>>> = HashMap::new();
+    for stock in portfolio.stocks.values() {
+        for (key, trade) in existing_keys(stock) {
+            existing.entry(key).or_default().push_back(trade);
+        }
+    }
+
+    let total = operations.len();
+    let mut remaining = vec![];
+    let mut updated = 0;
+    for operation in operations {
+        let Some(trade) = existing
+            .get_mut(&operation.key())
+            .and_then(VecDeque::pop_front)
+        else {
+            remaining.push(operation);
+            continue;
+        };
+
+        if let (
+            Some(index),
+            OperationKind::Trade {
+                fees, withheld_tax, ..
+            },
+        ) = (trade, &operation.kind)
+        {
+            let trade = &mut portfolio
+                .stocks
+                .get_mut(&operation.symbol)
+                .expect("BUG: The matched stock should be in the portfolio")
+                .trades[index];
+            let withheld_tax = withheld_tax.or(trade.withheld_tax);
+
+            let has_costs = !fees.total().is_zero() || withheld_tax.is_some();
+            if has_costs && (trade.fees != *fees || trade.withheld_tax != withheld_tax) {
+                trade.fees = *fees;
+                trade.withheld_tax = withheld_tax;
+                updated += 1;
+            }
+        }
+    }
+    let skipped = total - remaining.len();
+
+    (remaining, skipped, updated)
+}
+
+/// Checks that the `imported` portfolio never sells more shares than held. Each sale is valid
+/// when imported, but a backdated operation might take the shares of a later sale, also in the
+/// stocks receiving shares from it. The stocks already oversold `before` are left as they are.
+pub fn check_positions(before: &Portfolio, imported: &Portfolio) -> Result<()> {
+    for (symbol, stock) in &imported.stocks {
+        let previous = match before.stocks.get(symbol) {
+            Some(stock) => stock.oversold_on(before)?,
+            None => None,
+        };
+
+        if let Some(day) = stock
+            .oversold_on(imported)?
+            .filter(|day| previous != Some(*day))
+        {
+            anyhow::bail!("The imported operations leave more {symbol} sold than held on {day}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Sorts the operations as they happened, the purchases of a day come before its sales, as the
+/// statements without time can't tell their order.
+pub fn sort_chronologically(operations: &mut [ImportedOperation]) {
+    operations.sort_by_key(|operation| {
+        let is_sale = matches!(
+            operation.kind,
+            OperationKind::Trade {
+                kind: TradeKind::Sell,
+                ..
+            }
+        );
+
+        (operation.datetime, is_sale)
+    });
+}
+
+/// The keys of the stock's operations, along with the index of the trades.
+fn existing_keys(stock: &Stock) -> Vec<(String, Option<usize>)> {
+    let symbol = stock.symbol.as_str();
+
+    let trades = stock.trades.iter().enumerate().map(|(index, trade)| {
+        let key = trade_key(
+            symbol,
+            trade.datetime.date(),
+            trade.kind,
+            trade.quantity,
+            trade.price,
+        );
+        (key, Some(index))
+    });
+    let incomes = stock.incomes.iter().map(|income| {
+        let key = income_key(symbol, income.payment_date, income.kind, income.amount);
+        (key, None)
+    });
+    let events = stock.events.iter().map(|event| {
+        let key = event_key(symbol, event.datetime.date(), event.kind.name());
+        (key, None)
+    });
+
+    trades.chain(incomes).chain(events).collect()
+}
+
+fn trade_key(symbol: &str, date: Date, kind: TradeKind, quantity: u32, price: Decimal) -> String {
+    format!(
+        "trade;{symbol};{date};{kind};{quantity};{}",
+        price.normalize()
+    )
+}
+
+fn income_key(symbol: &str, date: Date, kind: IncomeKind, amount: Decimal) -> String {
+    format!("income;{symbol};{date};{kind};{}", amount.normalize())
+}
+
+fn event_key(symbol: &str, date: Date, kind: &str) -> String {
+    format!("event;{symbol};{date};{kind}")
+}
+
+/// The JCP are paid net of the withheld tax, the gross amount is derived from it.
+fn jcp_from_net(net: Decimal) -> (Decimal, Decimal) {
+    let amount = round_money(net / (Decimal::ONE - JCP_WITHHOLDING_RATE));
+    (amount, amount - net)
+}
+
+/// The statements only have dates, the corporate events are recorded at the end of the day as the
+/// ones entered in the command line.
+fn end_of_day(date: Date) -> OffsetDateTime {
+    date.with_time(Time::from_hms(23, 59, 59).expect("BUG: Should be a valid time"))
+        .assume_utc()
+}
+
+/// Parses a symbol traded in the B3, a root of 4 letters or digits (e.g. `B3SA`) followed by 1 or 2
+/// digits.
+fn parse_symbol(text: &str) -> Result<String> {
+    let symbol = text.trim().to_uppercase();
+
+    let (root, suffix) = symbol.split_at_checked(4).unwrap_or((&symbol, ""));
+    ensure!(
+        root.len() == 4
+            && root.starts_with(|c: char| c.is_ascii_alphabetic())
+            && root.chars().all(|c| c.is_ascii_alphanumeric())
+            && (1..=2).contains(&suffix.len())
+            && suffix.chars().all(|c| c.is_ascii_digit()),
+        "Invalid stock symbol `{text}`"
+    );
+
+    Ok(symbol)
+}
+
+/// Tells whether the code is of an option, the series letter and the strike follow the root of the
+/// underlying stock (e.g. `PETRA300`).
+fn is_option_code(code: &str) -> bool {
+    let code = code.trim().as_bytes();
+    code.len() > 5
+        && code[..4].iter().all(u8::is_ascii_alphanumeric)
+        && code[4].is_ascii_alphabetic()
+        && code[5].is_ascii_digit()
+}
+
+/// Parses the code a stock is traded with, the codes of the fractional market have a `F` appended
+/// to the symbol.
+fn parse_trading_code(code: &str) -> Result<String> {
+    let code = code.trim();
+    parse_symbol(code.strip_suffix(['F', 'f']).unwrap_or(code))
+}
+
+/// Parses the symbol of any stock the portfolio accepts, including the foreign ones (e.g. `AAPL`),
+/// the codes of the B3's fractional market are read as the symbol of their stock.
+fn parse_any_symbol(text: &str) -> Result<String> {
+    let symbol = text.trim().to_uppercase();
+    ensure!(
+        !symbol.is_empty() && !symbol.contains(char::is_whitespace),
+        "Invalid stock symbol `{text}`"
+    );
+
+    Ok(parse_trading_code(&symbol).unwrap_or(symbol))
+}
+
+fn positive_quantity(quantity: Decimal) -> Result<u32> {
+    ensure!(
+        quantity.fract().is_zero() && quantity > Decimal::ZERO,
+        "The quantity must be a positive whole number, found {quantity}"
+    );
+
+    u32::try_from(quantity).context("The quantity is too large")
+}
+
+impl Display for LineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: {:#}", self.line, self.error)
+    }
+}
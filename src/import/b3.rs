@@ -0,0 +1,341 @@
+//! The statements exported from the B3's Área do Investidor, as CSV or XLSX. The `Negociação`
+//! statement has the trades and the `Movimentação` one has the incomes and the corporate events,
+//! they are told apart by their columns.
+
+use super::sheet::{normalize, Cell, Sheet, BRAZILIAN_NUMBERS};
+use super::{
+    end_of_day, is_option_code, jcp_from_net, parse_symbol, parse_trading_code, positive_quantity,
+    ImportedOperation, LineError, OperationKind, Statement,
+};
+use crate::portfolio::{Fees, IncomeKind, TradeKind};
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::path::Path;
+use time::Time;
+
+const DATE_FORMAT: &str = "[day]/[month]/[year]";
+
+const TRADES_COLUMNS: [&str; 5] = [
+    "Data do Negócio",
+    "Tipo de Movimentação",
+    "Código de Negociação",
+    "Quantidade",
+    "Preço",
+];
+
+const MOVEMENTS_COLUMNS: [&str; 7] = [
+    "Entrada/Saída",
+    "Data",
+    "Movimentação",
+    "Produto",
+    "Quantidade",
+    "Preço unitário",
+    "Valor da Operação",
+];
+
+/// Reads the statement at `path`, failing when it can't be read or isn't a B3 statement. Every
+/// invalid line is reported instead of only the first one.
+pub fn parse(path: &Path) -> Result<Result<Statement, Vec<LineError>>> {
+    let sheet = Sheet::read(path)?;
+
+    if sheet.has_columns(&TRADES_COLUMNS) {
+        parse_rows(&sheet, &TRADES_COLUMNS, parse_trade)
+    } else if sheet.has_columns(&MOVEMENTS_COLUMNS) {
+        parse_rows(&sheet, &MOVEMENTS_COLUMNS, parse_movement)
+    } else {
+        anyhow::bail!(
+            "{path:?} is neither a `Negociação` nor a `Movimentação` statement from the B3's Área \
+            do Investidor"
+        )
+    }
+}
+
+/// Parses each row with the cells of the `columns` in their order, the rows that don't change the
+/// portfolio are `None`.
+fn parse_rows(
+    sheet: &Sheet,
+    columns: &[&str],
+    parse: fn(usize, &[&Cell]) -> Result<Option<ImportedOperation>>,
+) -> Result<Result<Statement, Vec<LineError>>> {
+    let indexes = columns
+        .iter()
+        .map(|column| sheet.required_column(column))
+        .collect::<Result<Vec<usize>>>()?;
+
+    let mut operations = vec![];
+    let mut ignored = 0;
+    let mut errors = vec![];
+
+    for (line, row) in &sheet.rows {
+        let cells: Vec<&Cell> = indexes
+            .iter()
+            .map(|index| row.get(*index).unwrap_or(&Cell::Empty))
+            .collect();
+
+        match parse(*line, &cells) {
+            Ok(Some(operation)) => operations.push(operation),
+            Ok(None) => ignored += 1,
+            Err(error) => errors.push(LineError { line: *line, error }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Ok(Statement {
+            operations,
+            ignored,
+            totals: vec![],
+        }))
+    } else {
+        Ok(Err(errors))
+    }
+}
+
+fn parse_trade(line: usize, cells: &[&Cell]) -> Result<Option<ImportedOperation>> {
+    let [date, kind, symbol, quantity, price] = cells else {
+        unreachable!("BUG: There should be a cell for each column")
+    };
+
+    // Only the stocks, ETFs and REITs are tracked, not the options.
+    let symbol = symbol.to_text();
+    if is_option_code(&symbol) {
+        return Ok(None);
+    }
+    let symbol = parse_trading_code(&symbol)?;
+
+    let kind = kind.to_text();
+    let kind = match normalize(&kind).as_str() {
+        "compra" => TradeKind::Buy,
+        "venda" => TradeKind::Sell,
+        _ => anyhow::bail!("Unknown trade kind `{kind}`, expected `Compra` or `Venda`"),
+    };
+
+    let date = date.date(DATE_FORMAT)?;
+    let quantity = positive_quantity(quantity.required_number(BRAZILIAN_NUMBERS)?)?;
+    let price = price.required_number(BRAZILIAN_NUMBERS)?;
+    anyhow::ensure!(!price.is_sign_negative(), "The price can't be negative");
+
+    // The statement has neither the time nor the fees of the trades.
+    Ok(Some(ImportedOperation {
+        line,
+        symbol,
+        datetime: date.with_time(Time::MIDNIGHT).assume_utc(),
+        kind: OperationKind::Trade {
+            kind,
+            quantity,
+            price,
+            fees: Fees::default(),
+            withheld_tax: None,
+        },
+    }))
+}
+
+fn parse_movement(line: usize, cells: &[&Cell]) -> Result<Option<ImportedOperation>> {
+    let [direction, date, movement, product, quantity, unit_price, value] = cells else {
+        unreachable!("BUG: There should be a cell for each column")
+    };
+
+    let is_credit = normalize(&direction.to_text()) == "credito";
+    let movement = normalize(&movement.to_text());
+
+    let kind = match movement.as_str() {
+        // The REITs pay `Rendimento`, which is exempt as the dividends.
+        "dividendo" | "rendimento" if is_credit => {
+            let amount = value.required_number(BRAZILIAN_NUMBERS)?;
+            OperationKind::Income {
+                kind: IncomeKind::Dividend,
+                amount,
+                withheld_tax: Decimal::ZERO,
+            }
+        }
+        // The statement has the amount paid, after the withheld tax.
+        "juros sobre capital proprio" if is_credit => {
+            let (amount, withheld_tax) = jcp_from_net(value.required_number(BRAZILIAN_NUMBERS)?);
+            OperationKind::Income {
+                kind: IncomeKind::Jcp,
+                amount,
+                withheld_tax,
+            }
+        }
+        "bonificacao em ativos" => OperationKind::Bonus {
+            shares: positive_quantity(quantity.required_number(BRAZILIAN_NUMBERS)?)?,
+            unit_cost: unit_price.number(BRAZILIAN_NUMBERS)?.unwrap_or_default(),
+        },
+        "desdobro" => OperationKind::Split {
+            shares: positive_quantity(quantity.required_number(BRAZILIAN_NUMBERS)?)?,
+        },
+        "grupamento" => OperationKind::ReverseSplit {
+            shares: positive_quantity(quantity.required_number(BRAZILIAN_NUMBERS)?)?,
+        },
+        // The trades are in the `Negociação` statement, the settlements, transfers, rights and
+        // updates don't change the portfolio.
+        _ => return Ok(None),
+    };
+
+    // The products are written as `PETR4 - PETROLEO BRASILEIRO S.A. PETROBRAS`, the other
+    // investments (e.g. Tesouro Direto) have none of the movements above.
+    let product = product.to_text();
+    let symbol = parse_symbol(product.split(" - ").next().unwrap_or_default())?;
+    let date = date.date(DATE_FORMAT)?;
+
+    Ok(Some(ImportedOperation {
+        line,
+        symbol,
+        datetime: end_of_day(date),
+        kind,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use tempfile::TempDir;
+    use time::macros::{date, datetime};
+
+    const TRADES_HEADER: &str =
+        "Data do Negócio;Tipo de Movimentação;Mercado;Código de Negociação;Quantidade;Preço\n";
+
+    const MOVEMENTS_HEADER: &str = "Entrada/Saída;Data;Movimentação;Produto;Instituição;\
+        Quantidade;Preço unitário;Valor da Operação\n";
+
+    fn parse_text(text: &str) -> Result<Statement, Vec<LineError>> {
+        let directory = TempDir::new().expect("The temporary directory should be created");
+        let path = directory.path().join("statement.csv");
+        std::fs::write(&path, text).expect("The statement should be written");
+
+        parse(&path).expect("The statement should be read")
+    }
+
+    #[test]
+    fn parse_symbol_accepts_the_b3_symbols() {
+        for symbol in ["ITSA4", "petr4", "TAEE11", "B3SA3", "AAPL34"] {
+            assert_eq!(
+                parse_symbol(symbol).expect("The symbol should be valid"),
+                symbol.to_uppercase()
+            );
+        }
+        for symbol in ["ITSA", "ITSA123", "3SAB3", "TESOURO SELIC 2029", "ITSÁ4"] {
+            assert!(parse_symbol(symbol).is_err(), "{symbol} should be invalid");
+        }
+
+        assert_eq!(
+            parse_trading_code("B3SA3F").expect("The trading code should be valid"),
+            "B3SA3"
+        );
+    }
+
+    #[test]
+    fn parse_reads_the_trades() {
+        let text = format!(
+            "{TRADES_HEADER}\
+            04/03/2024;Compra;Mercado à Vista;ITSA4;100;9,50\n\
+            05/03/2024;Venda;Mercado Fracionário;B3SA3F;7;11,20\n\
+            06/03/2024;Compra;Opção de Compra;PETRA300;100;0,35\n"
+        );
+
+        let Ok(statement) = parse_text(&text) else {
+            panic!("The statement should be valid");
+        };
+
+        assert_eq!(statement.operations.len(), 2);
+        assert_eq!(statement.ignored, 1);
+        let operation = &statement.operations[1];
+        assert_eq!(operation.line, 3);
+        assert_eq!(operation.symbol, "B3SA3");
+        assert_eq!(operation.datetime, datetime!(2024-03-05 0:00 UTC));
+        let OperationKind::Trade {
+            kind,
+            quantity,
+            price,
+            ..
+        } = &operation.kind
+        else {
+            panic!("The operation should be a trade");
+        };
+        assert!(*kind == TradeKind::Sell);
+        assert_eq!(*quantity, 7);
+        assert_eq!(*price, dec!(11.20));
+    }
+
+    #[test]
+    fn parse_reports_the_unknown_trading_codes() {
+        let text = format!(
+            "{TRADES_HEADER}\
+            04/03/2024;Compra;Mercado à Vista;ITSA4;100;9,50\n\
+            05/03/2024;Compra;Mercado à Vista;ITSA;100;9,50\n\
+            06/03/2024;Troca;Mercado à Vista;ITSA4;100;9,50\n"
+        );
+
+        let Err(errors) = parse_text(&text) else {
+            panic!("The statement should be invalid");
+        };
+
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, [3, 4]);
+    }
+
+    #[test]
+    fn parse_reads_the_movements() {
+        let text = format!(
+            "{MOVEMENTS_HEADER}\
+            Credito;15/03/2024;Dividendo;B3SA3 - B3 S.A.;XP;100;0,10;10,00\n\
+            Credito;15/03/2024;Juros Sobre Capital Próprio;ITSA4 - ITAUSA S.A.;XP;100;0,085;8,50\n\
+            Credito;18/03/2024;Bonificação em Ativos;ITSA4 - ITAUSA S.A.;XP;5;18,71;\n\
+            Debito;19/03/2024;Transferência - Liquidação;ITSA4 - ITAUSA S.A.;XP;100;9,50;950,00\n\
+            Credito;20/03/2024;Vencimento;Tesouro Selic 2029;XP;1;14.000,00;14.000,00\n"
+        );
+
+        let Ok(statement) = parse_text(&text) else {
+            panic!("The statement should be valid");
+        };
+
+        assert_eq!(statement.operations.len(), 3);
+        assert_eq!(statement.ignored, 2);
+        assert_eq!(statement.operations[0].symbol, "B3SA3");
+        assert_eq!(
+            statement.operations[0].datetime.date(),
+            date!(2024 - 03 - 15)
+        );
+        let OperationKind::Income {
+            kind,
+            amount,
+            withheld_tax,
+        } = &statement.operations[1].kind
+        else {
+            panic!("The operation should be an income");
+        };
+        assert!(*kind == IncomeKind::Jcp);
+        assert_eq!(*amount, dec!(10.00));
+        assert_eq!(*withheld_tax, dec!(1.50));
+        let OperationKind::Bonus { shares, unit_cost } = &statement.operations[2].kind else {
+            panic!("The operation should be a bonus");
+        };
+        assert_eq!(*shares, 5);
+        assert_eq!(*unit_cost, dec!(18.71));
+    }
+
+    #[test]
+    fn parse_reports_the_movements_of_unknown_products() {
+        let text = format!(
+            "{MOVEMENTS_HEADER}\
+            Credito;15/03/2024;Dividendo;XPTO - XPTO S.A.;XP;100;0,10;10,00\n"
+        );
+
+        let Err(errors) = parse_text(&text) else {
+            panic!("The statement should be invalid");
+        };
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+    }
+
+    #[test]
+    fn parse_requires_a_b3_statement() {
+        let directory = TempDir::new().expect("The temporary directory should be created");
+        let path = directory.path().join("statement.csv");
+        std::fs::write(&path, "Data;Ativo;Valor\n04/03/2024;ITSA4;9,50\n")
+            .expect("The statement should be written");
+
+        assert!(parse(&path).is_err());
+    }
+}
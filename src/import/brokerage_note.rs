@@ -0,0 +1,737 @@
+//! The brokerage notes (notas de corretagem) in the SINACOR layout used by the Brazilian brokers,
+//! as the text extracted from the PDF (e.g. with `pdftotext -layout`). Unlike the B3 statements
+//! they have the fees, which are prorated over the trades of each note by their value.
+
+use super::sheet::{normalize, parse_number, BRAZILIAN_NUMBERS};
+use super::{
+    parse_trading_code, positive_quantity, ImportedOperation, LineError, OperationKind, Statement,
+    Totals,
+};
+use crate::money::{prorate, round_money};
+use crate::portfolio::{Fees, TradeKind};
+use anyhow::{ensure, Context, Result};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::BufRead;
+use time::macros::format_description;
+use time::{Date, Time};
+
+/// The totals in the summary of a note.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Total {
+    Sales,
+    Purchases,
+    Brokerage,
+    Emoluments,
+    Settlement,
+    Iss,
+    /// Charged for keeping the shares, not for the trades, so it isn't a cost of them.
+    Custody,
+    /// The other charges of the note, they aren't costs of the trades either.
+    Other,
+    WithheldTax,
+    /// The amount debited or credited by the note.
+    Net,
+}
+
+/// The labels of the totals, they are matched by their start as some end with a date or a base
+/// amount (e.g. `Líquido para 17/03/2023`).
+const TOTALS: [(&str, Total); 16] = [
+    ("vendas a vista", Total::Sales),
+    ("compras a vista", Total::Purchases),
+    ("corretagem", Total::Brokerage),
+    ("taxa operacional", Total::Brokerage),
+    ("execucao", Total::Brokerage),
+    ("taxa de custodia", Total::Custody),
+    ("outros", Total::Other),
+    ("emolumentos", Total::Emoluments),
+    ("taxa a.n.a.", Total::Emoluments),
+    ("taxa de termo/opcoes", Total::Emoluments),
+    ("taxa de liquidacao", Total::Settlement),
+    ("taxa de registro", Total::Settlement),
+    ("impostos", Total::Iss),
+    ("i.r.r.f. s/ operacoes", Total::WithheldTax),
+    ("irrf s/ operacoes", Total::WithheldTax),
+    ("liquido para", Total::Net),
+];
+
+struct Note {
+    number: String,
+    /// The line of the note's header.
+    line: usize,
+    date: Date,
+    trades: Vec<NoteTrade>,
+    /// The totals along with their lines, a total might be split over several lines (e.g. the
+    /// settlement and the registration fees).
+    totals: Vec<(Total, usize, Decimal)>,
+}
+
+struct NoteTrade {
+    line: usize,
+    /// The trades of other markets (e.g. options) share the fees, but aren't imported.
+    symbol: Option<String>,
+    kind: TradeKind,
+    quantity: u32,
+    price: Decimal,
+    value: Decimal,
+}
+
+/// Reads all the notes in the text, their fees are prorated over their trades and their totals
+/// are checked against them. The notes only have the names of the stocks (e.g. `ITAUSA PN N1`),
+/// the ones without a symbol are looked up in `symbols`. Every invalid line is reported instead of
+/// only the first one.
+pub fn parse(
+    reader: impl BufRead,
+    symbols: &HashMap<String, String>,
+) -> Result<Statement, Vec<LineError>> {
+    let symbols: HashMap<String, &str> = symbols
+        .iter()
+        .map(|(name, symbol)| (normalize(name), symbol.as_str()))
+        .collect();
+
+    let mut notes: Vec<Note> = vec![];
+    let mut errors = vec![];
+    // The header's values are usually in the line after its labels.
+    let mut header_line = None;
+
+    for (index, line) in reader.lines().enumerate() {
+        let number = index + 1;
+
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                errors.push(LineError {
+                    line: number,
+                    error: err.into(),
+                });
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let normalized = normalize(&line);
+        if normalized.contains("data pregao") {
+            header_line = Some(number);
+        }
+
+        let parsed = if let Some(header) = header_line {
+            match parse_header(&line) {
+                Ok(Some((note_number, date))) => {
+                    header_line = None;
+                    start_note(&mut notes, header, note_number, date);
+                    Ok(())
+                }
+                Ok(None) if number == header => Ok(()),
+                Ok(None) => {
+                    header_line = None;
+                    Err(anyhow::anyhow!(
+                        "Expected the note's number and trading date after its header"
+                    ))
+                }
+                Err(error) => {
+                    header_line = None;
+                    Err(error)
+                }
+            }
+        } else if is_trade(&normalized) {
+            match notes.last_mut() {
+                Some(note) => {
+                    parse_trade(number, &line, &symbols).map(|trade| note.trades.push(trade))
+                }
+                None => Err(anyhow::anyhow!("The trade comes before the note's header")),
+            }
+        } else {
+            if let Some(note) = notes.last_mut() {
+                for (total, amount) in parse_totals(&line) {
+                    note.totals.push((total, number, amount));
+                }
+            }
+            Ok(())
+        };
+
+        if let Err(error) = parsed {
+            errors.push(LineError {
+                line: number,
+                error,
+            });
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut operations = vec![];
+    let mut ignored = 0;
+    let mut totals = vec![];
+    for note in &notes {
+        match reconcile(note) {
+            Ok((note_operations, note_totals)) => {
+                ignored += note.trades.len() - note_operations.len();
+                operations.extend(note_operations);
+                totals.push(note_totals);
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if notes.is_empty() {
+        errors.push(LineError {
+            line: 1,
+            error: anyhow::anyhow!("No brokerage note found, expected a `Data pregão` header"),
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(Statement {
+            operations,
+            ignored,
+            totals,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// The next pages of a note repeat its header, their trades are added to the same note.
+fn start_note(notes: &mut Vec<Note>, line: usize, number: String, date: Date) {
+    if notes
+        .last()
+        .is_some_and(|note| note.number == number && note.date == date)
+    {
+        return;
+    }
+
+    notes.push(Note {
+        number,
+        line,
+        date,
+        trades: vec![],
+        totals: vec![],
+    });
+}
+
+/// The note's number and trading date, they are absent when the line only has the labels.
+fn parse_header(line: &str) -> Result<Option<(String, Date)>> {
+    let date = line
+        .split_whitespace()
+        .find_map(|token| Date::parse(token, format_description!("[day]/[month]/[year]")).ok());
+    let Some(date) = date else {
+        return Ok(None);
+    };
+
+    let number = line
+        .split_whitespace()
+        .map(|token| token.replace('.', ""))
+        .find(|token| !token.is_empty() && token.chars().all(|c| c.is_ascii_digit()))
+        .context("No note number found in the header")?;
+
+    Ok(Some((number, date)))
+}
+
+/// The trades start with the market where they happened, e.g. `1-BOVESPA` or `B3 RV LISTADO`.
+fn is_trade(normalized: &str) -> bool {
+    let first = normalized.split_whitespace().next().unwrap_or_default();
+    first.ends_with("-bovespa") || normalized.starts_with("b3 rv listado")
+}
+
+/// Parses a trade, e.g. `1-BOVESPA C VISTA ITAUSA PN N1 100 9,50 950,00 D`.
+fn parse_trade(line: usize, text: &str, symbols: &HashMap<String, &str>) -> Result<NoteTrade> {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let start = if normalize(tokens[0]).ends_with("-bovespa") {
+        1
+    } else {
+        3
+    };
+    ensure!(
+        tokens.len() >= start + 7,
+        "Expected the kind, the market, the stock, the quantity, the price, the value and `D` or \
+        `C` in the trade"
+    );
+
+    let kind = match tokens[start] {
+        "C" | "c" => TradeKind::Buy,
+        "V" | "v" => TradeKind::Sell,
+        kind => anyhow::bail!("Unknown trade kind `{kind}`, expected `C` or `V`"),
+    };
+
+    let [quantity, price, value, direction] = tokens[tokens.len() - 4..] else {
+        unreachable!("BUG: There should be 4 tokens at the end")
+    };
+    ensure!(
+        matches!(direction, "D" | "C"),
+        "Expected `D` or `C` at the end of the trade, found `{direction}`"
+    );
+    let quantity = positive_quantity(required_number(quantity)?)?;
+    let price = required_number(price)?;
+    let value = required_number(value)?;
+    ensure!(
+        round_money(price * Decimal::from(quantity)) == value,
+        "The value R$ {value} isn't the quantity {quantity} times the price R$ {price}"
+    );
+
+    // The options, the forward contracts and the other markets are not tracked.
+    let market = normalize(tokens[start + 1]);
+    let symbol = if market == "vista" || market == "fracionario" {
+        Some(parse_stock(&tokens[start + 2..tokens.len() - 4], symbols)?)
+    } else {
+        None
+    };
+
+    Ok(NoteTrade {
+        line,
+        symbol,
+        kind,
+        quantity,
+        price,
+        value,
+    })
+}
+
+/// The stock is given by its name followed by the observations (e.g. `#` for the trades of the
+/// day), some brokers also write its symbol.
+fn parse_stock(tokens: &[&str], symbols: &HashMap<String, &str>) -> Result<String> {
+    if let Some(symbol) = tokens
+        .iter()
+        .find_map(|token| parse_trading_code(token).ok())
+    {
+        return Ok(symbol);
+    }
+
+    let end = tokens
+        .iter()
+        .rposition(|token| token.len() > 1 && !token.contains('#'))
+        .map_or(0, |position| position + 1);
+    let name = tokens[..end].join(" ");
+
+    let symbol = symbols.get(&normalize(&name)).with_context(|| {
+        format!("Unknown stock `{name}`, give its symbol with `--symbol \"{name}=<SYMBOL>\"`")
+    })?;
+
+    parse_trading_code(symbol)
+}
+
+/// The totals in the line, the summary is usually laid out in two columns (e.g.
+/// `Vendas à vista 125,00   Taxa de liquidação 0,29 D`).
+fn parse_totals(line: &str) -> Vec<(Total, Decimal)> {
+    let mut totals = vec![];
+    let mut label: Vec<&str> = vec![];
+    let mut amount = None;
+
+    let mut push = |label: &mut Vec<&str>, amount: Decimal, direction: Option<&str>| {
+        let name = normalize(&label.join(" "));
+        label.clear();
+
+        let Some((_, total)) = TOTALS.iter().find(|(prefix, _)| name.starts_with(prefix)) else {
+            return;
+        };
+        // Only the net amount can be credited.
+        let amount = if *total == Total::Net && direction == Some("D") {
+            -amount
+        } else {
+            amount
+        };
+        totals.push((*total, amount));
+    };
+
+    for token in line.split_whitespace() {
+        // The amounts always have cents, so the numbers in the labels aren't mistaken for them.
+        let number = token
+            .contains(',')
+            .then(|| parse_number(token, BRAZILIAN_NUMBERS).ok().flatten())
+            .flatten();
+
+        match (number, amount) {
+            // Some labels have a base before the amount (e.g. `I.R.R.F. s/ operações, base
+            // R$0,00 0,00`), the last amount is used.
+            (Some(number), _) => amount = Some(number),
+            (None, Some(last)) => {
+                amount = None;
+                if matches!(token, "D" | "C") {
+                    push(&mut label, last, Some(token));
+                } else {
+                    push(&mut label, last, None);
+                    label.push(token);
+                }
+            }
+            (None, None) => label.push(token),
+        }
+    }
+
+    if let Some(last) = amount {
+        push(&mut label, last, None);
+    }
+
+    totals
+}
+
+/// Checks the trades against the note's totals and prorates the fees over them. Returns the trades
+/// of the stocks, along with their totals to check them once imported.
+fn reconcile(note: &Note) -> Result<(Vec<ImportedOperation>, Totals), LineError> {
+    let total = |kind: Total| -> Option<(usize, Decimal)> {
+        let lines = note.totals.iter().filter(|(total, _, _)| *total == kind);
+        let (_, line, _) = lines.clone().next()?;
+        Some((*line, lines.map(|(_, _, amount)| amount).sum()))
+    };
+    let required_total = |kind: Total, label: &str| {
+        total(kind).ok_or_else(|| LineError {
+            line: note.line,
+            error: anyhow::anyhow!(
+                "The note {} has no `{label}` total, the text might be incomplete",
+                note.number
+            ),
+        })
+    };
+    let amount = |kind: Total| total(kind).map_or(Decimal::ZERO, |(_, amount)| amount);
+
+    let traded = |kind: TradeKind, stocks_only: bool| -> Decimal {
+        note.trades
+            .iter()
+            .filter(|trade| trade.kind == kind && (!stocks_only || trade.symbol.is_some()))
+            .map(|trade| trade.value)
+            .sum()
+    };
+    let check = |(line, expected): (usize, Decimal), actual: Decimal, label: &str| {
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(LineError {
+                line,
+                error: anyhow::anyhow!(
+                    "The note {} has R$ {expected} in `{label}`, but R$ {actual} is expected \
+                    from its trades",
+                    note.number
+                ),
+            })
+        }
+    };
+
+    check(
+        required_total(Total::Sales, "Vendas à vista")?,
+        traded(TradeKind::Sell, true),
+        "Vendas à vista",
+    )?;
+    check(
+        required_total(Total::Purchases, "Compras à vista")?,
+        traded(TradeKind::Buy, true),
+        "Compras à vista",
+    )?;
+
+    let fees = Fees {
+        brokerage: amount(Total::Brokerage),
+        emoluments: amount(Total::Emoluments),
+        settlement: amount(Total::Settlement),
+        iss: amount(Total::Iss),
+    };
+    let withheld_tax = amount(Total::WithheldTax);
+
+    let net = traded(TradeKind::Sell, false)
+        - traded(TradeKind::Buy, false)
+        - fees.total()
+        - amount(Total::Custody)
+        - amount(Total::Other)
+        - withheld_tax;
+    check(
+        required_total(Total::Net, "Líquido para")?,
+        net,
+        "Líquido para",
+    )?;
+
+    // The fees are shared by all the trades of the note, while the tax is only withheld from the
+    // sales.
+    let values: Vec<Decimal> = note.trades.iter().map(|trade| trade.value).collect();
+    let sales: Vec<Decimal> = note
+        .trades
+        .iter()
+        .map(|trade| match trade.kind {
+            TradeKind::Sell => trade.value,
+            TradeKind::Buy => Decimal::ZERO,
+        })
+        .collect();
+
+    let brokerage = distribute(fees.brokerage, &values);
+    let emoluments = distribute(fees.emoluments, &values);
+    let settlement = distribute(fees.settlement, &values);
+    let iss = distribute(fees.iss, &values);
+    let withheld_taxes = distribute(withheld_tax, &sales);
+
+    let datetime = note.date.with_time(Time::MIDNIGHT).assume_utc();
+
+    let operations: Vec<ImportedOperation> = note
+        .trades
+        .iter()
+        .enumerate()
+        .filter_map(|(index, trade)| {
+            let symbol = trade.symbol.clone()?;
+
+            Some(ImportedOperation {
+                line: trade.line,
+                symbol,
+                datetime,
+                kind: OperationKind::Trade {
+                    kind: trade.kind,
+                    quantity: trade.quantity,
+                    price: trade.price,
+                    fees: Fees {
+                        brokerage: brokerage[index],
+                        emoluments: emoluments[index],
+                        settlement: settlement[index],
+                        iss: iss[index],
+                    },
+                    withheld_tax: match trade.kind {
+                        TradeKind::Sell => Some(withheld_taxes[index]),
+                        TradeKind::Buy => None,
+                    },
+                },
+            })
+        })
+        .collect();
+
+    // The shares of the fees and of the tax of the other markets aren't imported.
+    let mut totals = Totals {
+        line: note.line,
+        name: format!("The note {}", note.number),
+        trades: vec![],
+        sales: traded(TradeKind::Sell, true),
+        purchases: traded(TradeKind::Buy, true),
+        fees: Decimal::ZERO,
+        withheld_tax: Decimal::ZERO,
+    };
+    for operation in &operations {
+        let OperationKind::Trade {
+            fees, withheld_tax, ..
+        } = &operation.kind
+        else {
+            unreachable!("BUG: The note should only have trades")
+        };
+        totals.fees += fees.total();
+        totals.withheld_tax += withheld_tax.unwrap_or_default();
+        totals
+            .trades
+            .push((operation.symbol.clone(), operation.key()));
+    }
+
+    Ok((operations, totals))
+}
+
+/// Prorates the `amount` by the `weights`, the rounding difference goes to the last weighted
+/// share, so the shares add up to the `amount`.
+fn distribute(amount: Decimal, weights: &[Decimal]) -> Vec<Decimal> {
+    let whole: Decimal = weights.iter().sum();
+    let mut shares: Vec<Decimal> = weights
+        .iter()
+        .map(|weight| prorate(amount, *weight, whole))
+        .collect();
+
+    if let Some(last) = weights.iter().rposition(|weight| !weight.is_zero()) {
+        let distributed: Decimal = shares.iter().sum();
+        shares[last] += amount - distributed;
+    }
+
+    shares
+}
+
+fn required_number(token: &str) -> Result<Decimal> {
+    parse_number(token, BRAZILIAN_NUMBERS)?
+        .with_context(|| format!("Expected a number, found `{token}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::skip_existing;
+    use crate::portfolio::{Portfolio, TradeDetails};
+    use rust_decimal::dec;
+    use std::path::Path;
+    use time::macros::datetime;
+
+    const NOTE: &str = "Nr. nota   Folha   Data pregão
+12345   1   02/01/2024
+1-BOVESPA C VISTA ITAUSA PN N1 100 9,50 950,00 D
+1-BOVESPA V VISTA PETR4 50 30,00 1.500,00 C
+Vendas à vista 1.500,00   Compras à vista 950,00
+Taxa de liquidação 0,41 D   Emolumentos 0,08 D
+Corretagem 10,00 D   I.R.R.F. s/ operações, base R$1.500,00 0,07
+Líquido para 03/01/2024 539,44 C
+";
+
+    fn symbols() -> HashMap<String, String> {
+        HashMap::from([("ITAUSA PN N1".to_string(), "ITSA4".to_string())])
+    }
+
+    fn trade(operation: &ImportedOperation) -> (TradeKind, u32, Decimal, Fees, Option<Decimal>) {
+        match &operation.kind {
+            OperationKind::Trade {
+                kind,
+                quantity,
+                price,
+                fees,
+                withheld_tax,
+            } => (*kind, *quantity, *price, *fees, *withheld_tax),
+            _ => panic!("The operation should be a trade"),
+        }
+    }
+
+    #[test]
+    fn parse_distributes_the_fees_over_the_trades() {
+        let Ok(statement) = parse(NOTE.as_bytes(), &symbols()) else {
+            panic!("The note should be valid");
+        };
+
+        assert_eq!(statement.operations.len(), 2);
+        assert_eq!(statement.ignored, 0);
+
+        let purchase = &statement.operations[0];
+        assert_eq!(purchase.line, 3);
+        assert_eq!(purchase.symbol, "ITSA4");
+        assert_eq!(purchase.datetime, datetime!(2024-01-02 00:00 UTC));
+        let (kind, quantity, price, fees, withheld_tax) = trade(purchase);
+        assert!(kind == TradeKind::Buy);
+        assert_eq!(quantity, 100);
+        assert_eq!(price, dec!(9.50));
+        assert_eq!(fees.brokerage, dec!(3.88));
+        assert_eq!(fees.emoluments, dec!(0.03));
+        assert_eq!(fees.settlement, dec!(0.16));
+        assert_eq!(withheld_tax, None);
+
+        let sale = &statement.operations[1];
+        assert_eq!(sale.symbol, "PETR4");
+        let (kind, quantity, _, fees, withheld_tax) = trade(sale);
+        assert!(kind == TradeKind::Sell);
+        assert_eq!(quantity, 50);
+        // The rounding remainder goes to the last trade.
+        assert_eq!(fees.brokerage, dec!(6.12));
+        assert_eq!(fees.emoluments, dec!(0.05));
+        assert_eq!(fees.settlement, dec!(0.25));
+        assert_eq!(withheld_tax, Some(dec!(0.07)));
+    }
+
+    #[test]
+    fn parse_reports_the_totals_not_matching_the_trades() {
+        let note = NOTE.replace("Vendas à vista 1.500,00", "Vendas à vista 1.400,00");
+
+        let Err(errors) = parse(note.as_bytes(), &symbols()) else {
+            panic!("The note should be invalid");
+        };
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 5);
+    }
+
+    #[test]
+    fn parse_reports_the_unknown_stocks() {
+        let Err(errors) = parse(NOTE.as_bytes(), &HashMap::new()) else {
+            panic!("The note should be invalid");
+        };
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+    }
+
+    #[test]
+    fn parse_keeps_the_custody_out_of_the_trades() {
+        let note = NOTE
+            .replace(
+                "Corretagem 10,00 D",
+                "Corretagem 10,00 D   Taxa de custódia 5,00 D",
+            )
+            .replace("539,44 C", "534,44 C");
+
+        let Ok(statement) = parse(note.as_bytes(), &symbols()) else {
+            panic!("The note should be valid");
+        };
+
+        let brokerage: Decimal = statement
+            .operations
+            .iter()
+            .map(|operation| trade(operation).3.brokerage)
+            .sum();
+        assert_eq!(brokerage, dec!(10.00));
+    }
+
+    /// A portfolio with PETR4 to be sold by the note, and with the note's trades when `imported`
+    /// (e.g. from the B3 statements, without their fees).
+    fn portfolio(imported: bool) -> Portfolio {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+        portfolio.buy(
+            "PETR4",
+            50,
+            dec!(25),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2023-12-01 0:00 UTC),
+        );
+
+        if imported {
+            portfolio.buy(
+                "ITSA4",
+                100,
+                dec!(9.50),
+                Fees::default(),
+                TradeDetails::default(),
+                datetime!(2024-01-02 0:00 UTC),
+            );
+            portfolio
+                .sell(
+                    "PETR4",
+                    50,
+                    dec!(30),
+                    Fees::default(),
+                    TradeDetails::default(),
+                    datetime!(2024-01-02 0:00 UTC),
+                )
+                .expect("The sale should be valid");
+        }
+
+        portfolio
+    }
+
+    #[test]
+    fn totals_match_the_imported_trades() {
+        let Ok(statement) = parse(NOTE.as_bytes(), &symbols()) else {
+            panic!("The note should be valid");
+        };
+        let mut portfolio = portfolio(false);
+
+        for operation in &statement.operations {
+            operation
+                .apply(&mut portfolio, TradeDetails::default())
+                .expect("The operation should be applied");
+        }
+
+        assert_eq!(statement.totals.len(), 1);
+        statement.totals[0]
+            .check(&portfolio)
+            .expect("The totals should match the trades");
+    }
+
+    #[test]
+    fn skip_existing_gives_the_fees_to_the_existing_trades() {
+        let Ok(statement) = parse(NOTE.as_bytes(), &symbols()) else {
+            panic!("The note should be valid");
+        };
+        let mut portfolio = portfolio(true);
+        assert!(statement.totals[0].check(&portfolio).is_err());
+
+        let (operations, skipped, updated) = skip_existing(statement.operations, &mut portfolio);
+
+        assert!(operations.is_empty());
+        assert_eq!(skipped, 2);
+        assert_eq!(updated, 2);
+        let sale = &portfolio.stocks["PETR4"].trades[1];
+        assert_eq!(sale.fees.brokerage, dec!(6.12));
+        assert_eq!(sale.withheld_tax, Some(dec!(0.07)));
+        statement.totals[0]
+            .check(&portfolio)
+            .expect("The totals should match the trades");
+    }
+
+    #[test]
+    fn distribute_gives_the_remainder_to_the_last_share() {
+        let shares = distribute(dec!(1.00), &[dec!(1), dec!(1), dec!(1), Decimal::ZERO]);
+
+        assert_eq!(shares, [dec!(0.33), dec!(0.33), dec!(0.34), Decimal::ZERO]);
+    }
+}
@@ -0,0 +1,207 @@
+//! The format written by [`Portfolio::dump_trades`](crate::portfolio::Portfolio::dump_trades), a
+//! `;` separated file with the `symbol;date;kind;quantity;price` columns.
+
+use super::{ImportedOperation, LineError, OperationKind, Statement};
+use crate::portfolio::{Fees, TradeKind};
+use anyhow::{ensure, Context, Result};
+use rust_decimal::Decimal;
+use std::io::BufRead;
+use time::macros::format_description;
+use time::{PrimitiveDateTime, UtcOffset};
+
+const HEADER: &str = "symbol;date;kind;quantity;price";
+
+/// Reads all the trades in the file, every invalid line is reported instead of only the first one.
+pub fn parse(reader: impl BufRead) -> Result<Statement, Vec<LineError>> {
+    let mut operations = vec![];
+    let mut errors = vec![];
+
+    for (index, line) in reader.lines().enumerate() {
+        let number = index + 1;
+
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                errors.push(LineError {
+                    line: number,
+                    error: err.into(),
+                });
+                break;
+            }
+        };
+
+        if number == 1 {
+            if !line.trim().eq_ignore_ascii_case(HEADER) {
+                errors.push(LineError {
+                    line: number,
+                    error: anyhow::anyhow!("Expected the `{HEADER}` header"),
+                });
+            }
+            continue;
+        }
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_trade(number, &line) {
+            Ok(operation) => operations.push(operation),
+            Err(error) => errors.push(LineError {
+                line: number,
+                error,
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Statement {
+            operations,
+            ignored: 0,
+            totals: vec![],
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+fn parse_trade(line: usize, text: &str) -> Result<ImportedOperation> {
+    let columns: Vec<&str> = text.split(';').map(str::trim).collect();
+    ensure!(
+        columns.len() == 5,
+        "Expected 5 columns ({HEADER}), found {}",
+        columns.len()
+    );
+
+    let symbol = columns[0].to_uppercase();
+    ensure!(!symbol.is_empty(), "No stock symbol");
+
+    let datetime = PrimitiveDateTime::parse(
+        columns[1],
+        format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
+    )
+    .with_context(|| format!("Invalid date `{}`", columns[1]))?
+    .assume_offset(UtcOffset::UTC);
+
+    let kind = match columns[2].to_lowercase().as_str() {
+        "buy" => TradeKind::Buy,
+        "sell" => TradeKind::Sell,
+        kind => anyhow::bail!("Unknown trade kind `{kind}`, expected `buy` or `sell`"),
+    };
+
+    let quantity: u32 = columns[3]
+        .parse()
+        .with_context(|| format!("Invalid quantity `{}`", columns[3]))?;
+    ensure!(quantity > 0, "The quantity must be positive");
+
+    let price: Decimal = columns[4]
+        .parse()
+        .with_context(|| format!("Invalid price `{}`", columns[4]))?;
+    ensure!(!price.is_sign_negative(), "The price can't be negative");
+
+    Ok(ImportedOperation {
+        line,
+        symbol,
+        datetime,
+        kind: OperationKind::Trade {
+            kind,
+            quantity,
+            price,
+            fees: Fees::default(),
+            withheld_tax: None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::{Fees, Portfolio, TradeDetails};
+    use rust_decimal::dec;
+    use std::path::Path;
+    use time::macros::datetime;
+
+    #[test]
+    fn parse_reads_the_trades() {
+        let text = "symbol;date;kind;quantity;price\n\
+            itsa4;2024-03-04 10:30:00;buy;100;9.5\n\
+            \n\
+            PETR4;2024-03-05 15:00:00;Sell;50;30.00\n";
+
+        let Ok(statement) = parse(text.as_bytes()) else {
+            panic!("The dump should be valid");
+        };
+
+        assert_eq!(statement.operations.len(), 2);
+        let operation = &statement.operations[0];
+        assert_eq!(operation.line, 2);
+        assert_eq!(operation.symbol, "ITSA4");
+        assert_eq!(operation.datetime, datetime!(2024-03-04 10:30 UTC));
+        let OperationKind::Trade {
+            kind,
+            quantity,
+            price,
+            ..
+        } = &operation.kind
+        else {
+            panic!("The operation should be a trade");
+        };
+        assert!(*kind == TradeKind::Buy);
+        assert_eq!(*quantity, 100);
+        assert_eq!(*price, dec!(9.5));
+        assert_eq!(statement.operations[1].line, 4);
+    }
+
+    #[test]
+    fn parse_reports_every_invalid_line() {
+        let text = "symbol;date;kind;quantity;price\n\
+            ITSA4;2024-03-04;buy;100;9.5\n\
+            ITSA4;2024-03-04 10:30:00;buy;100;9.5\n\
+            ITSA4;2024-03-04 10:30:00;rent;100;9.5\n\
+            ITSA4;2024-03-04 10:30:00;sell;0;9.5\n";
+
+        let Err(errors) = parse(text.as_bytes()) else {
+            panic!("The dump should be invalid");
+        };
+
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, [2, 4, 5]);
+    }
+
+    #[test]
+    fn parse_requires_the_header() {
+        let Err(errors) = parse("ITSA4;2024-03-04 10:30:00;buy;100;9.5\n".as_bytes()) else {
+            panic!("The dump should be invalid");
+        };
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 1);
+    }
+
+    #[test]
+    fn parse_reads_the_dumped_trades() {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+        portfolio.buy(
+            "ITSA4",
+            100,
+            dec!(9.5),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-03-04 10:30 UTC),
+        );
+        let mut dump = vec![];
+        portfolio
+            .dump_trades(&mut dump)
+            .expect("The trades should be dumped");
+
+        let Ok(statement) = parse(dump.as_slice()) else {
+            panic!("The dump should be valid");
+        };
+
+        assert_eq!(statement.operations.len(), 1);
+        assert_eq!(statement.operations[0].symbol, "ITSA4");
+        assert_eq!(
+            statement.operations[0].datetime,
+            datetime!(2024-03-04 10:30 UTC)
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! The trades in any spreadsheet or CSV file, read according to a mapping profile that tells which
+//! columns have the trades' fields and how they are written. A new broker's format only needs a
+//! new profile.
+
+use super::sheet::{normalize, Cell, NumberFormat, Sheet, BRAZILIAN_NUMBERS};
+use super::{
+    parse_any_symbol, positive_quantity, ImportedOperation, LineError, OperationKind, Statement,
+};
+use crate::portfolio::{Fees, TradeKind};
+use anyhow::{ensure, Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::path::Path;
+
+/// How the trades are laid out in the files of a broker, it's read from a JSON file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// The column separator of the CSV files, it's detected from the header when absent.
+    #[serde(default)]
+    delimiter: Option<char>,
+    #[serde(default)]
+    locale: Locale,
+    /// The format of the dates, e.g. `[day]/[month]/[year]`, it may also have the time.
+    #[serde(default = "default_date_format")]
+    date_format: String,
+    columns: Columns,
+    /// The values of the `kind` column for the purchases, e.g. `C` or `Compra`.
+    buy: Vec<String>,
+    /// The values of the `kind` column for the sales, e.g. `V` or `Venda`.
+    sell: Vec<String>,
+}
+
+/// The names of the columns with each field of the trades.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Columns {
+    symbol: String,
+    date: String,
+    kind: String,
+    quantity: String,
+    price: String,
+    #[serde(default)]
+    brokerage: Option<String>,
+    #[serde(default)]
+    emoluments: Option<String>,
+    #[serde(default)]
+    settlement: Option<String>,
+    #[serde(default)]
+    iss: Option<String>,
+    /// The income tax withheld in the sales, it's estimated when absent.
+    #[serde(default)]
+    withheld_tax: Option<String>,
+}
+
+/// How the numbers are written.
+#[derive(Deserialize, Default, Clone, Copy)]
+enum Locale {
+    /// `1,234.56`
+    #[default]
+    #[serde(rename = "en-US")]
+    English,
+    /// `1.234,56`
+    #[serde(rename = "pt-BR")]
+    Brazilian,
+}
+
+/// The indexes of the columns in the file.
+struct ColumnIndexes {
+    symbol: usize,
+    date: usize,
+    kind: usize,
+    quantity: usize,
+    price: usize,
+    brokerage: Option<usize>,
+    emoluments: Option<usize>,
+    settlement: Option<usize>,
+    iss: Option<usize>,
+    withheld_tax: Option<usize>,
+}
+
+impl Profile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Could not open the profile {path:?}"))?;
+        let profile: Profile = serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Could not read the profile {path:?}"))?;
+
+        time::format_description::parse_borrowed::<1>(&profile.date_format)
+            .with_context(|| format!("Invalid date format `{}`", profile.date_format))?;
+        ensure!(
+            profile
+                .delimiter
+                .is_none_or(|delimiter| delimiter.is_ascii()),
+            "The delimiter must be an ASCII character"
+        );
+        ensure!(
+            !profile.buy.is_empty() && !profile.sell.is_empty(),
+            "The profile must list the `buy` and the `sell` keywords"
+        );
+
+        Ok(profile)
+    }
+
+    /// Reads all the trades in the file at `path`, failing when it can't be read or lacks the
+    /// profile's columns. Every invalid line is reported instead of only the first one.
+    pub fn parse(&self, path: &Path) -> Result<Result<Statement, Vec<LineError>>> {
+        let delimiter = self.delimiter.map(|delimiter| delimiter as u8);
+        let sheet = Sheet::read_with_delimiter(path, delimiter)?;
+
+        let optional = |name: &Option<String>| {
+            name.as_deref()
+                .map(|name| sheet.required_column(name))
+                .transpose()
+        };
+        let columns = ColumnIndexes {
+            symbol: sheet.required_column(&self.columns.symbol)?,
+            date: sheet.required_column(&self.columns.date)?,
+            kind: sheet.required_column(&self.columns.kind)?,
+            quantity: sheet.required_column(&self.columns.quantity)?,
+            price: sheet.required_column(&self.columns.price)?,
+            brokerage: optional(&self.columns.brokerage)?,
+            emoluments: optional(&self.columns.emoluments)?,
+            settlement: optional(&self.columns.settlement)?,
+            iss: optional(&self.columns.iss)?,
+            withheld_tax: optional(&self.columns.withheld_tax)?,
+        };
+
+        let mut operations = vec![];
+        let mut errors = vec![];
+        for (line, row) in &sheet.rows {
+            match self.parse_trade(*line, row, &columns) {
+                Ok(operation) => operations.push(operation),
+                Err(error) => errors.push(LineError { line: *line, error }),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Ok(Statement {
+                operations,
+                ignored: 0,
+                totals: vec![],
+            }))
+        } else {
+            Ok(Err(errors))
+        }
+    }
+
+    fn parse_trade(
+        &self,
+        line: usize,
+        row: &[Cell],
+        columns: &ColumnIndexes,
+    ) -> Result<ImportedOperation> {
+        let cell = |index: usize| row.get(index).unwrap_or(&Cell::Empty);
+        let number_format = self.locale.number_format();
+        let amount = |index: Option<usize>| -> Result<Option<Decimal>> {
+            match index {
+                Some(index) => cell(index).number(number_format),
+                None => Ok(None),
+            }
+        };
+
+        let symbol = parse_any_symbol(&cell(columns.symbol).to_text())?;
+        let datetime = cell(columns.date).datetime(&self.date_format)?.assume_utc();
+
+        let kind = cell(columns.kind).to_text();
+        let is_kind = |keywords: &[String]| {
+            keywords
+                .iter()
+                .any(|keyword| normalize(keyword) == normalize(&kind))
+        };
+        let kind = if is_kind(&self.buy) {
+            TradeKind::Buy
+        } else if is_kind(&self.sell) {
+            TradeKind::Sell
+        } else {
+            anyhow::bail!(
+                "Unknown trade kind `{kind}`, expected one of {}",
+                self.buy
+                    .iter()
+                    .chain(&self.sell)
+                    .map(|keyword| format!("`{keyword}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        };
+
+        let quantity = positive_quantity(cell(columns.quantity).required_number(number_format)?)?;
+        let price = cell(columns.price).required_number(number_format)?;
+        ensure!(!price.is_sign_negative(), "The price can't be negative");
+
+        // The fees are usually written as negative amounts by the brokers.
+        let fees = Fees {
+            brokerage: amount(columns.brokerage)?.unwrap_or_default().abs(),
+            emoluments: amount(columns.emoluments)?.unwrap_or_default().abs(),
+            settlement: amount(columns.settlement)?.unwrap_or_default().abs(),
+            iss: amount(columns.iss)?.unwrap_or_default().abs(),
+        };
+        let withheld_tax = match kind {
+            TradeKind::Sell => amount(columns.withheld_tax)?.map(|tax| tax.abs()),
+            TradeKind::Buy => None,
+        };
+
+        Ok(ImportedOperation {
+            line,
+            symbol,
+            datetime,
+            kind: OperationKind::Trade {
+                kind,
+                quantity,
+                price,
+                fees,
+                withheld_tax,
+            },
+        })
+    }
+}
+
+impl Locale {
+    fn number_format(self) -> NumberFormat {
+        match self {
+            Locale::English => NumberFormat {
+                decimal_separator: '.',
+                thousands_separator: Some(','),
+            },
+            Locale::Brazilian => BRAZILIAN_NUMBERS,
+        }
+    }
+}
+
+fn default_date_format() -> String {
+    "[year]-[month]-[day]".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use tempfile::TempDir;
+    use time::macros::datetime;
+
+    const PROFILE: &str = r#"{
+        "locale": "pt-BR",
+        "date_format": "[day]/[month]/[year] [hour]:[minute]",
+        "columns": {
+            "symbol": "Ativo",
+            "date": "Data",
+            "kind": "C/V",
+            "quantity": "Quantidade",
+            "price": "Preço",
+            "brokerage": "Corretagem",
+            "withheld_tax": "IRRF"
+        },
+        "buy": ["C"],
+        "sell": ["V"]
+    }"#;
+
+    /// Writes the `profile` and the `trades` to files, parsing the trades with the profile.
+    fn parse_with(profile: &str, trades: &str) -> Result<Result<Statement, Vec<LineError>>> {
+        let directory = TempDir::new().expect("The temporary directory should be created");
+        let profile_path = directory.path().join("profile.json");
+        let trades_path = directory.path().join("trades.csv");
+        std::fs::write(&profile_path, profile).expect("The profile should be written");
+        std::fs::write(&trades_path, trades).expect("The trades should be written");
+
+        Profile::load(&profile_path)?.parse(&trades_path)
+    }
+
+    #[test]
+    fn parse_reads_the_trades_with_the_profile() {
+        let trades = "Data;Ativo;C/V;Quantidade;Preço;Corretagem;IRRF\n\
+            04/03/2024 10:30;b3sa3;C;100;11,20;-4,90;\n\
+            05/03/2024 15:00;AAPL;v;2;1.234,56;-4,90;-0,12\n\
+            06/03/2024 11:00;PETR4F;C;7;38,00;;\n";
+
+        let Ok(statement) = parse_with(PROFILE, trades).expect("The trades should be read") else {
+            panic!("The trades should be valid");
+        };
+
+        let symbols: Vec<&str> = statement
+            .operations
+            .iter()
+            .map(|operation| operation.symbol.as_str())
+            .collect();
+        assert_eq!(symbols, ["B3SA3", "AAPL", "PETR4"]);
+
+        let sale = &statement.operations[1];
+        assert_eq!(sale.line, 3);
+        assert_eq!(sale.datetime, datetime!(2024-03-05 15:00 UTC));
+        let OperationKind::Trade {
+            kind,
+            quantity,
+            price,
+            fees,
+            withheld_tax,
+        } = &sale.kind
+        else {
+            panic!("The operation should be a trade");
+        };
+        assert!(*kind == TradeKind::Sell);
+        assert_eq!(*quantity, 2);
+        assert_eq!(*price, dec!(1234.56));
+        assert_eq!(fees.brokerage, dec!(4.90));
+        assert_eq!(*withheld_tax, Some(dec!(0.12)));
+    }
+
+    #[test]
+    fn parse_reports_every_invalid_line() {
+        let trades = "Data;Ativo;C/V;Quantidade;Preço;Corretagem;IRRF\n\
+            04/03/2024 10:30;ITSA4;C;100;9,50;;\n\
+            2024-03-05;ITSA4;X;100;9,50;;\n\
+            05/03/2024 10:30;;C;100;9,50;;\n\
+            06/03/2024 10:30;ITSA4;V;1,5;9,50;;\n";
+
+        let Err(errors) = parse_with(PROFILE, trades).expect("The trades should be read") else {
+            panic!("The trades should be invalid");
+        };
+
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, [3, 4, 5]);
+    }
+
+    #[test]
+    fn parse_requires_the_profile_columns() {
+        let trades = "Data;Ativo;C/V;Quantidade;Preço\n04/03/2024 10:30;ITSA4;C;100;9,50\n";
+
+        assert!(parse_with(PROFILE, trades).is_err());
+    }
+
+    #[test]
+    fn load_requires_the_trade_kinds() {
+        let profile = PROFILE.replace(r#""buy": ["C"]"#, r#""buy": []"#);
+
+        assert!(parse_with(&profile, "").is_err());
+    }
+}
@@ -0,0 +1,415 @@
+//! Reads the first sheet of a spreadsheet (XLSX, XLS or ODS) or a CSV file as rows of cells.
+
+use crate::money::from_legacy_float;
+use anyhow::{ensure, Context, Result};
+use calamine::{open_workbook_auto, Data, Reader};
+use rust_decimal::Decimal;
+use std::path::Path;
+use time::macros::format_description;
+use time::{Date, Duration, PrimitiveDateTime};
+
+/// The spreadsheets count the days since this date, Excel's day 1 is 1900-01-01 but it also
+/// counts the non-existent 1900-02-29.
+const SPREADSHEET_EPOCH: Date = time::macros::date!(1899 - 12 - 30);
+
+/// The time is stored as the fraction of the day.
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+pub struct Sheet {
+    headers: Vec<String>,
+    /// The rows after the headers along with their line number, the empty ones are skipped.
+    pub rows: Vec<(usize, Vec<Cell>)>,
+}
+
+pub enum Cell {
+    Text(String),
+    Number(Decimal),
+    Date(Date),
+    /// A date with a time other than midnight, only the spreadsheets have them.
+    DateTime(PrimitiveDateTime),
+    Empty,
+}
+
+/// How the numbers written as text are formatted.
+#[derive(Clone, Copy)]
+pub struct NumberFormat {
+    pub decimal_separator: char,
+    pub thousands_separator: Option<char>,
+}
+
+/// The Brazilian format, e.g. `1.234,56`.
+pub const BRAZILIAN_NUMBERS: NumberFormat = NumberFormat {
+    decimal_separator: ',',
+    thousands_separator: Some('.'),
+};
+
+impl Sheet {
+    /// Reads the file according to its extension, any other extension is read as CSV.
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::read_with_delimiter(path, None)
+    }
+
+    /// Reads the file as [`Sheet::read`], the CSV files use the `delimiter` instead of the one
+    /// detected from their header.
+    pub fn read_with_delimiter(path: &Path, delimiter: Option<u8>) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        let rows = match extension.as_deref() {
+            Some("xlsx" | "xlsm" | "xls" | "ods") => read_spreadsheet(path)?,
+            _ => read_csv(path, delimiter)?,
+        };
+
+        Self::from_rows(rows)
+    }
+
+    fn from_rows(rows: Vec<Vec<Cell>>) -> Result<Self> {
+        let mut rows = rows
+            .into_iter()
+            .enumerate()
+            .map(|(index, row)| (index + 1, row))
+            .filter(|(_, row)| row.iter().any(|cell| !matches!(cell, Cell::Empty)));
+
+        let (_, headers) = rows.next().context("The file is empty")?;
+        let headers = headers.iter().map(Cell::to_text).collect();
+
+        Ok(Self {
+            headers,
+            rows: rows.collect(),
+        })
+    }
+
+    /// The index of the column named `name`, the case and the accents are ignored.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        let name = normalize(name);
+        self.headers
+            .iter()
+            .position(|header| normalize(header) == name)
+    }
+
+    pub fn has_columns(&self, names: &[&str]) -> bool {
+        names.iter().all(|name| self.column(name).is_some())
+    }
+
+    /// The index of the column named `name`, failing when it's absent.
+    pub fn required_column(&self, name: &str) -> Result<usize> {
+        self.column(name)
+            .with_context(|| format!("There is no `{name}` column"))
+    }
+}
+
+impl Cell {
+    pub fn to_text(&self) -> String {
+        match self {
+            Cell::Text(text) => text.trim().to_string(),
+            Cell::Number(number) => number.to_string(),
+            Cell::Date(date) => date.to_string(),
+            Cell::DateTime(datetime) => datetime
+                .format(format_description!(
+                    "[year]-[month]-[day] [hour]:[minute]:[second]"
+                ))
+                .expect("BUG: Should format a datetime"),
+            Cell::Empty => String::new(),
+        }
+    }
+
+    /// The number in the cell, see [`parse_number`].
+    pub fn number(&self, format: NumberFormat) -> Result<Option<Decimal>> {
+        match self {
+            Cell::Number(number) => Ok(Some(*number)),
+            Cell::Empty => Ok(None),
+            Cell::Date(_) | Cell::DateTime(_) => {
+                anyhow::bail!("Expected a number, found the date {}", self.to_text())
+            }
+            Cell::Text(text) => parse_number(text, format),
+        }
+    }
+
+    pub fn required_number(&self, format: NumberFormat) -> Result<Decimal> {
+        self.number(format)?
+            .with_context(|| format!("Expected a number, found `{}`", self.to_text()))
+    }
+
+    /// The date in the cell, the text is parsed with the `format` (e.g. `[day]/[month]/[year]`).
+    pub fn date(&self, format: &str) -> Result<Date> {
+        match self {
+            Cell::Date(date) => Ok(*date),
+            Cell::DateTime(datetime) => Ok(datetime.date()),
+            Cell::Text(text) => {
+                let description = time::format_description::parse_borrowed::<1>(format)
+                    .with_context(|| format!("Invalid date format `{format}`"))?;
+
+                Date::parse(text.trim(), &description)
+                    .or_else(|err| {
+                        Date::parse(text.trim(), format_description!("[year]-[month]-[day]"))
+                            .map_err(|_| err)
+                    })
+                    .with_context(|| format!("Invalid date `{}`", text.trim()))
+            }
+            Cell::Number(_) | Cell::Empty => {
+                anyhow::bail!("Expected a date, found `{}`", self.to_text())
+            }
+        }
+    }
+
+    /// The date and time in the cell, the `format` might have only the date, which is at
+    /// midnight.
+    pub fn datetime(&self, format: &str) -> Result<PrimitiveDateTime> {
+        match self {
+            Cell::DateTime(datetime) => return Ok(*datetime),
+            Cell::Text(text) => {
+                let description = time::format_description::parse_borrowed::<1>(format)
+                    .with_context(|| format!("Invalid date format `{format}`"))?;
+
+                if let Ok(datetime) = PrimitiveDateTime::parse(text.trim(), &description) {
+                    return Ok(datetime);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(self.date(format)?.midnight())
+    }
+}
+
+/// Parses a number written in the `format`, the currency symbols are ignored. The empty text and a
+/// dash are absent.
+pub fn parse_number(text: &str, format: NumberFormat) -> Result<Option<Decimal>> {
+    let mut number: String = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && Some(*c) != format.thousands_separator)
+        .collect();
+    number = number
+        .trim_start_matches("R$")
+        .trim_start_matches("US$")
+        .trim_start_matches('$')
+        .replace(format.decimal_separator, ".");
+
+    if number.is_empty() || number == "-" {
+        return Ok(None);
+    }
+
+    number
+        .parse()
+        .map(Some)
+        .with_context(|| format!("Invalid number `{}`", text.trim()))
+}
+
+fn read_spreadsheet(path: &Path) -> Result<Vec<Vec<Cell>>> {
+    let mut workbook =
+        open_workbook_auto(path).with_context(|| format!("Could not open {path:?}"))?;
+    let range = workbook
+        .worksheet_range_at(0)
+        .context("The spreadsheet has no sheets")?
+        .with_context(|| format!("Could not read {path:?}"))?;
+
+    range
+        .rows()
+        .map(|row| row.iter().map(cell_from_spreadsheet).collect())
+        .collect()
+}
+
+fn cell_from_spreadsheet(data: &Data) -> Result<Cell> {
+    Ok(match data {
+        Data::Int(number) => Cell::Number(Decimal::from(*number)),
+        Data::Float(number) => Cell::Number(from_legacy_float(
+            Decimal::try_from(*number).with_context(|| format!("Invalid number {number}"))?,
+        )),
+        Data::DateTime(datetime) => {
+            let value = datetime.as_f64();
+            let date = SPREADSHEET_EPOCH + Duration::days(value.trunc() as i64);
+            let seconds = (value.fract() * SECONDS_PER_DAY).round() as i64;
+
+            if seconds == 0 {
+                Cell::Date(date)
+            } else {
+                Cell::DateTime(date.midnight() + Duration::seconds(seconds))
+            }
+        }
+        Data::String(text) | Data::DateTimeIso(text) | Data::DurationIso(text) => {
+            Cell::Text(text.clone())
+        }
+        Data::Bool(value) => Cell::Text(value.to_string()),
+        Data::Error(_) | Data::Empty => Cell::Empty,
+    })
+}
+
+fn read_csv(path: &Path, delimiter: Option<u8>) -> Result<Vec<Vec<Cell>>> {
+    let contents = std::fs::read(path).with_context(|| format!("Could not open {path:?}"))?;
+
+    // The files exported in Brazil usually separate the columns with `;`, as `,` is the decimal
+    // separator.
+    let delimiter = delimiter.unwrap_or_else(|| {
+        let header = contents.split(|byte| *byte == b'\n').next().unwrap_or(&[]);
+        let count = |delimiter: u8| header.iter().filter(|byte| **byte == delimiter).count();
+
+        [b';', b'\t', b',']
+            .into_iter()
+            .max_by_key(|delimiter| count(*delimiter))
+            .expect("BUG: There are delimiters")
+    });
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_slice());
+
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record.with_context(|| format!("Could not read {path:?}"))?;
+
+        // The line is counted up to the record's start, as the reader doesn't count the empty
+        // lines before it, so the rows match the lines of the file.
+        let line = record.position().map_or(rows.len() + 1, |position| {
+            let position = usize::try_from(position.byte())
+                .map_or(contents.len(), |byte| byte.min(contents.len()));
+            let start = contents[position..]
+                .iter()
+                .position(|byte| !matches!(byte, b'\r' | b'\n'))
+                .map_or(contents.len(), |offset| position + offset);
+
+            contents[..start]
+                .iter()
+                .filter(|byte| **byte == b'\n')
+                .count()
+                + 1
+        });
+        ensure!(line > rows.len(), "BUG: The records are read in order");
+        rows.resize_with(line - 1, Vec::new);
+
+        rows.push(
+            record
+                .iter()
+                .map(|field| {
+                    if field.trim().is_empty() {
+                        Cell::Empty
+                    } else {
+                        Cell::Text(field.to_string())
+                    }
+                })
+                .collect(),
+        );
+    }
+
+    Ok(rows)
+}
+
+/// Drops the case, the accents and the surrounding spaces, so the headers can be matched however
+/// they are written.
+pub fn normalize(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ã' | 'ä' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'õ' | 'ö' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use tempfile::TempDir;
+    use time::macros::{date, datetime};
+
+    const ENGLISH_NUMBERS: NumberFormat = NumberFormat {
+        decimal_separator: '.',
+        thousands_separator: Some(','),
+    };
+
+    #[test]
+    fn parse_number_reads_the_formats() {
+        let parse = |text: &str, format: NumberFormat| {
+            parse_number(text, format).expect("The number should be valid")
+        };
+
+        assert_eq!(parse("1.234,56", BRAZILIAN_NUMBERS), Some(dec!(1234.56)));
+        assert_eq!(parse("R$ -0,07", BRAZILIAN_NUMBERS), Some(dec!(-0.07)));
+        assert_eq!(parse("1,234.56", ENGLISH_NUMBERS), Some(dec!(1234.56)));
+        assert_eq!(parse("US$ 150", ENGLISH_NUMBERS), Some(dec!(150)));
+        assert_eq!(parse(" - ", BRAZILIAN_NUMBERS), None);
+        assert_eq!(parse("", BRAZILIAN_NUMBERS), None);
+        assert!(parse_number("9,50 D", BRAZILIAN_NUMBERS).is_err());
+    }
+
+    #[test]
+    fn normalize_drops_the_case_and_the_accents() {
+        assert_eq!(normalize("  Código de Negociação "), "codigo de negociacao");
+        assert_eq!(normalize("PREÇO"), "preco");
+    }
+
+    #[test]
+    fn read_keeps_the_line_numbers() {
+        let directory = TempDir::new().expect("The temporary directory should be created");
+        let path = directory.path().join("trades.csv");
+        std::fs::write(
+            &path,
+            "Código;Preço;Data\nITSA4;9,50;04/03/2024\n;;\n\nPETR4;30,00;2024-03-05\n",
+        )
+        .expect("The file should be written");
+
+        let sheet = Sheet::read(&path).expect("The file should be read");
+
+        assert_eq!(sheet.column("preco"), Some(1));
+        assert!(sheet.has_columns(&["CÓDIGO", "Data"]));
+        assert!(sheet.required_column("Quantidade").is_err());
+
+        let lines: Vec<usize> = sheet.rows.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [2, 5]);
+        let (_, row) = &sheet.rows[1];
+        assert_eq!(row[0].to_text(), "PETR4");
+        assert_eq!(
+            row[1]
+                .required_number(BRAZILIAN_NUMBERS)
+                .expect("The price should be valid"),
+            dec!(30.00)
+        );
+        assert_eq!(
+            row[2]
+                .date("[day]/[month]/[year]")
+                .expect("The ISO date should also be valid"),
+            date!(2024 - 03 - 05)
+        );
+    }
+
+    #[test]
+    fn read_uses_the_given_delimiter() {
+        let directory = TempDir::new().expect("The temporary directory should be created");
+        let path = directory.path().join("trades.txt");
+        std::fs::write(&path, "Código|Data;Hora\nITSA4|04/03/2024;10:30\n")
+            .expect("The file should be written");
+
+        let sheet = Sheet::read_with_delimiter(&path, Some(b'|')).expect("The file should be read");
+
+        assert_eq!(sheet.column("Data;Hora"), Some(1));
+    }
+
+    #[test]
+    fn datetime_defaults_to_midnight() {
+        let cell = Cell::Text("04/03/2024".to_string());
+        let format = "[day]/[month]/[year] [hour]:[minute]";
+
+        assert_eq!(
+            cell.datetime("[day]/[month]/[year]")
+                .expect("The date should be valid"),
+            datetime!(2024-03-04 0:00)
+        );
+        assert_eq!(
+            Cell::Text("04/03/2024 10:30".to_string())
+                .datetime(format)
+                .expect("The datetime should be valid"),
+            datetime!(2024-03-04 10:30)
+        );
+        assert!(Cell::Number(dec!(1)).datetime(format).is_err());
+    }
+}
@@ -1,3 +1,10 @@
 #![allow(clippy::new_without_default)]
+pub mod calendar;
+pub mod import;
+pub mod money;
 pub mod portfolio;
 pub mod stock_market;
+pub mod tax;
+
+// Dates without time are (de)serialized as `YYYY-MM-DD`.
+time::serde::format_description!(serde_date, Date, "[year]-[month]-[day]");
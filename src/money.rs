@@ -0,0 +1,82 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// The amounts of money are in cents, as in the brokerage notes and in the DARF.
+pub const MONEY_DECIMALS: u32 = 2;
+/// The average price keeps more decimals, otherwise the cost of a large position would drift from
+/// its quantity times its average price.
+pub const AVERAGE_PRICE_DECIMALS: u32 = 6;
+/// The prices, FX rates and ratios read from the older portfolios, which stored them as floats, are
+/// rounded to it to drop the floating point errors (e.g. `0.30000000000000004`).
+pub const LEGACY_FLOAT_DECIMALS: u32 = 10;
+
+/// Rounds an amount of money to cents, the halves are rounded away from zero.
+pub fn round_money(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(MONEY_DECIMALS, RoundingStrategy::MidpointAwayFromZero)
+}
+
+/// The average price of `quantity` shares that cost `cost` in total.
+pub fn average_price(cost: Decimal, quantity: Decimal) -> Decimal {
+    if quantity.is_zero() {
+        return Decimal::ZERO;
+    }
+
+    (cost / quantity).round_dp_with_strategy(
+        AVERAGE_PRICE_DECIMALS,
+        RoundingStrategy::MidpointAwayFromZero,
+    )
+}
+
+/// The part of the `amount` proportional to `part` out of `whole`, rounded to cents. It's how the
+/// cost of a position is split when only some of its shares are sold.
+pub fn prorate(amount: Decimal, part: Decimal, whole: Decimal) -> Decimal {
+    if whole.is_zero() {
+        return Decimal::ZERO;
+    }
+
+    round_money(amount * part / whole)
+}
+
+/// Drops the floating point errors of a value stored as a float, e.g. by older portfolios or
+/// spreadsheets.
+pub fn from_legacy_float(value: Decimal) -> Decimal {
+    value.round_dp(LEGACY_FLOAT_DECIMALS).normalize()
+}
+
+/// The `part` as a percentage of the `whole`, it's zero when there is no `whole`.
+pub fn percentage(part: Decimal, whole: Decimal) -> Decimal {
+    if whole.is_zero() {
+        return Decimal::ZERO;
+    }
+
+    (part * Decimal::ONE_HUNDRED / whole).round_dp(MONEY_DECIMALS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn round_money_rounds_the_halves_away_from_zero() {
+        assert_eq!(round_money(dec!(0.125)), dec!(0.13));
+        assert_eq!(round_money(dec!(-0.125)), dec!(-0.13));
+        assert_eq!(round_money(dec!(0.124)), dec!(0.12));
+    }
+
+    #[test]
+    fn average_price_keeps_more_decimals() {
+        assert_eq!(average_price(dec!(100), dec!(3)), dec!(33.333333));
+        assert_eq!(average_price(dec!(100), Decimal::ZERO), Decimal::ZERO);
+    }
+
+    #[test]
+    fn prorate_rounds_to_cents() {
+        assert_eq!(prorate(dec!(100), dec!(1), dec!(3)), dec!(33.33));
+        assert_eq!(prorate(dec!(100), dec!(1), Decimal::ZERO), Decimal::ZERO);
+    }
+
+    #[test]
+    fn from_legacy_float_drops_the_floating_point_errors() {
+        assert_eq!(from_legacy_float(dec!(0.30000000000000004)), dec!(0.3));
+    }
+}
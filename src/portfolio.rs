@@ -1,78 +1,392 @@
+pub(crate) mod backup;
+mod currency;
+mod lock;
+mod lots;
+mod migration;
+mod storage;
+
+pub use backup::Backup;
+pub use currency::Currency;
+pub use lock::{LockMode, PortfolioLock};
+pub use lots::{CostBasis, Lot, LotId, Lots, RealizedLot};
+pub use migration::{AppliedMigration, MigrationReport, SCHEMA_VERSION};
+pub use storage::StorageFormat;
+
+use crate::money::{average_price, from_legacy_float, prorate, round_money};
 use anyhow::ensure;
 use anyhow::Context;
 use anyhow::Result;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{dec, Decimal};
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::path::Path;
+use std::path::PathBuf;
 use time::format_description;
+use time::Date;
+use time::Duration;
 use time::OffsetDateTime;
+use time::PrimitiveDateTime;
+use time::Time;
 
-#[derive(Serialize, Deserialize)]
+/// The income tax withheld at source (IRRF or "dedo-duro") over the swing trade sales.
+pub const SWING_TRADE_WITHHOLDING_RATE: Decimal = dec!(0.00005);
+/// The income tax withheld at source over the day trade profits.
+pub const DAY_TRADE_WITHHOLDING_RATE: Decimal = dec!(0.01);
+/// The income tax usually withheld from JCP.
+pub const JCP_WITHHOLDING_RATE: Decimal = dec!(0.15);
+/// The shares received in the corporate events are rounded to it, the ratios that can't be
+/// represented exactly (e.g. a third) would leave a tiny fraction of a share behind.
+const SHARE_DECIMALS: u32 = 6;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Portfolio {
+    /// Where the portfolio is saved.
+    #[serde(skip)]
+    path: PathBuf,
+    /// The schema version the portfolio was saved with, the older ones are migrated when loaded.
+    #[serde(default)]
+    version: u32,
     pub stocks: HashMap<String, Stock>,
+    /// The fee schedules of the brokers, keyed by the broker name.
+    #[serde(default)]
+    pub brokers: HashMap<String, FeeSchedule>,
+    /// The cost basis of the lots report, the taxes always use the average cost.
+    #[serde(default)]
+    pub cost_basis: CostBasis,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Stock {
     pub symbol: String,
     pub trades: Vec<Trade>,
+    /// The cash income (dividends and JCP) paid by the stock.
+    #[serde(default)]
+    pub incomes: Vec<Income>,
+    /// The corporate events (e.g. splits and bonus shares) that change the shares held, they only
+    /// affect the shares acquired before them.
+    #[serde(default)]
+    pub events: Vec<CorporateEvent>,
+    /// The CNPJ of the company, it's required by the annual income tax declaration.
+    #[serde(default)]
+    pub cnpj: Option<String>,
+    /// Overrides the asset class inferred from the symbol.
+    #[serde(default)]
+    pub asset_class: Option<AssetClass>,
+    /// The currency of the prices, the trades store the FX rate to BRL when it's not BRL.
+    #[serde(default)]
+    pub currency: Currency,
+}
+
+/// The kind of asset, each one is taxed and declared differently.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum AssetClass {
+    Stock,
+    /// A certificate made of stocks of the same company (e.g. TAEE11).
+    Unit,
+    /// Exchange traded funds (e.g. BOVA11).
+    Etf,
+    /// Brazilian depositary receipts of foreign stocks (e.g. AAPL34).
+    Bdr,
+    /// Fundos de investimento imobiliário (e.g. HGLG11).
+    Fii,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Trade {
     pub quantity: u32,
-    pub price: f64,
+    pub price: Decimal,
     #[serde(with = "time::serde::rfc3339")]
     pub datetime: OffsetDateTime,
     pub kind: TradeKind,
-    /// The list of splits allow us to adjust the stock quantity according to the reference date
-    /// (i.e. if the reference date is before or after the split date).
-    pub splits: Vec<Split>,
+    /// Older portfolios stored the splits in each trade, they are only read to be converted into
+    /// the stock's corporate events.
+    #[serde(rename = "splits", default, skip_serializing)]
+    legacy_splits: Vec<Split>,
+    /// The costs of the trade, they are added to the purchase cost and deducted from the sale
+    /// proceeds.
+    #[serde(default)]
+    pub fees: Fees,
+    /// The income tax withheld at source over a sale as reported by the broker, when absent it's
+    /// estimated from the sold amount.
+    #[serde(default)]
+    pub withheld_tax: Option<Decimal>,
+    /// The lot sold when using the specific lot identification.
+    #[serde(default)]
+    pub lot: Option<LotId>,
+    /// The BRL value of one unit of the stock's currency when the trade happened, it's absent for
+    /// the BRL stocks.
+    #[serde(default)]
+    pub fx_rate: Option<Decimal>,
+}
+
+/// The optional details of a trade.
+#[derive(Default)]
+pub struct TradeDetails {
+    /// The income tax withheld at source in a sale as reported by the broker, it's estimated when
+    /// absent.
+    pub withheld_tax: Option<Decimal>,
+    /// The lot to sell from, it's stored with its number once found.
+    pub lot: Option<LotId>,
+    /// The BRL value of one unit of the stock's currency.
+    pub fx_rate: Option<Decimal>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Split {
-    pub ratio: f64,
+/// The costs charged over a trade.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+pub struct Fees {
+    /// Corretagem, charged by the broker.
+    pub brokerage: Decimal,
+    /// Emolumentos, charged by B3.
+    pub emoluments: Decimal,
+    /// Taxa de liquidação, charged by B3.
+    pub settlement: Decimal,
+    /// The ISS tax over the brokerage.
+    pub iss: Decimal,
+}
+
+/// How a broker charges the fees of a trade.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct FeeSchedule {
+    /// The fixed brokerage charged per trade.
+    pub brokerage: Decimal,
+    /// The emoluments rate over the traded amount, as a percentage.
+    pub emoluments_rate: Decimal,
+    /// The settlement rate over the traded amount, as a percentage.
+    pub settlement_rate: Decimal,
+    /// The ISS rate over the brokerage, as a percentage.
+    pub iss_rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Split {
+    ratio: Decimal,
     #[serde(with = "time::serde::rfc3339")]
+    datetime: OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CorporateEvent {
+    #[serde(with = "time::serde::rfc3339")]
+    pub datetime: OffsetDateTime,
+    pub kind: CorporateEventKind,
+}
+
+/// The fractions of shares that result from the events are sold in auction by the company, when
+/// the `fraction_price` (the auction price per share) is known the proceeds are realized as a sale,
+/// otherwise the cost of the fraction remains in the average price.
+///
+/// Merger and spin-offs are recorded in both stocks, in the original one as a `Merger` or `SpinOff`
+/// and in the receiving one as a `Transfer`.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum CorporateEventKind {
+    /// Desdobramento, every share becomes `ratio` shares. Older portfolios also stored grupamentos
+    /// as a split with a `ratio` lower than 1.
+    Split {
+        ratio: Decimal,
+        #[serde(default)]
+        fraction_price: Option<Decimal>,
+    },
+    /// Grupamento, every `ratio` shares become a single share.
+    ReverseSplit {
+        ratio: Decimal,
+        fraction_price: Option<Decimal>,
+    },
+    /// Bonificação, every share receives `ratio` new shares (e.g. 0.1 for 10%) and the company
+    /// declares the cost of each new share, which is added to the cost basis.
+    Bonus {
+        ratio: Decimal,
+        unit_cost: Decimal,
+        fraction_price: Option<Decimal>,
+    },
+    /// The stock was renamed from the `from` symbol, the history is kept under the new symbol.
+    Rename { from: String },
+    /// Incorporação, the company was merged `into` another one and every share became `ratio`
+    /// shares of it, the shares are transferred along with their cost.
+    Merger { into: String, ratio: Decimal },
+    /// Cisão, part of the company was spun off `into` another one and every share received `ratio`
+    /// shares of it, `cost_percentage` of the cost is transferred to the new shares.
+    SpinOff {
+        into: String,
+        ratio: Decimal,
+        cost_percentage: Decimal,
+    },
+    /// The shares received `from` a merger or spin-off, they are derived along with their cost
+    /// from the `from` position right before the event, so a late recorded trade is accounted for.
+    Transfer {
+        from: String,
+        fraction_price: Option<Decimal>,
+    },
+}
+
+/// The shares held at a given moment.
+#[derive(Default, Clone, Copy)]
+pub struct Position {
+    pub quantity: u32,
+    /// The total cost of the shares, including the fees.
+    pub cost: Decimal,
+}
+
+/// A day selling more shares of the stock than held, the position can't be replayed past it.
+#[derive(Debug)]
+pub struct Oversold {
+    pub symbol: String,
+    pub date: Date,
+}
+
+/// A realized result, either from a sale or from fractions of shares sold in auction.
+pub struct Realization {
     pub datetime: OffsetDateTime,
+    pub regime: TradeRegime,
+    pub sold_amount: Decimal,
+    pub profit: Decimal,
+    /// The income tax withheld at source, it can be deducted from the monthly tax.
+    pub withheld_tax: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Income {
+    pub kind: IncomeKind,
+    /// The gross amount paid, before the withheld tax.
+    pub amount: Decimal,
+    /// The income tax withheld at source, JCP usually have 15% withheld.
+    pub withheld_tax: Decimal,
+    /// Only the shareholders before this date are entitled to the income.
+    #[serde(with = "crate::serde_date")]
+    pub ex_date: Date,
+    #[serde(with = "crate::serde_date")]
+    pub payment_date: Date,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum IncomeKind {
+    Dividend,
+    /// Juros sobre capital próprio.
+    Jcp,
+}
+
+/// Day trades are the shares bought and sold in the same day, they are taxed separately from the
+/// swing trades.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum TradeRegime {
+    SwingTrade,
+    DayTrade,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
 pub enum TradeKind {
     Buy,
     Sell,
 }
 
+/// The income received in a period, it's accounted by the payment date.
+#[derive(Default)]
+pub struct IncomeSummary {
+    pub dividends: Decimal,
+    pub jcp: Decimal,
+    pub withheld_tax: Decimal,
+}
+
 #[derive(Default)]
 pub struct MonthSummary {
-    pub profit: f64,
-    pub sold_amount: f64,
+    /// The swing trade profit.
+    pub profit: Decimal,
+    /// The swing trade sold amount.
+    pub sold_amount: Decimal,
+    /// The swing trade income tax withheld at source.
+    pub withheld_tax: Decimal,
+    pub day_trade_profit: Decimal,
+    pub day_trade_sold_amount: Decimal,
+    pub day_trade_withheld_tax: Decimal,
 }
 
 impl Portfolio {
-    pub fn new() -> Self {
+    /// An empty portfolio, it's only written to `path` when saved.
+    pub fn new(path: &Path) -> Self {
         Self {
+            path: path.to_path_buf(),
+            version: SCHEMA_VERSION,
             stocks: HashMap::new(),
+            brokers: HashMap::new(),
+            cost_basis: CostBasis::default(),
         }
     }
 
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Saves the portfolio in the format of its file extension, a crash never leaves it half
+    /// written. The previous version is kept as a backup.
     pub fn save(&self) -> Result<()> {
-        let file = std::fs::File::create("portfolio.json")?;
-        serde_json::to_writer(file, self)?;
+        storage::save(self, &self.path)
+            .with_context(|| format!("Could not save the portfolio {:?}", self.path))
+    }
+
+    /// Moves the portfolio to `path`, converting it to the format of its extension. The file it's
+    /// moved from is kept as a backup.
+    pub fn move_to(&mut self, path: &Path) -> Result<()> {
+        ensure!(!path.exists(), "There is already a portfolio at {path:?}");
+
+        let previous_path = std::mem::replace(&mut self.path, path.to_path_buf());
+        self.save()?;
+
+        if previous_path.exists() {
+            backup::take_backup(&previous_path)?;
+            std::fs::remove_file(&previous_path)
+                .with_context(|| format!("Could not remove the portfolio {previous_path:?}"))?;
+        }
+
         Ok(())
     }
 
-    pub fn load() -> Result<Self> {
-        let file = std::fs::File::open("portfolio.json")?;
-        let portfolio = serde_json::from_reader(file)?;
-        Ok(portfolio)
+    /// The backups of the portfolio, the most recent first.
+    pub fn backups(&self) -> Result<Vec<Backup>> {
+        backup::backups(&self.path)
+    }
+
+    /// Rolls the portfolio back to the backup `id`. The current version is backed up as well, so
+    /// the restore can be undone.
+    pub fn restore(&mut self, id: &str) -> Result<()> {
+        let backup = self
+            .backups()?
+            .into_iter()
+            .find(|backup| backup.id == id)
+            .with_context(|| format!("There is no backup `{id}`"))?;
+
+        let mut restored = Portfolio::load(&backup.path)?;
+        restored.path = std::mem::take(&mut self.path);
+        *self = restored;
+
+        self.save()
+    }
+
+    /// Loads the portfolio, it's migrated to the current schema version in memory and only saved
+    /// in it when saved.
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::load_and_migrate(path)?.0)
+    }
+
+    /// Loads the portfolio along with the report of the migrations applied to it.
+    pub fn load_and_migrate(path: &Path) -> Result<(Self, MigrationReport)> {
+        let mut portfolio = storage::load(path)
+            .with_context(|| format!("Could not load the portfolio {path:?}"))?;
+        portfolio.path = path.to_path_buf();
+
+        let report = migration::migrate(&mut portfolio);
+
+        Ok((portfolio, report))
     }
 
     pub fn dump_trades(&self, file: &mut impl std::io::Write) -> Result<()> {
         writeln!(file, "symbol;date;kind;quantity;price")?;
 
-        let format_description = format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]")?;
+        let format_description = format_description::parse_borrowed::<1>(
+            "[year]-[month]-[day] [hour]:[minute]:[second]",
+        )?;
 
         for stock in self.stocks.values() {
             for trade in &stock.trades {
@@ -91,47 +405,471 @@ impl Portfolio {
         Ok(())
     }
 
-    pub fn split(&mut self, symbol: &str, ratio: f64, datetime: OffsetDateTime) {
-        let stock = self
-            .stocks
+    /// Returns the fraction of a share left over by the event.
+    pub fn split(
+        &mut self,
+        symbol: &str,
+        ratio: Decimal,
+        fraction_price: Option<Decimal>,
+        datetime: OffsetDateTime,
+    ) -> Result<Decimal> {
+        ensure!(ratio > Decimal::ZERO, "The split ratio must be positive");
+
+        self.stocks
             .entry(symbol.to_string())
             .or_insert_with(|| Stock::new(symbol.to_string()));
 
-        stock.split(ratio, datetime);
+        self.add_event(
+            symbol,
+            CorporateEventKind::Split {
+                ratio,
+                fraction_price,
+            },
+            datetime,
+        )
     }
 
-    pub fn buy(&mut self, symbol: &str, quantity: u32, price: f64, datetime: OffsetDateTime) {
+    /// Returns the fraction of a share left over by the event.
+    pub fn reverse_split(
+        &mut self,
+        symbol: &str,
+        ratio: Decimal,
+        fraction_price: Option<Decimal>,
+        datetime: OffsetDateTime,
+    ) -> Result<Decimal> {
+        ensure!(
+            ratio > Decimal::ZERO,
+            "The reverse split ratio must be positive"
+        );
+
+        self.add_event(
+            symbol,
+            CorporateEventKind::ReverseSplit {
+                ratio,
+                fraction_price,
+            },
+            datetime,
+        )
+    }
+
+    /// Returns the fraction of a share left over by the event.
+    pub fn bonus(
+        &mut self,
+        symbol: &str,
+        ratio: Decimal,
+        unit_cost: Decimal,
+        fraction_price: Option<Decimal>,
+        datetime: OffsetDateTime,
+    ) -> Result<Decimal> {
+        ensure!(ratio > Decimal::ZERO, "The bonus ratio must be positive");
+
+        self.add_event(
+            symbol,
+            CorporateEventKind::Bonus {
+                ratio,
+                unit_cost,
+                fraction_price,
+            },
+            datetime,
+        )
+    }
+
+    /// Renames the stock keeping its whole history, the new symbol can't be in the portfolio.
+    pub fn rename(
+        &mut self,
+        symbol: &str,
+        new_symbol: &str,
+        datetime: OffsetDateTime,
+    ) -> Result<()> {
+        ensure!(
+            !self.stocks.contains_key(new_symbol),
+            "There is already {new_symbol} in the portfolio, use a merger instead"
+        );
+
+        let mut stock = self
+            .stocks
+            .remove(symbol)
+            .with_context(|| format!("There is no {symbol} in the portfolio"))?;
+
+        stock.symbol = new_symbol.to_string();
+        stock.add_event(
+            CorporateEventKind::Rename {
+                from: symbol.to_string(),
+            },
+            datetime,
+        );
+
+        self.stocks.insert(new_symbol.to_string(), stock);
+
+        // The mergers and spin-offs refer to the other stock by its symbol.
+        for stock in self.stocks.values_mut() {
+            for event in &mut stock.events {
+                event.kind.rename_reference(symbol, new_symbol);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merges the stock into another one, every share becomes `ratio` shares of it. Returns the
+    /// fraction of a share left over by the event.
+    pub fn merge(
+        &mut self,
+        symbol: &str,
+        into: &str,
+        ratio: Decimal,
+        fraction_price: Option<Decimal>,
+        datetime: OffsetDateTime,
+    ) -> Result<Decimal> {
+        ensure!(symbol != into, "A stock can't be merged into itself");
+        ensure!(ratio > Decimal::ZERO, "The merger ratio must be positive");
+
+        self.add_event(
+            symbol,
+            CorporateEventKind::Merger {
+                into: into.to_string(),
+                ratio,
+            },
+            datetime,
+        )?;
+
+        self.transfer(symbol, into, fraction_price, datetime)
+    }
+
+    /// Spins off part of the stock into another one, every share receives `ratio` shares of it and
+    /// `cost_percentage` of the cost is transferred to them. Returns the fraction of a share left
+    /// over by the event.
+    pub fn spin_off(
+        &mut self,
+        symbol: &str,
+        into: &str,
+        ratio: Decimal,
+        cost_percentage: Decimal,
+        fraction_price: Option<Decimal>,
+        datetime: OffsetDateTime,
+    ) -> Result<Decimal> {
+        ensure!(symbol != into, "A stock can't be spun off into itself");
+        ensure!(ratio > Decimal::ZERO, "The spin-off ratio must be positive");
+        ensure!(
+            (Decimal::ZERO..=Decimal::ONE_HUNDRED).contains(&cost_percentage),
+            "The cost percentage must be between 0 and 100"
+        );
+
+        self.add_event(
+            symbol,
+            CorporateEventKind::SpinOff {
+                into: into.to_string(),
+                ratio,
+                cost_percentage,
+            },
+            datetime,
+        )?;
+
+        self.transfer(symbol, into, fraction_price, datetime)
+    }
+
+    /// Records the shares received by `into` in a merger or spin-off `from` another stock. Returns
+    /// the fraction of a share left over by the event.
+    fn transfer(
+        &mut self,
+        from: &str,
+        into: &str,
+        fraction_price: Option<Decimal>,
+        datetime: OffsetDateTime,
+    ) -> Result<Decimal> {
+        self.add_checked_event(
+            into,
+            CorporateEventKind::Transfer {
+                from: from.to_string(),
+                fraction_price,
+            },
+            datetime,
+        )?;
+
+        let (shares, _) = self.transferred(from, into, datetime)?;
+        Ok(whole_and_fraction(shares).1)
+    }
+
+    /// The shares received by `into` in a merger or spin-off `from` another stock, along with their
+    /// cost. They are derived from the `from` position right before the event.
+    fn transferred(
+        &self,
+        from: &str,
+        into: &str,
+        datetime: OffsetDateTime,
+    ) -> Result<(Decimal, Decimal)> {
+        let Some((stock, ratio, cost_percentage)) = self.transfer_terms(from, into, datetime)
+        else {
+            return Ok((Decimal::ZERO, Decimal::ZERO));
+        };
+
+        let position = stock.position(self, datetime)?;
+        let shares = Decimal::from(position.quantity)
+            .checked_mul(ratio)
+            .with_context(|| too_many_shares(datetime))?;
+
+        Ok((
+            shares,
+            prorate(position.cost, cost_percentage, Decimal::ONE_HUNDRED),
+        ))
+    }
+
+    /// The `from` stock of a merger or spin-off into `into`, along with the shares received for
+    /// each share and the percentage of the cost transferred to them.
+    fn transfer_terms(
+        &self,
+        from: &str,
+        into: &str,
+        datetime: OffsetDateTime,
+    ) -> Option<(&Stock, Decimal, Decimal)> {
+        let stock = self.stocks.get(from)?;
+
+        stock
+            .events
+            .iter()
+            .filter(|event| event.datetime == datetime)
+            .find_map(|event| match &event.kind {
+                CorporateEventKind::Merger { into: to, ratio } if to == into => {
+                    Some((stock, *ratio, Decimal::ONE_HUNDRED))
+                }
+                CorporateEventKind::SpinOff {
+                    into: to,
+                    ratio,
+                    cost_percentage,
+                } if to == into => Some((stock, *ratio, *cost_percentage)),
+                _ => None,
+            })
+    }
+
+    /// Adds the event to the stock, returns the fraction of a share left over by it.
+    fn add_event(
+        &mut self,
+        symbol: &str,
+        kind: CorporateEventKind,
+        datetime: OffsetDateTime,
+    ) -> Result<Decimal> {
+        let stock = self
+            .stocks
+            .get(symbol)
+            .with_context(|| format!("There is no {symbol} in the portfolio"))?;
+
+        let fraction = kind.fraction(stock.quantity(self, datetime)?);
+        self.add_checked_event(symbol, kind, datetime)?;
+
+        Ok(fraction)
+    }
+
+    /// Adds the event to the stock unless it breaks its replay, e.g. a huge ratio might leave more
+    /// shares than supported.
+    fn add_checked_event(
+        &mut self,
+        symbol: &str,
+        kind: CorporateEventKind,
+        datetime: OffsetDateTime,
+    ) -> Result<()> {
+        let mut stock = self
+            .stocks
+            .get(symbol)
+            .cloned()
+            .unwrap_or_else(|| Stock::new(symbol.to_string()));
+
+        stock.add_event(kind, datetime);
+        stock.position(self, PrimitiveDateTime::MAX.assume_utc())?;
+
+        self.stocks.insert(symbol.to_string(), stock);
+
+        Ok(())
+    }
+
+    pub fn buy(
+        &mut self,
+        symbol: &str,
+        quantity: u32,
+        price: Decimal,
+        fees: Fees,
+        details: TradeDetails,
+        datetime: OffsetDateTime,
+    ) {
         let stock = self
             .stocks
             .entry(symbol.to_string())
             .or_insert_with(|| Stock::new(symbol.to_string()));
 
-        stock.buy(quantity, price, datetime);
+        stock.buy(quantity, price, fees, details, datetime);
     }
 
     pub fn sell(
         &mut self,
         symbol: &str,
         quantity: u32,
-        price: f64,
+        price: Decimal,
+        fees: Fees,
+        mut details: TradeDetails,
         datetime: OffsetDateTime,
-    ) -> Result<f64> {
+    ) -> Result<Decimal> {
+        let mut stock = self
+            .stocks
+            .get(symbol)
+            .cloned()
+            .context("Not enough shares to sell")?;
+
+        // The history is replayed once until the start of the day, only the day is replayed again.
+        let day = datetime.date();
+        let start_of_day = datetime.replace_time(Time::MIDNIGHT);
+        let end_of_day = start_of_day + Duration::days(1);
+        let position = stock.position(self, start_of_day)?;
+
+        ensure!(
+            quantity
+                <= stock
+                    .replay_between(self, position, start_of_day, datetime, |_| {})?
+                    .quantity,
+            "Not enough shares to sell"
+        );
+        if let Some(lot) = details.lot {
+            let lots = stock.lots(self, datetime, CostBasis::SpecificLot)?;
+            details.lot = Some(lots.open[lots.index_of(lot)?].id());
+        }
+
+        // The sale might turn other trades of the same day into day trades, so the profit is the
+        // change in the realized profit of the day. The rest of the history is replayed after the
+        // sale, so it fails when the sale takes the shares of a later one.
+        let mut previous_profit = Decimal::ZERO;
+        stock.replay_between(self, position, start_of_day, end_of_day, |realization| {
+            previous_profit += realization.profit;
+        })?;
+
+        stock.sell(quantity, price, fees, details, datetime);
+
+        let mut profit = Decimal::ZERO;
+        stock.replay_between(
+            self,
+            position,
+            start_of_day,
+            PrimitiveDateTime::MAX.assume_utc(),
+            |realization| {
+                if realization.datetime.date() == day {
+                    profit += realization.profit;
+                }
+            },
+        )?;
+
+        self.stocks.insert(symbol.to_string(), stock);
+
+        Ok(profit - previous_profit)
+    }
+
+    pub fn set_cnpj(&mut self, symbol: &str, cnpj: &str) -> Result<()> {
         let stock = self
             .stocks
             .get_mut(symbol)
-            .context("Not enough shares to sell")?;
+            .with_context(|| format!("There is no {symbol} in the portfolio"))?;
+
+        stock.cnpj = Some(cnpj.to_string());
+
+        Ok(())
+    }
+
+    /// Sets the currency of the stock, it should be set before trading it as the trades of the
+    /// other currencies store their FX rate.
+    pub fn set_currency(&mut self, symbol: &str, currency: Currency) {
+        let stock = self
+            .stocks
+            .entry(symbol.to_string())
+            .or_insert_with(|| Stock::new(symbol.to_string()));
 
-        stock.sell(quantity, price, datetime)
+        stock.currency = currency;
     }
 
-    pub fn profit_by_month(&self, year: i32) -> [MonthSummary; 12] {
-        let mut profit_by_month: [MonthSummary; 12] = Default::default();
+    /// Sets the asset class of the stock, `None` infers it from the symbol.
+    pub fn set_asset_class(&mut self, symbol: &str, asset_class: Option<AssetClass>) -> Result<()> {
+        let stock = self
+            .stocks
+            .get_mut(symbol)
+            .with_context(|| format!("There is no {symbol} in the portfolio"))?;
+
+        stock.asset_class = asset_class;
+
+        Ok(())
+    }
+
+    /// Registers (or replaces) the fee schedule of a broker.
+    pub fn set_broker(&mut self, name: &str, schedule: FeeSchedule) {
+        self.brokers.insert(name.to_string(), schedule);
+    }
+
+    /// Calculates the fees of a trade according to the broker's fee schedule.
+    pub fn broker_fees(&self, broker: &str, quantity: u32, price: Decimal) -> Result<Fees> {
+        let schedule = self
+            .brokers
+            .get(broker)
+            .with_context(|| format!("Unknown broker `{broker}`"))?;
+
+        Ok(schedule.fees(round_money(price * Decimal::from(quantity))))
+    }
+
+    /// The year of the first trade or event in the portfolio.
+    pub fn first_year(&self) -> Option<i32> {
+        self.stocks
+            .values()
+            .flat_map(|stock| {
+                let trades = stock.trades.iter().map(|trade| trade.datetime);
+                let events = stock.events.iter().map(|event| event.datetime);
+                trades.chain(events)
+            })
+            .min()
+            .map(|datetime| datetime.year())
+    }
+
+    /// The profits of each month grouped by the asset class, as they are taxed separately.
+    pub fn profit_by_month(&self, year: i32) -> Result<BTreeMap<AssetClass, [MonthSummary; 12]>> {
+        let mut profit_by_month: BTreeMap<AssetClass, [MonthSummary; 12]> = BTreeMap::new();
+
+        for stock in self.stocks.values() {
+            let profit_by_month = profit_by_month.entry(stock.class()?).or_default();
+            stock.update_profit_by_month(self, profit_by_month, year)?;
+        }
+
+        Ok(profit_by_month)
+    }
+
+    pub fn add_income(&mut self, symbol: &str, income: Income) -> Result<()> {
+        let stock = self
+            .stocks
+            .get_mut(symbol)
+            .with_context(|| format!("There is no {symbol} in the portfolio"))?;
+
+        stock.add_income(income);
+
+        Ok(())
+    }
+
+    pub fn income_by_month(&self, year: i32) -> [IncomeSummary; 12] {
+        let mut income_by_month: [IncomeSummary; 12] = Default::default();
+
+        for stock in self.stocks.values() {
+            for income in stock.incomes_in(year) {
+                let month = income.payment_date.month() as usize - 1;
+                income_by_month[month].add(income);
+            }
+        }
+
+        income_by_month
+    }
+
+    pub fn income_by_symbol(&self, year: i32) -> HashMap<String, IncomeSummary> {
+        let mut income_by_symbol = HashMap::new();
 
         for stock in self.stocks.values() {
-            stock.update_profit_by_month(&mut profit_by_month, year);
+            for income in stock.incomes_in(year) {
+                income_by_symbol
+                    .entry(stock.symbol.clone())
+                    .or_insert_with(IncomeSummary::default)
+                    .add(income);
+            }
         }
 
-        profit_by_month
+        income_by_symbol
     }
 }
 
@@ -140,123 +878,256 @@ impl Stock {
         Self {
             symbol,
             trades: vec![],
+            incomes: vec![],
+            events: vec![],
+            cnpj: None,
+            asset_class: None,
+            currency: Currency::default(),
         }
     }
 
-    fn split(&mut self, ratio: f64, datetime: OffsetDateTime) {
-        for trade in &mut self.trades {
-            if trade.datetime >= datetime {
-                break;
-            }
-
-            trade.splits.push(Split { ratio, datetime });
-        }
+    /// The asset class of the stock, it fails when it's neither set nor inferred from the symbol.
+    pub fn class(&self) -> Result<AssetClass> {
+        self.asset_class
+            .or_else(|| AssetClass::infer(&self.symbol))
+            .with_context(|| {
+                format!(
+                    "The asset class of {} can't be inferred from its symbol, set it with \
+                    `asset-class`",
+                    self.symbol
+                )
+            })
     }
 
-    /// Dynamically calculate the total quantity of the stock at a given date.
-    pub fn quantity(&self, date: OffsetDateTime) -> u32 {
-        let mut quantity = 0;
+    fn add_event(&mut self, kind: CorporateEventKind, datetime: OffsetDateTime) {
+        self.events.push(CorporateEvent { datetime, kind });
+        self.events.sort_by_key(|event| event.datetime);
+    }
 
-        for trade in &self.trades {
-            if trade.datetime >= date {
-                break;
-            }
+    /// Dynamically calculate the total quantity of the stock at a given date. The stock belongs to
+    /// the `portfolio`, which has the stocks it received shares from.
+    pub fn quantity(&self, portfolio: &Portfolio, date: OffsetDateTime) -> Result<u32> {
+        Ok(self.position(portfolio, date)?.quantity)
+    }
 
-            let signal = if trade.kind == TradeKind::Buy {
-                1.0
-            } else {
-                -1.0
-            };
+    /// The first day selling more shares than held. The sales are checked as they are added, but a
+    /// trade or event added before them later might take the shares they sold. The replay stops
+    /// there, so it's the day it failed on.
+    pub fn oversold_on(&self, portfolio: &Portfolio) -> Result<Option<Date>> {
+        let Err(err) = self.position(portfolio, PrimitiveDateTime::MAX.assume_utc()) else {
+            return Ok(None);
+        };
 
-            quantity += (trade.quantity(date) as f64 * signal) as i32;
+        match err.downcast_ref::<Oversold>() {
+            // The stocks it received shares from might be the oversold ones.
+            Some(oversold) if oversold.symbol == self.symbol => Ok(Some(oversold.date)),
+            _ => Err(err),
         }
-
-        quantity as u32
     }
 
     /// Dynamically calculate the average purchase price of the stock at a given date.
-    pub fn average_purchase_price(&self, date: OffsetDateTime) -> f64 {
-        let mut quantity = 0;
-        let mut average_purchase_price = 0.0;
-
-        // We assume that the trades are sorted by date.
-        for trade in &self.trades {
-            if trade.datetime >= date {
-                break;
-            }
+    pub fn average_purchase_price(
+        &self,
+        portfolio: &Portfolio,
+        date: OffsetDateTime,
+    ) -> Result<Decimal> {
+        Ok(self.position(portfolio, date)?.average_price())
+    }
+
+    /// Dynamically calculate the shares held at a given date.
+    pub fn position(&self, portfolio: &Portfolio, date: OffsetDateTime) -> Result<Position> {
+        self.replay(portfolio, date, |_| {})
+    }
+
+    /// Replays the trades and corporate events before `date` in chronological order, calling
+    /// `on_realization` for every realized result.
+    fn replay(
+        &self,
+        portfolio: &Portfolio,
+        date: OffsetDateTime,
+        on_realization: impl FnMut(Realization),
+    ) -> Result<Position> {
+        self.replay_between(
+            portfolio,
+            Position::default(),
+            PrimitiveDateTime::MIN.assume_utc(),
+            date,
+            on_realization,
+        )
+    }
 
-            if trade.kind == TradeKind::Buy {
-                average_purchase_price = ((average_purchase_price * f64::from(quantity))
-                    + (trade.price(date) * trade.quantity(date) as f64))
-                    / f64::from(quantity + trade.quantity(date));
-                quantity += trade.quantity(date);
-            } else {
-                quantity -= trade.quantity(date);
-                if quantity == 0 {
-                    // When the total quantity is 0, we have sold all the shares, which mean we need
-                    // to reset the average_purchase_price back to 0.
-                    average_purchase_price = 0.0;
+    /// Replays the trades and corporate events from `from` (inclusive) until `date` starting with
+    /// the `position` held at `from`. It fails with [`Oversold`] on a day selling more shares than
+    /// held.
+    fn replay_between(
+        &self,
+        portfolio: &Portfolio,
+        mut position: Position,
+        from: OffsetDateTime,
+        date: OffsetDateTime,
+        mut on_realization: impl FnMut(Realization),
+    ) -> Result<Position> {
+        let transferred =
+            |from: &str, datetime| portfolio.transferred(from, &self.symbol, datetime);
+
+        let mut trades = self
+            .trades
+            .iter()
+            .skip_while(|trade| trade.datetime < from)
+            .peekable();
+        let mut events = self
+            .events
+            .iter()
+            .skip_while(|event| event.datetime < from)
+            .peekable();
+
+        // We assume that the trades and the events are sorted by date. An event only affects the
+        // trades before it, so it goes first when both happen at the same time.
+        loop {
+            let next_trade = trades.peek().filter(|trade| trade.datetime < date);
+            let next_event = events.peek().filter(|event| event.datetime < date);
+
+            let realizations = match (next_trade, next_event) {
+                (Some(trade), Some(event)) if event.datetime <= trade.datetime => position
+                    .apply(events.next().expect("BUG: Event should exist"), transferred)?
+                    .into_iter()
+                    .collect(),
+                (Some(trade), next_event) => {
+                    // The trades of the same day are processed together to detect day trades.
+                    let day = trade.datetime.date();
+                    let until = next_event.map_or(date, |event| event.datetime.min(date));
+
+                    let mut day_trades = vec![];
+                    while let Some(trade) = trades
+                        .next_if(|trade| trade.datetime.date() == day && trade.datetime < until)
+                    {
+                        day_trades.push(trade);
+                    }
+
+                    position.trade_day(&day_trades).with_context(|| Oversold {
+                        symbol: self.symbol.clone(),
+                        date: day,
+                    })?
                 }
+                (None, Some(_)) => position
+                    .apply(events.next().expect("BUG: Event should exist"), transferred)?
+                    .into_iter()
+                    .collect(),
+                (None, None) => break,
+            };
+
+            for realization in realizations {
+                on_realization(realization);
             }
         }
 
-        average_purchase_price
+        Ok(position)
     }
 
-    fn buy(&mut self, quantity: u32, price: f64, datetime: OffsetDateTime) {
+    fn buy(
+        &mut self,
+        quantity: u32,
+        price: Decimal,
+        fees: Fees,
+        details: TradeDetails,
+        datetime: OffsetDateTime,
+    ) {
         let trade = Trade {
             quantity,
             price,
             datetime,
             kind: TradeKind::Buy,
-            splits: vec![],
+            legacy_splits: vec![],
+            fees,
+            withheld_tax: None,
+            lot: None,
+            fx_rate: details.fx_rate,
         };
 
         self.add_trade(trade);
     }
 
-    fn sell(&mut self, quantity: u32, price: f64, datetime: OffsetDateTime) -> Result<f64> {
-        ensure!(
-            quantity <= self.quantity(datetime),
-            "Not enough shares to sell"
-        );
-
+    fn sell(
+        &mut self,
+        quantity: u32,
+        price: Decimal,
+        fees: Fees,
+        details: TradeDetails,
+        datetime: OffsetDateTime,
+    ) {
         let trade = Trade {
             quantity,
             price,
             datetime,
             kind: TradeKind::Sell,
-            splits: vec![],
+            legacy_splits: vec![],
+            fees,
+            withheld_tax: details.withheld_tax,
+            lot: details.lot,
+            fx_rate: details.fx_rate,
         };
 
-        let profit = self.calculate_profit(&trade);
-
         self.add_trade(trade);
-
-        Ok(profit)
     }
 
-    fn calculate_profit(&self, trade: &Trade) -> f64 {
-        let average_purchase_price = self.average_purchase_price(trade.datetime);
+    fn update_profit_by_month(
+        &self,
+        portfolio: &Portfolio,
+        profit_by_month: &mut [MonthSummary; 12],
+        year: i32,
+    ) -> Result<()> {
+        self.replay(
+            portfolio,
+            PrimitiveDateTime::MAX.assume_utc(),
+            |realization| {
+                if realization.datetime.year() != year {
+                    return;
+                }
+
+                let summary = &mut profit_by_month[realization.datetime.month() as usize - 1];
+
+                match realization.regime {
+                    TradeRegime::SwingTrade => {
+                        summary.sold_amount += realization.sold_amount;
+                        summary.profit += realization.profit;
+                        summary.withheld_tax += realization.withheld_tax;
+                    }
+                    TradeRegime::DayTrade => {
+                        summary.day_trade_sold_amount += realization.sold_amount;
+                        summary.day_trade_profit += realization.profit;
+                        summary.day_trade_withheld_tax += realization.withheld_tax;
+                    }
+                }
+            },
+        )?;
 
-        (trade.price - average_purchase_price) * f64::from(trade.quantity)
+        Ok(())
     }
 
-    fn update_profit_by_month(&self, profit_by_month: &mut [MonthSummary; 12], year: i32) {
-        for trade in &self.trades {
-            if trade.kind != TradeKind::Sell {
-                continue;
-            }
+    fn add_income(&mut self, income: Income) {
+        self.incomes.push(income);
+        self.incomes.sort_by_key(|income| income.payment_date);
+    }
 
-            if trade.datetime.year() != year {
-                continue;
-            }
+    pub fn incomes_in(&self, year: i32) -> impl Iterator<Item = &Income> {
+        self.incomes
+            .iter()
+            .filter(move |income| income.payment_date.year() == year)
+    }
 
-            let month = trade.datetime.month() as usize - 1;
+    /// The net income paid in the year before the given date (inclusive), it's the basis for the
+    /// yield on cost.
+    pub fn trailing_income(&self, date: OffsetDateTime) -> Decimal {
+        let to = date.date();
+        let from = to
+            .replace_year(to.year() - 1)
+            .unwrap_or(to - Duration::days(365));
 
-            profit_by_month[month].sold_amount += trade.price * f64::from(trade.quantity);
-            profit_by_month[month].profit += self.calculate_profit(trade);
-        }
+        self.incomes
+            .iter()
+            .filter(|income| income.payment_date > from && income.payment_date <= to)
+            .map(Income::net_amount)
+            .sum()
     }
 
     fn add_trade(&mut self, trade: Trade) {
@@ -264,29 +1135,485 @@ impl Stock {
 
         // We ensure that the trades are sorted by date so that we can iterate over all the trades
         // in chronological order.
-        self.trades.sort_by(|a, b| a.datetime.cmp(&b.datetime));
+        self.trades.sort_by_key(|trade| trade.datetime);
     }
 }
 
 impl Trade {
-    fn quantity(&self, datetime: OffsetDateTime) -> u32 {
-        let split_ratio = self
-            .splits
-            .iter()
-            .filter(|split| split.datetime < datetime)
-            .fold(1.0, |acc, split| acc * split.ratio);
+    /// The traded amount, without the fees.
+    fn amount(&self) -> Decimal {
+        round_money(self.price * Decimal::from(self.quantity))
+    }
+
+    /// The total cost of a purchase, including the fees. It's not affected by splits.
+    fn cost(&self) -> Decimal {
+        self.amount() + self.fees.total()
+    }
+
+    /// The net amount received from a sale, after the fees.
+    fn proceeds(&self) -> Decimal {
+        self.amount() - self.fees.total()
+    }
+
+    /// The income tax withheld at source over a sale when it's a swing trade.
+    fn withheld(&self) -> Decimal {
+        self.withheld_tax
+            .unwrap_or_else(|| round_money(self.amount() * SWING_TRADE_WITHHOLDING_RATE))
+    }
+}
 
-        (self.quantity as f64 * split_ratio) as u32
+impl Position {
+    pub fn average_price(&self) -> Decimal {
+        average_price(self.cost, Decimal::from(self.quantity))
     }
 
-    fn price(&self, datetime: OffsetDateTime) -> f64 {
-        let split_ratio = self
-            .splits
+    /// Processes the trades of a single day. When shares are bought and sold in the same day, the
+    /// matching quantity is a day trade, which has its own average prices and doesn't affect the
+    /// average price of the position.
+    fn trade_day(&mut self, trades: &[&Trade]) -> Result<Vec<Realization>> {
+        let (buys, sells): (Vec<&Trade>, Vec<&Trade>) = trades
+            .iter()
+            .partition(|trade| trade.kind == TradeKind::Buy);
+
+        let bought: u32 = buys.iter().map(|trade| trade.quantity).sum();
+        let sold: u32 = sells.iter().map(|trade| trade.quantity).sum();
+        let day_trade_quantity = bought.min(sold);
+
+        if day_trade_quantity == 0 {
+            return trades
+                .iter()
+                .filter_map(|trade| self.trade(trade).transpose())
+                .collect();
+        }
+
+        // The amounts of the day trade are prorated from the totals of the day, the remaining is
+        // left to the swing trades so nothing is lost to rounding.
+        let cost: Decimal = buys.iter().map(|trade| trade.cost()).sum();
+        let sold_amount: Decimal = sells.iter().map(|trade| trade.amount()).sum();
+        let proceeds: Decimal = sells.iter().map(|trade| trade.proceeds()).sum();
+        let withheld_tax: Decimal = sells.iter().map(|trade| trade.withheld()).sum();
+        let datetime = sells
             .iter()
-            .filter(|split| split.datetime < datetime)
-            .fold(1.0, |acc, split| acc * split.ratio);
+            .map(|trade| trade.datetime)
+            .max()
+            .expect("BUG: There should be sales");
+
+        let (quantity, bought, sold) = (
+            Decimal::from(day_trade_quantity),
+            Decimal::from(bought),
+            Decimal::from(sold),
+        );
+        let day_trade_cost = prorate(cost, quantity, bought);
+        let day_trade_sold_amount = prorate(sold_amount, quantity, sold);
+        let day_trade_proceeds = prorate(proceeds, quantity, sold);
+
+        let profit = day_trade_proceeds - day_trade_cost;
+
+        // The tax recorded in the sales is what was actually withheld, it's prorated as the
+        // amounts. It's only estimated when a sale lacks it.
+        let recorded_tax: Option<Decimal> = sells.iter().map(|trade| trade.withheld_tax).sum();
+        let day_trade_withheld_tax = match recorded_tax {
+            Some(tax) => prorate(tax, quantity, sold),
+            None => round_money(profit.max(Decimal::ZERO) * DAY_TRADE_WITHHOLDING_RATE),
+        };
+
+        let mut realizations = vec![Realization {
+            datetime,
+            regime: TradeRegime::DayTrade,
+            sold_amount: day_trade_sold_amount,
+            profit,
+            withheld_tax: day_trade_withheld_tax,
+        }];
+
+        // The remaining shares are swing trades.
+        if bought > quantity {
+            self.quantity += (bought - quantity).to_u32().expect("BUG: Should be a u32");
+            self.cost += cost - day_trade_cost;
+        }
+
+        if sold > quantity {
+            realizations.push(self.sell(
+                (sold - quantity).to_u32().expect("BUG: Should be a u32"),
+                sold_amount - day_trade_sold_amount,
+                proceeds - day_trade_proceeds,
+                withheld_tax - prorate(withheld_tax, quantity, sold),
+                datetime,
+            )?);
+        }
+
+        Ok(realizations)
+    }
+
+    fn trade(&mut self, trade: &Trade) -> Result<Option<Realization>> {
+        match trade.kind {
+            TradeKind::Buy => {
+                self.quantity += trade.quantity;
+                self.cost += trade.cost();
+
+                Ok(None)
+            }
+            TradeKind::Sell => self
+                .sell(
+                    trade.quantity,
+                    trade.amount(),
+                    trade.proceeds(),
+                    trade.withheld(),
+                    trade.datetime,
+                )
+                .map(Some),
+        }
+    }
+
+    /// Sells `quantity` shares at the average price as a swing trade, it fails when selling more
+    /// shares than held.
+    fn sell(
+        &mut self,
+        quantity: u32,
+        sold_amount: Decimal,
+        proceeds: Decimal,
+        withheld_tax: Decimal,
+        datetime: OffsetDateTime,
+    ) -> Result<Realization> {
+        let remaining = self
+            .quantity
+            .checked_sub(quantity)
+            .with_context(|| format!("Not enough shares to sell on {}", datetime.date()))?;
+
+        // The cost is prorated from the total cost instead of using the rounded average price, so
+        // selling all the shares in parts costs the same as selling them at once.
+        let cost = prorate(
+            self.cost,
+            Decimal::from(quantity),
+            Decimal::from(self.quantity),
+        );
+
+        self.quantity = remaining;
+        self.cost -= cost;
+
+        Ok(Realization {
+            datetime,
+            regime: TradeRegime::SwingTrade,
+            sold_amount,
+            profit: proceeds - cost,
+            withheld_tax,
+        })
+    }
+
+    /// Applies the corporate event, `transferred` gives the shares and their cost received `from`
+    /// a stock at a given moment.
+    fn apply(
+        &mut self,
+        event: &CorporateEvent,
+        transferred: impl FnOnce(&str, OffsetDateTime) -> Result<(Decimal, Decimal)>,
+    ) -> Result<Option<Realization>> {
+        let quantity = Decimal::from(self.quantity);
+        let shares = |ratio: &Decimal| {
+            quantity
+                .checked_mul(*ratio)
+                .with_context(|| too_many_shares(event.datetime))
+        };
+
+        match &event.kind {
+            CorporateEventKind::Split {
+                ratio,
+                fraction_price,
+            } => {
+                let shares = shares(ratio)?;
+                let cost = self.take();
+                self.receive(shares, cost, *fraction_price, event.datetime)
+            }
+            CorporateEventKind::ReverseSplit {
+                ratio,
+                fraction_price,
+            } => {
+                let shares = quantity
+                    .checked_div(*ratio)
+                    .with_context(|| too_many_shares(event.datetime))?;
+                let cost = self.take();
+                self.receive(shares, cost, *fraction_price, event.datetime)
+            }
+            CorporateEventKind::Bonus {
+                ratio,
+                unit_cost,
+                fraction_price,
+            } => {
+                let shares = shares(ratio)?;
+                let cost = shares
+                    .checked_mul(*unit_cost)
+                    .map(round_money)
+                    .with_context(|| too_many_shares(event.datetime))?;
+                self.receive(shares, cost, *fraction_price, event.datetime)
+            }
+            CorporateEventKind::Rename { .. } => Ok(None),
+            CorporateEventKind::Merger { .. } => {
+                // The shares (and their cost) are carried over to the incorporating company.
+                self.take();
+                Ok(None)
+            }
+            CorporateEventKind::SpinOff {
+                cost_percentage, ..
+            } => {
+                self.cost -= prorate(self.cost, *cost_percentage, Decimal::ONE_HUNDRED);
+                Ok(None)
+            }
+            CorporateEventKind::Transfer {
+                from,
+                fraction_price,
+            } => {
+                let (shares, cost) = transferred(from, event.datetime)?;
+                self.receive(shares, cost, *fraction_price, event.datetime)
+            }
+        }
+    }
+
+    /// Removes all the shares, returning their cost.
+    fn take(&mut self) -> Decimal {
+        self.quantity = 0;
+        std::mem::take(&mut self.cost)
+    }
+
+    /// Adds the `shares` that cost `cost` in total, the fraction of a share is sold in auction when
+    /// its price is known, otherwise its cost remains in the average price.
+    fn receive(
+        &mut self,
+        shares: Decimal,
+        cost: Decimal,
+        fraction_price: Option<Decimal>,
+        datetime: OffsetDateTime,
+    ) -> Result<Option<Realization>> {
+        let (whole_shares, fraction) = whole_and_fraction(shares);
+
+        self.quantity = whole_shares
+            .to_u32()
+            .and_then(|whole_shares| self.quantity.checked_add(whole_shares))
+            .with_context(|| too_many_shares(datetime))?;
+
+        let Some(fraction_price) = fraction_price.filter(|_| fraction > Decimal::ZERO) else {
+            self.cost += cost;
+            return Ok(None);
+        };
+
+        let fraction_cost = prorate(cost, fraction, shares);
+        let sold_amount = round_money(fraction * fraction_price);
+        self.cost += cost - fraction_cost;
+
+        Ok(Some(Realization {
+            datetime,
+            regime: TradeRegime::SwingTrade,
+            sold_amount,
+            profit: sold_amount - fraction_cost,
+            withheld_tax: Decimal::ZERO,
+        }))
+    }
+}
+
+impl CorporateEventKind {
+    /// The exact number of shares received in the event when holding `quantity` shares, the
+    /// resulting shares for splits and the new shares for bonuses. The transfers don't depend on
+    /// the shares held.
+    fn shares(&self, quantity: u32) -> Option<Decimal> {
+        let quantity = Decimal::from(quantity);
+
+        match self {
+            CorporateEventKind::Split { ratio, .. } => quantity.checked_mul(*ratio),
+            CorporateEventKind::ReverseSplit { ratio, .. } => quantity.checked_div(*ratio),
+            CorporateEventKind::Bonus { ratio, .. } => quantity.checked_mul(*ratio),
+            CorporateEventKind::Rename { .. }
+            | CorporateEventKind::Merger { .. }
+            | CorporateEventKind::SpinOff { .. }
+            | CorporateEventKind::Transfer { .. } => None,
+        }
+    }
+
+    /// The fraction of a share left over by the event when holding `quantity` shares.
+    fn fraction(&self, quantity: u32) -> Decimal {
+        self.shares(quantity)
+            .map_or(Decimal::ZERO, |shares| whole_and_fraction(shares).1)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CorporateEventKind::Split { .. } => "Split",
+            CorporateEventKind::ReverseSplit { .. } => "ReverseSplit",
+            CorporateEventKind::Bonus { .. } => "Bonus",
+            CorporateEventKind::Rename { .. } => "Rename",
+            CorporateEventKind::Merger { .. } => "Merger",
+            CorporateEventKind::SpinOff { .. } => "SpinOff",
+            CorporateEventKind::Transfer { .. } => "Transfer",
+        }
+    }
+
+    fn upgrade_float_amounts(&mut self) {
+        match self {
+            CorporateEventKind::Split {
+                ratio,
+                fraction_price,
+            }
+            | CorporateEventKind::ReverseSplit {
+                ratio,
+                fraction_price,
+            } => {
+                *ratio = from_legacy_float(*ratio);
+                *fraction_price = fraction_price.map(from_legacy_float);
+            }
+            CorporateEventKind::Bonus {
+                ratio,
+                unit_cost,
+                fraction_price,
+            } => {
+                *ratio = from_legacy_float(*ratio);
+                *unit_cost = from_legacy_float(*unit_cost);
+                *fraction_price = fraction_price.map(from_legacy_float);
+            }
+            CorporateEventKind::Rename { .. } => {}
+            CorporateEventKind::Merger { ratio, .. } => *ratio = from_legacy_float(*ratio),
+            CorporateEventKind::SpinOff {
+                ratio,
+                cost_percentage,
+                ..
+            } => {
+                *ratio = from_legacy_float(*ratio);
+                *cost_percentage = from_legacy_float(*cost_percentage);
+            }
+            CorporateEventKind::Transfer { fraction_price, .. } => {
+                *fraction_price = fraction_price.map(from_legacy_float);
+            }
+        }
+    }
+
+    /// Points the mergers, spin-offs and transfers that refer to the `from` stock at its new
+    /// symbol.
+    fn rename_reference(&mut self, from: &str, to: &str) {
+        let symbol = match self {
+            CorporateEventKind::Merger { into, .. } | CorporateEventKind::SpinOff { into, .. } => {
+                into
+            }
+            CorporateEventKind::Transfer { from, .. } => from,
+            CorporateEventKind::Split { .. }
+            | CorporateEventKind::ReverseSplit { .. }
+            | CorporateEventKind::Bonus { .. }
+            | CorporateEventKind::Rename { .. } => return,
+        };
+
+        if symbol == from {
+            *symbol = to.to_string();
+        }
+    }
+}
+
+/// Splits the shares into the whole shares and the fraction of a share, after rounding them to
+/// [`SHARE_DECIMALS`].
+fn whole_and_fraction(shares: Decimal) -> (Decimal, Decimal) {
+    let shares = shares.round_dp(SHARE_DECIMALS);
+    let whole = shares.floor();
+
+    (whole, shares - whole)
+}
+
+/// The error of the corporate events whose ratio leaves more shares than supported.
+fn too_many_shares(datetime: OffsetDateTime) -> String {
+    format!(
+        "The corporate event on {} leaves more shares than supported, check its ratio",
+        datetime.date()
+    )
+}
+
+impl Income {
+    pub fn net_amount(&self) -> Decimal {
+        self.amount - self.withheld_tax
+    }
+}
 
-        self.price / split_ratio
+impl IncomeSummary {
+    fn add(&mut self, income: &Income) {
+        match income.kind {
+            IncomeKind::Dividend => self.dividends += income.amount,
+            IncomeKind::Jcp => self.jcp += income.amount,
+        }
+        self.withheld_tax += income.withheld_tax;
+    }
+
+    pub fn net_amount(&self) -> Decimal {
+        self.dividends + self.jcp - self.withheld_tax
+    }
+}
+
+impl Fees {
+    pub fn total(&self) -> Decimal {
+        self.brokerage + self.emoluments + self.settlement + self.iss
+    }
+
+    /// Rounds each fee to cents, as they are charged.
+    pub fn round(&mut self) {
+        self.brokerage = round_money(self.brokerage);
+        self.emoluments = round_money(self.emoluments);
+        self.settlement = round_money(self.settlement);
+        self.iss = round_money(self.iss);
+    }
+}
+
+impl FeeSchedule {
+    /// Calculates the fees for a trade of the given `amount`.
+    pub fn fees(&self, amount: Decimal) -> Fees {
+        Fees {
+            brokerage: self.brokerage,
+            emoluments: prorate(amount, self.emoluments_rate, Decimal::ONE_HUNDRED),
+            settlement: prorate(amount, self.settlement_rate, Decimal::ONE_HUNDRED),
+            iss: prorate(self.brokerage, self.iss_rate, Decimal::ONE_HUNDRED),
+        }
+    }
+}
+
+impl AssetClass {
+    /// Infers the asset class from the number at the end of the ticker. The tickers ending in 11
+    /// might be units, ETFs or FIIs, so their class isn't guessed.
+    pub fn infer(symbol: &str) -> Option<Self> {
+        let suffix = symbol.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+
+        match suffix {
+            "11" => None,
+            "32" | "33" | "34" | "35" | "39" => Some(AssetClass::Bdr),
+            _ => Some(AssetClass::Stock),
+        }
+    }
+}
+
+impl Display for Oversold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "There are more {} shares sold than held on {}",
+            self.symbol, self.date
+        )
+    }
+}
+
+impl Display for AssetClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                AssetClass::Stock => "stock",
+                AssetClass::Unit => "unit",
+                AssetClass::Etf => "ETF",
+                AssetClass::Bdr => "BDR",
+                AssetClass::Fii => "FII",
+            }
+        )
+    }
+}
+
+impl Display for IncomeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                IncomeKind::Dividend => "dividend",
+                IncomeKind::Jcp => "JCP",
+            }
+        )
     }
 }
 
@@ -302,3 +1629,364 @@ impl Display for TradeKind {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::{date, datetime};
+
+    fn portfolio_with(symbol: &str, quantity: u32, price: Decimal) -> Portfolio {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+        portfolio.buy(
+            symbol,
+            quantity,
+            price,
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-01-02 10:00 UTC),
+        );
+        portfolio
+    }
+
+    fn trade(kind: TradeKind, quantity: u32, price: Decimal, datetime: OffsetDateTime) -> Trade {
+        Trade {
+            quantity,
+            price,
+            datetime,
+            kind,
+            legacy_splits: vec![],
+            fees: Fees::default(),
+            withheld_tax: None,
+            lot: None,
+            fx_rate: None,
+        }
+    }
+
+    #[test]
+    fn reverse_split_sells_the_fraction_in_auction() {
+        let mut portfolio = portfolio_with("ITSA4", 105, dec!(10));
+
+        let fraction = portfolio
+            .reverse_split(
+                "ITSA4",
+                dec!(10),
+                Some(dec!(120)),
+                datetime!(2024-02-01 10:00 UTC),
+            )
+            .expect("The reverse split should be valid");
+
+        assert_eq!(fraction, dec!(0.5));
+        let position = portfolio.stocks["ITSA4"]
+            .position(&portfolio, datetime!(2024-03-01 00:00 UTC))
+            .expect("The position should be valid");
+        assert_eq!(position.quantity, 10);
+        assert_eq!(position.cost, dec!(1000));
+
+        let profit_by_month = portfolio
+            .profit_by_month(2024)
+            .expect("The profits should be valid");
+        let february = &profit_by_month[&AssetClass::Stock][1];
+        assert_eq!(february.sold_amount, dec!(60));
+        assert_eq!(february.profit, dec!(10));
+    }
+
+    #[test]
+    fn bonus_adds_the_shares_at_their_unit_cost() {
+        let mut portfolio = portfolio_with("ITSA4", 100, dec!(10));
+
+        portfolio
+            .bonus(
+                "ITSA4",
+                dec!(0.1),
+                dec!(5),
+                None,
+                datetime!(2024-02-01 10:00 UTC),
+            )
+            .expect("The bonus should be valid");
+
+        let position = portfolio.stocks["ITSA4"]
+            .position(&portfolio, datetime!(2024-03-01 00:00 UTC))
+            .expect("The position should be valid");
+        assert_eq!(position.quantity, 110);
+        assert_eq!(position.cost, dec!(1050));
+    }
+
+    #[test]
+    fn split_rejects_a_ratio_leaving_too_many_shares() {
+        let mut portfolio = portfolio_with("ITSA4", 100, dec!(10));
+
+        let split = portfolio.split(
+            "ITSA4",
+            dec!(100000000000),
+            None,
+            datetime!(2024-02-01 10:00 UTC),
+        );
+
+        assert!(split.is_err());
+        assert!(portfolio.stocks["ITSA4"].events.is_empty());
+    }
+
+    #[test]
+    fn whole_and_fraction_rounds_the_shares() {
+        assert_eq!(
+            whole_and_fraction(dec!(100) / dec!(3) * dec!(3)),
+            (dec!(100), Decimal::ZERO)
+        );
+        assert_eq!(whole_and_fraction(dec!(10.25)), (dec!(10), dec!(0.25)));
+    }
+
+    #[test]
+    fn merger_derives_the_transferred_shares_from_the_original_stock() {
+        let mut portfolio = portfolio_with("ITSA4", 100, dec!(10));
+        let after = datetime!(2024-03-01 00:00 UTC);
+
+        portfolio
+            .merge(
+                "ITSA4",
+                "ITUB4",
+                dec!(0.5),
+                None,
+                datetime!(2024-02-01 10:00 UTC),
+            )
+            .expect("The merger should be valid");
+        // A trade recorded later is still carried over.
+        portfolio.buy(
+            "ITSA4",
+            50,
+            dec!(10),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-01-15 10:00 UTC),
+        );
+        portfolio
+            .rename("ITUB4", "ITUB3", datetime!(2024-02-15 10:00 UTC))
+            .expect("The rename should be valid");
+
+        let merged = portfolio.stocks["ITSA4"]
+            .position(&portfolio, after)
+            .expect("The position should be valid");
+        assert_eq!(merged.quantity, 0);
+        let position = portfolio.stocks["ITUB3"]
+            .position(&portfolio, after)
+            .expect("The position should be valid");
+        assert_eq!(position.quantity, 75);
+        assert_eq!(position.cost, dec!(1500));
+    }
+
+    #[test]
+    fn spin_off_transfers_part_of_the_cost() {
+        let mut portfolio = portfolio_with("ITSA4", 100, dec!(10));
+        let after = datetime!(2024-03-01 00:00 UTC);
+
+        portfolio
+            .spin_off(
+                "ITSA4",
+                "NEWC3",
+                dec!(1),
+                dec!(20),
+                None,
+                datetime!(2024-02-01 10:00 UTC),
+            )
+            .expect("The spin-off should be valid");
+
+        let original = portfolio.stocks["ITSA4"]
+            .position(&portfolio, after)
+            .expect("The position should be valid");
+        assert_eq!(original.quantity, 100);
+        assert_eq!(original.cost, dec!(800));
+        let spun_off = portfolio.stocks["NEWC3"]
+            .position(&portfolio, after)
+            .expect("The position should be valid");
+        assert_eq!(spun_off.quantity, 100);
+        assert_eq!(spun_off.cost, dec!(200));
+    }
+
+    #[test]
+    fn trade_day_splits_the_day_trade_from_the_swing_trade() {
+        let mut position = Position {
+            quantity: 100,
+            cost: dec!(1000),
+        };
+        let buy = trade(
+            TradeKind::Buy,
+            50,
+            dec!(12),
+            datetime!(2024-03-04 10:00 UTC),
+        );
+        let sell = trade(
+            TradeKind::Sell,
+            80,
+            dec!(13),
+            datetime!(2024-03-04 15:00 UTC),
+        );
+
+        let realizations = position
+            .trade_day(&[&buy, &sell])
+            .expect("The trades should be valid");
+
+        assert_eq!(realizations.len(), 2);
+        let (day_trade, swing_trade) = (&realizations[0], &realizations[1]);
+        assert!(day_trade.regime == TradeRegime::DayTrade);
+        assert_eq!(day_trade.sold_amount, dec!(650));
+        assert_eq!(day_trade.profit, dec!(50));
+        assert_eq!(day_trade.withheld_tax, dec!(0.50));
+        assert!(swing_trade.regime == TradeRegime::SwingTrade);
+        assert_eq!(swing_trade.sold_amount, dec!(390));
+        assert_eq!(swing_trade.profit, dec!(90));
+        assert_eq!(swing_trade.withheld_tax, dec!(0.02));
+        // The day trade doesn't change the average price.
+        assert_eq!(position.quantity, 70);
+        assert_eq!(position.cost, dec!(700));
+    }
+
+    #[test]
+    fn trade_day_prorates_the_recorded_withheld_tax() {
+        let mut position = Position::default();
+        let buy = trade(
+            TradeKind::Buy,
+            100,
+            dec!(10),
+            datetime!(2024-03-04 10:00 UTC),
+        );
+        let mut sell = trade(
+            TradeKind::Sell,
+            100,
+            dec!(11),
+            datetime!(2024-03-04 15:00 UTC),
+        );
+        sell.withheld_tax = Some(dec!(1.20));
+
+        let realizations = position
+            .trade_day(&[&buy, &sell])
+            .expect("The trades should be valid");
+
+        assert_eq!(realizations.len(), 1);
+        assert!(realizations[0].regime == TradeRegime::DayTrade);
+        assert_eq!(realizations[0].profit, dec!(100));
+        assert_eq!(realizations[0].withheld_tax, dec!(1.20));
+        assert_eq!(position.quantity, 0);
+        assert_eq!(position.cost, Decimal::ZERO);
+    }
+
+    #[test]
+    fn trade_day_without_day_trade_keeps_the_average_price() {
+        let mut position = Position {
+            quantity: 100,
+            cost: dec!(1000),
+        };
+        let sell = trade(
+            TradeKind::Sell,
+            40,
+            dec!(9),
+            datetime!(2024-03-04 15:00 UTC),
+        );
+
+        let realizations = position
+            .trade_day(&[&sell])
+            .expect("The trades should be valid");
+
+        assert_eq!(realizations.len(), 1);
+        assert!(realizations[0].regime == TradeRegime::SwingTrade);
+        assert_eq!(realizations[0].profit, dec!(-40));
+        assert_eq!(position.average_price(), dec!(10));
+    }
+
+    #[test]
+    fn trade_day_fails_selling_more_than_held() {
+        let mut position = Position {
+            quantity: 10,
+            cost: dec!(100),
+        };
+        let buy = trade(TradeKind::Buy, 5, dec!(10), datetime!(2024-03-04 10:00 UTC));
+        let sell = trade(
+            TradeKind::Sell,
+            20,
+            dec!(11),
+            datetime!(2024-03-04 15:00 UTC),
+        );
+
+        assert!(position.trade_day(&[&buy, &sell]).is_err());
+    }
+
+    #[test]
+    fn sell_fails_taking_the_shares_of_a_later_sale() {
+        let mut portfolio = portfolio_with("ITSA4", 100, dec!(10));
+        portfolio
+            .sell(
+                "ITSA4",
+                100,
+                dec!(12),
+                Fees::default(),
+                TradeDetails::default(),
+                datetime!(2024-03-04 10:00 UTC),
+            )
+            .expect("The sale should be valid");
+
+        let backdated = portfolio.sell(
+            "ITSA4",
+            50,
+            dec!(11),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-02-01 10:00 UTC),
+        );
+
+        assert!(backdated.is_err());
+        assert_eq!(portfolio.stocks["ITSA4"].trades.len(), 2);
+    }
+
+    #[test]
+    fn oversold_on_finds_the_first_day_selling_more_than_held() {
+        let mut portfolio = portfolio_with("ITSA4", 100, dec!(10));
+        let stock = portfolio
+            .stocks
+            .get_mut("ITSA4")
+            .expect("The stock should exist");
+        for (quantity, datetime) in [
+            (60, datetime!(2024-02-01 10:00 UTC)),
+            (60, datetime!(2024-03-04 10:00 UTC)),
+            (60, datetime!(2024-04-01 10:00 UTC)),
+        ] {
+            stock.sell(
+                quantity,
+                dec!(12),
+                Fees::default(),
+                TradeDetails::default(),
+                datetime,
+            );
+        }
+
+        let oversold_on = portfolio.stocks["ITSA4"]
+            .oversold_on(&portfolio)
+            .expect("The replay should only fail on the oversell");
+
+        assert_eq!(oversold_on, Some(date!(2024 - 03 - 04)));
+    }
+
+    #[test]
+    fn sell_returns_the_profit_of_the_day_it_turns_into_day_trades() {
+        let mut portfolio = portfolio_with("ITSA4", 100, dec!(10));
+        portfolio.buy(
+            "ITSA4",
+            100,
+            dec!(12),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-03-04 10:00 UTC),
+        );
+
+        let profit = portfolio
+            .sell(
+                "ITSA4",
+                100,
+                dec!(13),
+                Fees::default(),
+                TradeDetails::default(),
+                datetime!(2024-03-04 15:00 UTC),
+            )
+            .expect("The sale should be valid");
+
+        // The shares bought in the day are sold first, as a day trade.
+        assert_eq!(profit, dec!(100));
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::format_description::BorrowedFormatItem;
+use time::macros::format_description;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+/// How many backups are kept, the oldest ones are removed as new ones are taken.
+const MAX_BACKUPS: usize = 20;
+
+/// The backups are named after the UTC time they were taken, so they sort chronologically.
+const BACKUP_ID_FORMAT: &[BorrowedFormatItem<'static>] =
+    format_description!("[year]-[month]-[day]T[hour]-[minute]-[second].[subsecond digits:6]Z");
+
+/// A snapshot of the portfolio, taken right before it was overwritten.
+pub struct Backup {
+    pub id: String,
+    pub path: PathBuf,
+    pub taken_at: OffsetDateTime,
+}
+
+/// Replaces the file at `path` with `contents` without ever leaving it truncated: the contents are
+/// written to a temporary file, flushed to the disk and then renamed over the original. The
+/// previous contents are kept as a backup.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let temporary_path = write_temporary(path, ".tmp", contents)?;
+
+    if path.exists() {
+        take_backup(path)?;
+    }
+
+    replace(&temporary_path, path)
+}
+
+/// Replaces the file at `path` with `contents` as [`write_atomically`], but without a backup. The
+/// temporary file is unique to the process, as the writers might not hold a lock (e.g. of the
+/// price cache).
+pub fn replace_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let suffix = format!(".{}.tmp", std::process::id());
+    let temporary_path = write_temporary(path, &suffix, contents)?;
+
+    replace(&temporary_path, path)
+}
+
+/// Writes the `contents` next to `path`, flushing them to the disk.
+fn write_temporary(path: &Path, suffix: &str, contents: &[u8]) -> Result<PathBuf> {
+    let mut temporary_path = OsString::from(path);
+    temporary_path.push(suffix);
+    let temporary_path = PathBuf::from(temporary_path);
+
+    let mut file = File::create(&temporary_path)
+        .with_context(|| format!("Could not create {temporary_path:?}"))?;
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("Could not write {temporary_path:?}"))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&temporary_path);
+        })?;
+
+    Ok(temporary_path)
+}
+
+fn replace(temporary_path: &Path, path: &Path) -> Result<()> {
+    std::fs::rename(temporary_path, path).with_context(|| format!("Could not replace {path:?}"))?;
+
+    sync_parent(path)
+}
+
+/// The backups of the file at `path`, the most recent first.
+pub fn backups(path: &Path) -> Result<Vec<Backup>> {
+    let directory = backup_directory(path);
+
+    let entries = match std::fs::read_dir(&directory) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Could not read the backups in {directory:?}"))
+        }
+    };
+
+    let mut backups = vec![];
+    for entry in entries {
+        let path = entry?.path();
+
+        // Anything else in the directory was not created by us.
+        let Some(id) = path.file_stem().and_then(|id| id.to_str()) else {
+            continue;
+        };
+        let Ok(taken_at) = PrimitiveDateTime::parse(id, BACKUP_ID_FORMAT) else {
+            continue;
+        };
+
+        backups.push(Backup {
+            id: id.to_string(),
+            taken_at: taken_at.assume_utc(),
+            path,
+        });
+    }
+
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.taken_at));
+
+    Ok(backups)
+}
+
+/// The backups are kept next to the file, e.g. `default.json` is backed up in `default.backups`.
+fn backup_directory(path: &Path) -> PathBuf {
+    path.with_extension("backups")
+}
+
+/// Copies the file at `path` to the backups, keeping its extension so it's read in the same
+/// format.
+pub fn take_backup(path: &Path) -> Result<()> {
+    let directory = backup_directory(path);
+    std::fs::create_dir_all(&directory)
+        .with_context(|| format!("Could not create the backup directory {directory:?}"))?;
+
+    let id = OffsetDateTime::now_utc().format(BACKUP_ID_FORMAT)?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("json");
+    let backup_path = directory.join(format!("{id}.{extension}"));
+    std::fs::copy(path, &backup_path)
+        .with_context(|| format!("Could not back up {path:?} to {backup_path:?}"))?;
+
+    for backup in backups(path)?.iter().skip(MAX_BACKUPS) {
+        std::fs::remove_file(&backup.path)
+            .with_context(|| format!("Could not remove the old backup {:?}", backup.path))?;
+    }
+
+    Ok(())
+}
+
+/// The rename is only durable once the directory entry is flushed as well.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    File::open(parent)
+        .and_then(|directory| directory.sync_all())
+        .with_context(|| format!("Could not sync the directory {parent:?}"))
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::{Fees, Portfolio, TradeDetails};
+    use rust_decimal::dec;
+    use tempfile::TempDir;
+    use time::macros::datetime;
+
+    #[test]
+    fn write_atomically_backs_up_the_previous_contents() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let path = dir.path().join("default.json");
+
+        write_atomically(&path, b"first").expect("The file should be written");
+        assert!(backups(&path)
+            .expect("The backups should be read")
+            .is_empty());
+
+        write_atomically(&path, b"second").expect("The file should be written");
+        std::fs::write(backup_directory(&path).join("notes.txt"), "")
+            .expect("The file should be written");
+
+        let backups = backups(&path).expect("The backups should be read");
+        assert_eq!(backups.len(), 1);
+        assert_eq!(
+            std::fs::read(&backups[0].path).expect("The backup should be read"),
+            b"first"
+        );
+        assert_eq!(
+            std::fs::read(&path).expect("The file should be read"),
+            b"second"
+        );
+    }
+
+    #[test]
+    fn take_backup_removes_the_oldest_backups() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let path = dir.path().join("default.json");
+        std::fs::write(&path, "{}").expect("The file should be written");
+
+        for _ in 0..MAX_BACKUPS + 2 {
+            take_backup(&path).expect("The backup should be taken");
+            // The backups are named after the microsecond they were taken.
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let backups = backups(&path).expect("The backups should be read");
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        assert!(backups[0].taken_at > backups[MAX_BACKUPS - 1].taken_at);
+    }
+
+    #[test]
+    fn restore_rolls_back_and_can_be_undone() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let mut portfolio = Portfolio::new(&dir.path().join("default.json"));
+        portfolio.save().expect("The portfolio should be saved");
+        portfolio.buy(
+            "ITSA4",
+            100,
+            dec!(10),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-01-02 10:00 UTC),
+        );
+        portfolio.save().expect("The portfolio should be saved");
+
+        let empty = portfolio.backups().expect("The backups should be read")[0]
+            .id
+            .clone();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        portfolio.restore(&empty).expect("The backup should exist");
+        assert!(portfolio.stocks.is_empty());
+
+        let portfolio = Portfolio::load(portfolio.path()).expect("The portfolio should load");
+        assert!(portfolio.stocks.is_empty());
+        let backups = portfolio.backups().expect("The backups should be read");
+        assert_eq!(backups.len(), 2);
+        let undone = Portfolio::load(&backups[0].path).expect("The backup should load");
+        assert!(undone.stocks.contains_key("ITSA4"));
+    }
+}
@@ -0,0 +1,227 @@
+use super::{CorporateEventKind, Portfolio};
+use crate::money::round_money;
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use time::Date;
+
+/// The currency of the prices of a stock.
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    #[default]
+    Brl,
+    Usd,
+    Eur,
+    Gbp,
+}
+
+impl Currency {
+    /// The ISO 4217 code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Brl => "BRL",
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+            Currency::Gbp => "GBP",
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Currency::Brl => "R$",
+            Currency::Usd => "US$",
+            Currency::Eur => "€",
+            Currency::Gbp => "£",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        [Currency::Brl, Currency::Usd, Currency::Eur, Currency::Gbp]
+            .into_iter()
+            .find(|currency| currency.code().eq_ignore_ascii_case(code))
+    }
+}
+
+impl Portfolio {
+    /// Converts every amount of the portfolio into the `base` currency, `fx_rate` is the BRL value
+    /// of one unit of a currency at a given date.
+    ///
+    /// The trades are converted with the FX rate stored when they happened, so the cost basis
+    /// doesn't change with the current rates, while the income and the corporate events use the
+    /// rate of their dates. The prices keep their precision, but the amounts are rounded to cents.
+    pub fn convert(
+        &self,
+        base: Currency,
+        mut fx_rate: impl FnMut(Currency, Date) -> Result<Decimal>,
+    ) -> Result<Portfolio> {
+        let mut portfolio = self.clone();
+
+        for stock in portfolio.stocks.values_mut() {
+            let currency = stock.currency;
+            if currency == base {
+                continue;
+            }
+
+            // The amounts are divided by the base rate last, so the exact results aren't lost to a
+            // rounded factor.
+            let mut converter = |date: Date, trade_fx_rate: Option<Decimal>| -> Result<_> {
+                let rate = match trade_fx_rate {
+                    Some(rate) => rate,
+                    None => fx_rate(currency, date)?,
+                };
+                let base_rate = fx_rate(base, date)?;
+
+                Ok(move |amount: Decimal| amount * rate / base_rate)
+            };
+
+            for trade in &mut stock.trades {
+                let convert = converter(trade.datetime.date(), trade.fx_rate)?;
+
+                trade.price = convert(trade.price);
+                trade.fees.brokerage = convert(trade.fees.brokerage);
+                trade.fees.emoluments = convert(trade.fees.emoluments);
+                trade.fees.settlement = convert(trade.fees.settlement);
+                trade.fees.iss = convert(trade.fees.iss);
+                trade.fees.round();
+                trade.withheld_tax = trade.withheld_tax.map(|tax| round_money(convert(tax)));
+                trade.fx_rate = None;
+            }
+
+            for income in &mut stock.incomes {
+                let convert = converter(income.payment_date, None)?;
+
+                income.amount = round_money(convert(income.amount));
+                income.withheld_tax = round_money(convert(income.withheld_tax));
+            }
+
+            for event in &mut stock.events {
+                let convert = converter(event.datetime.date(), None)?;
+
+                match &mut event.kind {
+                    CorporateEventKind::Split { fraction_price, .. }
+                    | CorporateEventKind::ReverseSplit { fraction_price, .. } => {
+                        *fraction_price = fraction_price.map(convert);
+                    }
+                    CorporateEventKind::Bonus {
+                        unit_cost,
+                        fraction_price,
+                        ..
+                    } => {
+                        *unit_cost = convert(*unit_cost);
+                        *fraction_price = fraction_price.map(convert);
+                    }
+                    CorporateEventKind::Transfer { fraction_price, .. } => {
+                        *fraction_price = fraction_price.map(convert);
+                    }
+                    CorporateEventKind::Rename { .. }
+                    | CorporateEventKind::Merger { .. }
+                    | CorporateEventKind::SpinOff { .. } => {}
+                }
+            }
+
+            stock.currency = base;
+        }
+
+        Ok(portfolio)
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::{Fees, Income, IncomeKind, TradeDetails};
+    use rust_decimal::dec;
+    use std::path::Path;
+    use time::macros::{date, datetime};
+
+    fn fx_rate(currency: Currency, _date: Date) -> Result<Decimal> {
+        Ok(match currency {
+            Currency::Usd => dec!(5.2),
+            _ => Decimal::ONE,
+        })
+    }
+
+    fn portfolio() -> Portfolio {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+        portfolio.set_currency("AAPL", Currency::Usd);
+        portfolio.buy(
+            "AAPL",
+            10,
+            dec!(150),
+            Fees {
+                brokerage: dec!(1),
+                ..Fees::default()
+            },
+            TradeDetails {
+                fx_rate: Some(dec!(5.1)),
+                ..TradeDetails::default()
+            },
+            datetime!(2024-01-02 15:00 UTC),
+        );
+        portfolio
+            .add_income(
+                "AAPL",
+                Income {
+                    kind: IncomeKind::Dividend,
+                    amount: dec!(10),
+                    withheld_tax: dec!(3),
+                    ex_date: date!(2024 - 02 - 09),
+                    payment_date: date!(2024 - 02 - 15),
+                },
+            )
+            .expect("The stock should exist");
+        portfolio.buy(
+            "ITSA4",
+            100,
+            dec!(10.40),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-01-02 15:00 UTC),
+        );
+        portfolio
+    }
+
+    #[test]
+    fn convert_uses_the_fx_rate_of_the_trades() {
+        let portfolio = portfolio()
+            .convert(Currency::Brl, fx_rate)
+            .expect("The conversion should be valid");
+
+        let stock = &portfolio.stocks["AAPL"];
+        assert!(stock.currency == Currency::Brl);
+        assert_eq!(stock.trades[0].price, dec!(765));
+        assert_eq!(stock.trades[0].fees.brokerage, dec!(5.10));
+        assert_eq!(stock.trades[0].fx_rate, None);
+        // The income uses the rate of its payment date.
+        assert_eq!(stock.incomes[0].amount, dec!(52));
+        assert_eq!(stock.incomes[0].withheld_tax, dec!(15.60));
+
+        assert_eq!(portfolio.stocks["ITSA4"].trades[0].price, dec!(10.40));
+    }
+
+    #[test]
+    fn convert_into_another_base_keeps_the_stocks_in_it() {
+        let portfolio = portfolio()
+            .convert(Currency::Usd, fx_rate)
+            .expect("The conversion should be valid");
+
+        assert_eq!(portfolio.stocks["AAPL"].trades[0].price, dec!(150));
+        let stock = &portfolio.stocks["ITSA4"];
+        assert!(stock.currency == Currency::Usd);
+        assert_eq!(stock.trades[0].price, dec!(2));
+    }
+
+    #[test]
+    fn from_code_ignores_the_case() {
+        assert!(Currency::from_code("usd") == Some(Currency::Usd));
+        assert!(Currency::from_code("JPY").is_none());
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+/// An advisory lock over a portfolio, it's released when dropped. The processes that only read
+/// the portfolio share the lock, while the ones that change it hold it exclusively from loading to
+/// saving it, so they never overwrite each other's changes.
+///
+/// The lock is taken on a separate file, as the portfolio itself is replaced whenever it's saved.
+pub struct PortfolioLock {
+    _file: File,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+impl PortfolioLock {
+    /// Waits until the portfolio at `path` can be locked.
+    pub fn acquire(path: &Path, mode: LockMode) -> Result<Self> {
+        let file = open_lock_file(path)?;
+
+        match mode {
+            LockMode::Shared => file.lock_shared(),
+            LockMode::Exclusive => file.lock(),
+        }
+        .with_context(|| format!("Could not lock the portfolio {path:?}"))?;
+
+        Ok(Self { _file: file })
+    }
+
+    /// Locks the portfolio at `path` only if no other process holds a conflicting lock.
+    pub fn try_acquire(path: &Path, mode: LockMode) -> Result<Option<Self>> {
+        let file = open_lock_file(path)?;
+
+        let locked = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+
+        match locked {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => {
+                Err(err).with_context(|| format!("Could not lock the portfolio {path:?}"))
+            }
+        }
+    }
+}
+
+fn open_lock_file(path: &Path) -> Result<File> {
+    let mut lock_path = OsString::from(path);
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Could not open the lock file {lock_path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn try_acquire_shares_the_lock_between_readers() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let path = dir.path().join("default.json");
+
+        let reader = PortfolioLock::acquire(&path, LockMode::Shared).expect("The lock should work");
+
+        assert!(PortfolioLock::try_acquire(&path, LockMode::Shared)
+            .expect("The lock should work")
+            .is_some());
+        assert!(PortfolioLock::try_acquire(&path, LockMode::Exclusive)
+            .expect("The lock should work")
+            .is_none());
+
+        drop(reader);
+        assert!(PortfolioLock::try_acquire(&path, LockMode::Exclusive)
+            .expect("The lock should work")
+            .is_some());
+    }
+
+    #[test]
+    fn try_acquire_is_refused_while_writing() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let path = dir.path().join("default.json");
+
+        let writer =
+            PortfolioLock::acquire(&path, LockMode::Exclusive).expect("The lock should work");
+
+        assert!(PortfolioLock::try_acquire(&path, LockMode::Shared)
+            .expect("The lock should work")
+            .is_none());
+
+        drop(writer);
+        assert!(PortfolioLock::try_acquire(&path, LockMode::Shared)
+            .expect("The lock should work")
+            .is_some());
+        // The portfolio itself is left untouched.
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,632 @@
+use super::{
+    whole_and_fraction, CorporateEvent, CorporateEventKind, Portfolio, Stock, Trade, TradeKind,
+};
+use crate::money::{average_price, prorate, round_money};
+use anyhow::{bail, Result};
+use rust_decimal::{dec, Decimal};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use time::macros::format_description;
+use time::{Duration, OffsetDateTime};
+
+/// How the cost of the shares sold is determined.
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Clone, Copy)]
+pub enum CostBasis {
+    /// The weighted average cost, it's the method required by the Brazilian income tax.
+    #[default]
+    Average,
+    /// The oldest shares are sold first.
+    Fifo,
+    /// The newest shares are sold first.
+    Lifo,
+    /// The lot is chosen in each sale, the shares that exceed it and the sales without a lot are
+    /// sold as in FIFO.
+    SpecificLot,
+}
+
+/// Identifies a lot by its acquisition datetime and, as several lots might be acquired at the same
+/// moment (e.g. the trades imported without time), by their order among them. Without the number
+/// it only identifies the lot when it's the only one acquired at that moment.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct LotId {
+    #[serde(with = "time::serde::rfc3339")]
+    pub acquired_at: OffsetDateTime,
+    #[serde(default)]
+    pub number: Option<u32>,
+}
+
+/// Shares acquired together, they keep their own cost and acquisition date.
+#[derive(Clone)]
+pub struct Lot {
+    pub acquired_at: OffsetDateTime,
+    /// The order of the lot among the ones acquired at the same moment, starting at 1.
+    pub number: u32,
+    /// The corporate events (e.g. splits) might leave a fraction of a share in a lot.
+    pub quantity: Decimal,
+    /// The total cost of the shares, including the fees.
+    pub cost: Decimal,
+}
+
+/// The shares of a lot that were sold.
+pub struct RealizedLot {
+    pub acquired_at: OffsetDateTime,
+    pub sold_at: OffsetDateTime,
+    pub quantity: Decimal,
+    pub cost: Decimal,
+    /// The net amount received, after the fees.
+    pub proceeds: Decimal,
+}
+
+/// The lots of a stock at a given moment, along with the ones already sold.
+#[derive(Default)]
+pub struct Lots {
+    pub open: Vec<Lot>,
+    pub realized: Vec<RealizedLot>,
+    /// How many lots were opened at each moment, so the numbers don't change as the lots close.
+    opened: HashMap<OffsetDateTime, u32>,
+}
+
+impl Stock {
+    /// Replays the trades and corporate events before `date` keeping track of each lot. The
+    /// average cost has no lots, so it's treated as FIFO. It fails when a sale chose a lot that
+    /// isn't open or sold more shares than the open lots have.
+    pub fn lots(
+        &self,
+        portfolio: &Portfolio,
+        date: OffsetDateTime,
+        cost_basis: CostBasis,
+    ) -> Result<Lots> {
+        let mut lots = Lots::default();
+        let transferred = |from: &str, datetime| {
+            portfolio.transferred_lots(from, &self.symbol, datetime, cost_basis)
+        };
+
+        let mut trades = self.trades.iter().peekable();
+        let mut events = self.events.iter().peekable();
+
+        // An event only affects the trades before it, so it goes first when both happen at the
+        // same time.
+        loop {
+            let next_trade = trades.peek().filter(|trade| trade.datetime < date);
+            let next_event = events.peek().filter(|event| event.datetime < date);
+
+            match (next_trade, next_event) {
+                (Some(trade), Some(event)) if event.datetime <= trade.datetime => {
+                    lots.apply(events.next().expect("BUG: Event should exist"), transferred)?;
+                }
+                (Some(_), _) => {
+                    lots.trade(trades.next().expect("BUG: Trade should exist"), cost_basis)?
+                }
+                (None, Some(_)) => {
+                    lots.apply(events.next().expect("BUG: Event should exist"), transferred)?
+                }
+                (None, None) => break,
+            }
+        }
+
+        Ok(lots)
+    }
+}
+
+impl Portfolio {
+    /// The lots received by `into` in a merger or spin-off `from` another stock, they are derived
+    /// from the `from` lots right before the event and keep their acquisition date.
+    fn transferred_lots(
+        &self,
+        from: &str,
+        into: &str,
+        datetime: OffsetDateTime,
+        cost_basis: CostBasis,
+    ) -> Result<Vec<Lot>> {
+        let Some((stock, ratio, cost_percentage)) = self.transfer_terms(from, into, datetime)
+        else {
+            return Ok(vec![]);
+        };
+
+        let lots = stock.lots(self, datetime, cost_basis)?;
+
+        Ok(lots
+            .open
+            .into_iter()
+            .map(|lot| Lot {
+                acquired_at: lot.acquired_at,
+                number: lot.number,
+                quantity: lot.quantity * ratio,
+                cost: prorate(lot.cost, cost_percentage, Decimal::ONE_HUNDRED),
+            })
+            .collect())
+    }
+}
+
+impl Lots {
+    pub fn quantity(&self) -> Decimal {
+        self.open.iter().map(|lot| lot.quantity).sum()
+    }
+
+    fn trade(&mut self, trade: &Trade, cost_basis: CostBasis) -> Result<()> {
+        match trade.kind {
+            TradeKind::Buy => {
+                self.open(trade.datetime, Decimal::from(trade.quantity), trade.cost())
+            }
+            TradeKind::Sell => {
+                let sold = Decimal::from(trade.quantity);
+                let mut remaining = sold;
+                let mut proceeds = trade.proceeds();
+                // Only the first lot sold is chosen, the remaining shares are sold as in FIFO.
+                let mut lot = trade.lot;
+
+                while remaining > Decimal::ZERO {
+                    let Some(index) = self.next_lot(cost_basis, lot.take())? else {
+                        bail!(
+                            "Not enough shares in the open lots to sell on {}",
+                            trade.datetime.date()
+                        );
+                    };
+
+                    // The proceeds are prorated over the remaining shares, so they add up.
+                    let quantity = remaining.min(self.open[index].quantity);
+                    let lot_proceeds = prorate(proceeds, quantity, remaining);
+                    self.sell(index, quantity, lot_proceeds, trade.datetime);
+                    remaining -= quantity;
+                    proceeds -= lot_proceeds;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The index of the next lot to be sold, it's `None` when there are no open lots. It fails
+    /// when the chosen `lot` isn't open.
+    fn next_lot(&self, cost_basis: CostBasis, lot: Option<LotId>) -> Result<Option<usize>> {
+        if self.open.is_empty() {
+            return Ok(None);
+        }
+
+        let index = match (cost_basis, lot) {
+            (CostBasis::SpecificLot, Some(lot)) => self.index_of(lot)?,
+            (CostBasis::Lifo, _) => self.open.len() - 1,
+            (CostBasis::Average | CostBasis::Fifo | CostBasis::SpecificLot, _) => 0,
+        };
+
+        Ok(Some(index))
+    }
+
+    /// The index of the open lot identified by `lot`, it fails when there is no such lot or when
+    /// the lot has no number and several open lots were acquired at the same moment.
+    pub fn index_of(&self, lot: LotId) -> Result<usize> {
+        let mut matches = self.open.iter().enumerate().filter(|(_, open)| {
+            open.acquired_at == lot.acquired_at
+                && lot.number.is_none_or(|number| open.number == number)
+        });
+
+        match (matches.next(), matches.next()) {
+            (Some((index, _)), None) => Ok(index),
+            (None, _) => bail!("There is no open lot {lot}"),
+            (Some(_), Some(_)) => bail!(
+                "There are several open lots bought at {}, choose one by its number, e.g. `{}`",
+                format_datetime(lot.acquired_at),
+                LotId {
+                    number: Some(1),
+                    ..lot
+                },
+            ),
+        }
+    }
+
+    /// Opens a new lot, numbered after the ones acquired at the same moment.
+    fn open(&mut self, acquired_at: OffsetDateTime, quantity: Decimal, cost: Decimal) {
+        let number = self.opened.entry(acquired_at).or_default();
+        *number += 1;
+
+        self.open.push(Lot {
+            acquired_at,
+            number: *number,
+            quantity,
+            cost,
+        });
+    }
+
+    fn sell(
+        &mut self,
+        index: usize,
+        quantity: Decimal,
+        proceeds: Decimal,
+        datetime: OffsetDateTime,
+    ) {
+        let lot = &mut self.open[index];
+        let cost = prorate(lot.cost, quantity, lot.quantity);
+
+        lot.quantity -= quantity;
+        lot.cost -= cost;
+
+        self.realized.push(RealizedLot {
+            acquired_at: lot.acquired_at,
+            sold_at: datetime,
+            quantity,
+            cost,
+            proceeds,
+        });
+
+        // The ratios that can't be represented exactly might leave a tiny part of a share behind.
+        if lot.quantity < dec!(0.000001) {
+            self.open.remove(index);
+        }
+    }
+
+    /// Applies the corporate event, `transferred` gives the lots received `from` a stock at a given
+    /// moment.
+    fn apply(
+        &mut self,
+        event: &CorporateEvent,
+        transferred: impl FnOnce(&str, OffsetDateTime) -> Result<Vec<Lot>>,
+    ) -> Result<()> {
+        match &event.kind {
+            CorporateEventKind::Split {
+                ratio,
+                fraction_price,
+            } => {
+                self.open.iter_mut().for_each(|lot| lot.quantity *= ratio);
+                self.settle_fraction(*fraction_price, event.datetime);
+            }
+            CorporateEventKind::ReverseSplit {
+                ratio,
+                fraction_price,
+            } => {
+                self.open.iter_mut().for_each(|lot| lot.quantity /= ratio);
+                self.settle_fraction(*fraction_price, event.datetime);
+            }
+            CorporateEventKind::Bonus {
+                ratio,
+                unit_cost,
+                fraction_price,
+            } => {
+                let shares = self.quantity() * ratio;
+                let cost = round_money(shares * unit_cost);
+                self.receive(shares, cost, *fraction_price, event.datetime);
+            }
+            CorporateEventKind::Rename { .. } => {}
+            CorporateEventKind::Merger { .. } => {
+                // The shares (and their cost) are carried over to the incorporating company.
+                self.open.clear();
+            }
+            CorporateEventKind::SpinOff {
+                cost_percentage, ..
+            } => {
+                for lot in &mut self.open {
+                    lot.cost -= prorate(lot.cost, *cost_percentage, Decimal::ONE_HUNDRED);
+                }
+            }
+            CorporateEventKind::Transfer {
+                from,
+                fraction_price,
+            } => {
+                // The fraction of a share is taken from the received lots, which keep their
+                // acquisition date and are placed among the others by it.
+                let mut received = Lots {
+                    open: transferred(from, event.datetime)?,
+                    ..Lots::default()
+                };
+                received.settle_fraction(*fraction_price, event.datetime);
+
+                // They are numbered after the lots of this stock acquired at the same moment.
+                for mut lot in received.open {
+                    let number = self.opened.entry(lot.acquired_at).or_default();
+                    *number += 1;
+                    lot.number = *number;
+
+                    let index = self
+                        .open
+                        .partition_point(|open| open.acquired_at <= lot.acquired_at);
+                    self.open.insert(index, lot);
+                }
+                self.realized.append(&mut received.realized);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a new lot with the `shares` that cost `cost` in total.
+    fn receive(
+        &mut self,
+        shares: Decimal,
+        cost: Decimal,
+        fraction_price: Option<Decimal>,
+        datetime: OffsetDateTime,
+    ) {
+        if shares <= Decimal::ZERO {
+            return;
+        }
+
+        self.open(datetime, shares, cost);
+        self.settle_fraction(fraction_price, datetime);
+    }
+
+    /// The fraction of a share left over by a corporate event is taken from the newest lot, it's
+    /// sold in auction when its price is known, otherwise its cost remains in the lot.
+    fn settle_fraction(&mut self, fraction_price: Option<Decimal>, datetime: OffsetDateTime) {
+        let (_, fraction) = whole_and_fraction(self.quantity());
+
+        if fraction <= Decimal::ZERO {
+            return;
+        }
+
+        let Some(index) = self.open.len().checked_sub(1) else {
+            return;
+        };
+
+        match fraction_price {
+            Some(price) => self.sell(index, fraction, round_money(fraction * price), datetime),
+            None => {
+                let lot = &mut self.open[index];
+                lot.quantity -= fraction;
+
+                if lot.quantity < dec!(0.000001) {
+                    // The cost goes to the previous lot, as the shares would keep it in the
+                    // average price.
+                    let lot = self.open.remove(index);
+                    if let Some(previous) = self.open.last_mut() {
+                        previous.cost += lot.cost;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Lot {
+    pub fn id(&self) -> LotId {
+        LotId {
+            acquired_at: self.acquired_at,
+            number: Some(self.number),
+        }
+    }
+
+    pub fn average_price(&self) -> Decimal {
+        average_price(self.cost, self.quantity)
+    }
+
+    pub fn holding_period(&self, date: OffsetDateTime) -> Duration {
+        date - self.acquired_at
+    }
+}
+
+impl RealizedLot {
+    pub fn profit(&self) -> Decimal {
+        self.proceeds - self.cost
+    }
+
+    pub fn holding_period(&self) -> Duration {
+        self.sold_at - self.acquired_at
+    }
+}
+
+impl Display for LotId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format_datetime(self.acquired_at))?;
+
+        match self.number {
+            Some(number) => write!(f, "#{number}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for CostBasis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                CostBasis::Average => "average",
+                CostBasis::Fifo => "FIFO",
+                CostBasis::Lifo => "LIFO",
+                CostBasis::SpecificLot => "specific lot",
+            }
+        )
+    }
+}
+
+fn format_datetime(datetime: OffsetDateTime) -> String {
+    datetime
+        .format(format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second]"
+        ))
+        .unwrap_or_else(|_| datetime.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::{Fees, TradeDetails};
+    use std::path::Path;
+    use time::macros::datetime;
+
+    /// Two lots split 2:1 and a sale of the second one.
+    fn portfolio() -> Portfolio {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+        portfolio.buy(
+            "ITSA4",
+            100,
+            dec!(10),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-01-02 10:00 UTC),
+        );
+        portfolio.buy(
+            "ITSA4",
+            100,
+            dec!(20),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-02-01 10:00 UTC),
+        );
+        portfolio
+            .split("ITSA4", dec!(2), None, datetime!(2024-03-01 10:00 UTC))
+            .expect("The split should be valid");
+        portfolio
+            .sell(
+                "ITSA4",
+                50,
+                dec!(15),
+                Fees::default(),
+                TradeDetails {
+                    lot: Some(LotId {
+                        acquired_at: datetime!(2024-02-01 10:00 UTC),
+                        number: None,
+                    }),
+                    ..TradeDetails::default()
+                },
+                datetime!(2024-04-01 10:00 UTC),
+            )
+            .expect("The sale should be valid");
+        portfolio
+    }
+
+    #[test]
+    fn lots_sell_the_chosen_lot() {
+        let portfolio = portfolio();
+        let stock = &portfolio.stocks["ITSA4"];
+
+        let lots = stock
+            .lots(
+                &portfolio,
+                datetime!(2024-05-01 00:00 UTC),
+                CostBasis::SpecificLot,
+            )
+            .expect("The lots should be valid");
+
+        assert_eq!(lots.quantity(), dec!(350));
+        assert_eq!(lots.open[0].quantity, dec!(200));
+        assert_eq!(lots.open[0].cost, dec!(1000));
+        assert_eq!(lots.open[1].quantity, dec!(150));
+        assert_eq!(lots.open[1].cost, dec!(1500));
+        assert_eq!(lots.realized.len(), 1);
+        assert_eq!(
+            lots.realized[0].acquired_at,
+            datetime!(2024-02-01 10:00 UTC)
+        );
+        assert_eq!(lots.realized[0].profit(), dec!(250));
+    }
+
+    #[test]
+    fn lots_sell_the_oldest_lot_first_in_fifo() {
+        let portfolio = portfolio();
+        let stock = &portfolio.stocks["ITSA4"];
+
+        let lots = stock
+            .lots(&portfolio, datetime!(2024-05-01 00:00 UTC), CostBasis::Fifo)
+            .expect("The lots should be valid");
+
+        assert_eq!(lots.open[0].quantity, dec!(150));
+        assert_eq!(lots.open[0].cost, dec!(750));
+        assert_eq!(
+            lots.realized[0].acquired_at,
+            datetime!(2024-01-02 10:00 UTC)
+        );
+        assert_eq!(lots.realized[0].profit(), dec!(500));
+    }
+
+    #[test]
+    fn position_follows_the_split() {
+        let portfolio = portfolio();
+        let stock = &portfolio.stocks["ITSA4"];
+
+        let position = stock
+            .position(&portfolio, datetime!(2024-05-01 00:00 UTC))
+            .expect("The position should be valid");
+
+        assert_eq!(position.quantity, 350);
+        assert_eq!(position.cost, dec!(2625));
+        assert_eq!(position.average_price(), dec!(7.5));
+    }
+
+    #[test]
+    fn sell_stores_the_number_of_the_chosen_lot() {
+        let portfolio = portfolio();
+
+        let sale = &portfolio.stocks["ITSA4"].trades[2];
+        assert!(
+            sale.lot
+                == Some(LotId {
+                    acquired_at: datetime!(2024-02-01 10:00 UTC),
+                    number: Some(1),
+                })
+        );
+    }
+
+    #[test]
+    fn sell_fails_on_an_ambiguous_lot() {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+        let acquired_at = datetime!(2024-03-04 00:00 UTC);
+        for price in [dec!(10), dec!(12)] {
+            portfolio.buy(
+                "ITSA4",
+                100,
+                price,
+                Fees::default(),
+                TradeDetails::default(),
+                acquired_at,
+            );
+        }
+        let mut sell = |number| {
+            portfolio.sell(
+                "ITSA4",
+                50,
+                dec!(15),
+                Fees::default(),
+                TradeDetails {
+                    lot: Some(LotId {
+                        acquired_at,
+                        number,
+                    }),
+                    ..TradeDetails::default()
+                },
+                datetime!(2024-04-01 10:00 UTC),
+            )
+        };
+
+        assert!(sell(None).is_err());
+        sell(Some(2)).expect("The sale should be valid");
+
+        let stock = &portfolio.stocks["ITSA4"];
+        let lots = stock
+            .lots(
+                &portfolio,
+                datetime!(2024-05-01 00:00 UTC),
+                CostBasis::SpecificLot,
+            )
+            .expect("The lots should be valid");
+        assert_eq!(lots.realized[0].cost, dec!(600));
+        assert_eq!(lots.open[1].number, 2);
+        assert_eq!(lots.open[1].quantity, dec!(50));
+    }
+
+    #[test]
+    fn lots_fail_selling_more_than_the_open_lots() {
+        let portfolio = Portfolio::new(Path::new("portfolio.json"));
+        let mut stock = Stock::new("ITSA4".to_string());
+        stock.buy(
+            10,
+            dec!(10),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-01-02 10:00 UTC),
+        );
+        stock.sell(
+            20,
+            dec!(12),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-02-01 10:00 UTC),
+        );
+
+        for cost_basis in [CostBasis::Fifo, CostBasis::Lifo] {
+            assert!(stock
+                .lots(&portfolio, datetime!(2024-03-01 00:00 UTC), cost_basis)
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use super::{CorporateEvent, CorporateEventKind, Portfolio};
+use crate::money::{from_legacy_float, round_money};
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+/// The version of the portfolio's schema written by this version of the program. The portfolios
+/// without a version were written before it was introduced, they are version 0.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Upgrades a portfolio from the previous schema version, returning how many items it changed.
+struct Migration {
+    /// The version the portfolio is in after the migration.
+    version: u32,
+    description: &'static str,
+    migrate: fn(&mut Portfolio) -> usize,
+}
+
+/// The migrations in the order they are applied, each new schema version appends one.
+const MIGRATIONS: [Migration; 2] = [
+    Migration {
+        version: 1,
+        description: "Move the splits stored in each trade to the stock's corporate events",
+        migrate: move_legacy_splits,
+    },
+    Migration {
+        version: 2,
+        description: "Round the amounts stored as floats, dropping their floating point errors",
+        migrate: round_float_amounts,
+    },
+];
+
+/// The migrations applied to a portfolio.
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub applied: Vec<AppliedMigration>,
+}
+
+pub struct AppliedMigration {
+    pub version: u32,
+    pub description: &'static str,
+    /// How many trades, incomes, events and fee schedules were changed.
+    pub changes: usize,
+}
+
+/// Only the version of a portfolio, it's read before the rest because a newer portfolio might not
+/// be readable at all.
+#[derive(Deserialize)]
+pub struct SchemaHeader {
+    #[serde(default)]
+    pub version: u32,
+}
+
+/// Refuses the portfolios saved by a newer version of the program, they would be misread.
+pub fn ensure_supported(version: u32) -> Result<()> {
+    ensure!(
+        version <= SCHEMA_VERSION,
+        "The portfolio uses the schema version {version}, but this version of stocks only supports \
+        up to the version {SCHEMA_VERSION}, please update it"
+    );
+
+    Ok(())
+}
+
+impl MigrationReport {
+    pub fn is_up_to_date(&self) -> bool {
+        self.applied.is_empty()
+    }
+}
+
+/// Upgrades the `portfolio` to the current schema version.
+pub fn migrate(portfolio: &mut Portfolio) -> MigrationReport {
+    let from_version = portfolio.version;
+
+    let applied = MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > from_version)
+        .map(|migration| {
+            let changes = (migration.migrate)(portfolio);
+            portfolio.version = migration.version;
+
+            AppliedMigration {
+                version: migration.version,
+                description: migration.description,
+                changes,
+            }
+        })
+        .collect();
+
+    MigrationReport {
+        from_version,
+        applied,
+    }
+}
+
+/// Older portfolios stored the splits in each trade, they are moved to the stock's corporate
+/// events.
+fn move_legacy_splits(portfolio: &mut Portfolio) -> usize {
+    let mut changes = 0;
+
+    for stock in portfolio.stocks.values_mut() {
+        for trade in &mut stock.trades {
+            for split in trade.legacy_splits.drain(..) {
+                // Every trade before the split has its own copy of it.
+                if stock
+                    .events
+                    .iter()
+                    .any(|event| event.datetime == split.datetime)
+                {
+                    continue;
+                }
+
+                stock.events.push(CorporateEvent {
+                    datetime: split.datetime,
+                    kind: CorporateEventKind::Split {
+                        ratio: split.ratio,
+                        fraction_price: None,
+                    },
+                });
+                changes += 1;
+            }
+        }
+
+        stock.events.sort_by_key(|event| event.datetime);
+    }
+
+    changes
+}
+
+/// Older portfolios stored the amounts as floats, which are read with their floating point
+/// errors. The amounts of money are rounded to cents and the prices and ratios drop the errors.
+fn round_float_amounts(portfolio: &mut Portfolio) -> usize {
+    let mut changes = 0;
+
+    for stock in portfolio.stocks.values_mut() {
+        for trade in &mut stock.trades {
+            changes += usize::from(changed(trade, |trade| {
+                trade.price = from_legacy_float(trade.price);
+                trade.fees.round();
+                trade.withheld_tax = trade.withheld_tax.map(round_money);
+                trade.fx_rate = trade.fx_rate.map(from_legacy_float);
+            }));
+        }
+
+        for income in &mut stock.incomes {
+            changes += usize::from(changed(income, |income| {
+                income.amount = round_money(income.amount);
+                income.withheld_tax = round_money(income.withheld_tax);
+            }));
+        }
+
+        for event in &mut stock.events {
+            changes += usize::from(changed(&mut event.kind, |kind| {
+                kind.upgrade_float_amounts();
+            }));
+        }
+    }
+
+    for schedule in portfolio.brokers.values_mut() {
+        changes += usize::from(changed(schedule, |schedule| {
+            schedule.brokerage = round_money(schedule.brokerage);
+            schedule.emoluments_rate = from_legacy_float(schedule.emoluments_rate);
+            schedule.settlement_rate = from_legacy_float(schedule.settlement_rate);
+            schedule.iss_rate = from_legacy_float(schedule.iss_rate);
+        }));
+    }
+
+    changes
+}
+
+/// Applies the `upgrade` to the `item`, returns whether it changed how the item is saved.
+fn changed<T: Serialize>(item: &mut T, upgrade: impl FnOnce(&mut T)) -> bool {
+    let before = serde_json::to_value(&*item).ok();
+    upgrade(item);
+    serde_json::to_value(&*item).ok() != before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use std::path::Path;
+
+    #[test]
+    fn migrate_upgrades_an_unversioned_portfolio() {
+        let mut portfolio: Portfolio = serde_json::from_str(
+            r#"{"stocks": {"ITSA4": {"symbol": "ITSA4", "trades": [
+                {"quantity": 100, "price": 0.30000000000000004, "datetime": "2020-01-02T10:00:00Z",
+                 "kind": "Buy", "splits": [{"ratio": 2.0, "datetime": "2021-01-04T10:00:00Z"}]},
+                {"quantity": 50, "price": 10.0, "datetime": "2020-06-01T10:00:00Z",
+                 "kind": "Buy", "splits": [{"ratio": 2.0, "datetime": "2021-01-04T10:00:00Z"}]}
+            ]}}}"#,
+        )
+        .expect("The portfolio should be valid");
+
+        let report = migrate(&mut portfolio);
+
+        assert_eq!(report.from_version, 0);
+        let versions: Vec<u32> = report.applied.iter().map(|m| m.version).collect();
+        assert_eq!(versions, [1, 2]);
+        // Both trades have a copy of the same split.
+        assert_eq!(report.applied[0].changes, 1);
+        assert_eq!(portfolio.version, SCHEMA_VERSION);
+
+        let stock = &portfolio.stocks["ITSA4"];
+        assert_eq!(stock.events.len(), 1);
+        assert!(matches!(
+            stock.events[0].kind,
+            CorporateEventKind::Split { ratio, .. } if ratio == dec!(2)
+        ));
+        assert_eq!(stock.trades[0].price, dec!(0.3));
+    }
+
+    #[test]
+    fn migrate_keeps_a_current_portfolio() {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+
+        let report = migrate(&mut portfolio);
+
+        assert!(report.is_up_to_date());
+        assert_eq!(portfolio.version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn ensure_supported_refuses_newer_versions() {
+        assert!(ensure_supported(SCHEMA_VERSION).is_ok());
+        assert!(ensure_supported(SCHEMA_VERSION + 1).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+mod json;
+mod sqlite;
+
+use super::Portfolio;
+use anyhow::Result;
+use std::path::Path;
+
+/// How a portfolio is persisted, it's chosen by the extension of its file.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum StorageFormat {
+    /// A single JSON document, it's rewritten whenever the portfolio is saved.
+    Json,
+    /// An embedded SQLite database, with a table for each kind of record so it can be queried. Only
+    /// the records that changed are written when it's saved, and it also holds the price cache.
+    Sqlite,
+}
+
+/// A backend where a portfolio is loaded from and saved to.
+trait Storage {
+    /// Loads the portfolio as it's stored, the migrations are applied by the caller.
+    fn load(&self, path: &Path) -> Result<Portfolio>;
+    /// Saves the portfolio without ever leaving it half written. The JSON files keep the previous
+    /// version as a backup, while the database relies on its transactions.
+    fn save(&self, portfolio: &Portfolio, path: &Path) -> Result<()>;
+}
+
+impl StorageFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("sqlite" | "sqlite3" | "db") => StorageFormat::Sqlite,
+            _ => StorageFormat::Json,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StorageFormat::Json => "json",
+            StorageFormat::Sqlite => "sqlite",
+        }
+    }
+
+    fn storage(&self) -> &'static dyn Storage {
+        match self {
+            StorageFormat::Json => &json::JsonStorage,
+            StorageFormat::Sqlite => &sqlite::SqliteStorage,
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Portfolio> {
+    StorageFormat::from_path(path).storage().load(path)
+}
+
+pub fn save(portfolio: &Portfolio, path: &Path) -> Result<()> {
+    StorageFormat::from_path(path)
+        .storage()
+        .save(portfolio, path)
+}
@@ -0,0 +1,22 @@
+use super::Storage;
+use crate::portfolio::backup;
+use crate::portfolio::migration::{ensure_supported, SchemaHeader};
+use crate::portfolio::Portfolio;
+use anyhow::Result;
+use std::path::Path;
+
+pub struct JsonStorage;
+
+impl Storage for JsonStorage {
+    fn load(&self, path: &Path) -> Result<Portfolio> {
+        let contents = std::fs::read(path)?;
+
+        ensure_supported(serde_json::from_slice::<SchemaHeader>(&contents)?.version)?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    fn save(&self, portfolio: &Portfolio, path: &Path) -> Result<()> {
+        let contents = serde_json::to_vec(portfolio)?;
+        backup::write_atomically(path, &contents)
+    }
+}
@@ -0,0 +1,683 @@
+use super::Storage;
+use crate::portfolio::migration::ensure_supported;
+use crate::portfolio::{
+    CorporateEvent, FeeSchedule, Fees, Income, LotId, Portfolio, Stock, Trade, TradeKind,
+};
+use anyhow::{Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Transaction};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+
+/// The amounts are stored as text, so they are kept exact, and the dates in ISO 8601, so they sort
+/// chronologically. The enums are stored by name and the corporate events keep their details as
+/// JSON, which can be queried with SQLite's `json_extract`. The records of a stock are identified
+/// by their `sequence` in it, which keeps the order of the ones at the same time.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS metadata (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS stocks (
+        symbol TEXT PRIMARY KEY,
+        currency TEXT NOT NULL,
+        cnpj TEXT,
+        asset_class TEXT
+    );
+    CREATE TABLE IF NOT EXISTS trades (
+        symbol TEXT NOT NULL REFERENCES stocks (symbol),
+        sequence INTEGER NOT NULL,
+        datetime TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        quantity INTEGER NOT NULL,
+        price TEXT NOT NULL,
+        brokerage TEXT NOT NULL,
+        emoluments TEXT NOT NULL,
+        settlement TEXT NOT NULL,
+        iss TEXT NOT NULL,
+        withheld_tax TEXT,
+        lot TEXT,
+        lot_number INTEGER,
+        fx_rate TEXT,
+        PRIMARY KEY (symbol, sequence)
+    );
+    CREATE TABLE IF NOT EXISTS incomes (
+        symbol TEXT NOT NULL REFERENCES stocks (symbol),
+        sequence INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        withheld_tax TEXT NOT NULL,
+        ex_date TEXT NOT NULL,
+        payment_date TEXT NOT NULL,
+        PRIMARY KEY (symbol, sequence)
+    );
+    CREATE TABLE IF NOT EXISTS events (
+        symbol TEXT NOT NULL REFERENCES stocks (symbol),
+        sequence INTEGER NOT NULL,
+        datetime TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        details TEXT NOT NULL,
+        PRIMARY KEY (symbol, sequence)
+    );
+    CREATE TABLE IF NOT EXISTS brokers (
+        name TEXT PRIMARY KEY,
+        brokerage TEXT NOT NULL,
+        emoluments_rate TEXT NOT NULL,
+        settlement_rate TEXT NOT NULL,
+        iss_rate TEXT NOT NULL
+    );
+";
+
+pub struct SqliteStorage;
+
+impl Storage for SqliteStorage {
+    fn load(&self, path: &Path) -> Result<Portfolio> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        let version = match metadata(&connection, "version")? {
+            Some(version) => version.parse().context("Invalid schema version")?,
+            None => 0,
+        };
+        ensure_supported(version)?;
+
+        let mut portfolio = Portfolio::new(path);
+        portfolio.version = version;
+        if let Some(cost_basis) = metadata(&connection, "cost_basis")? {
+            portfolio.cost_basis = from_text(&cost_basis)?;
+        }
+
+        load_stocks(&connection, &mut portfolio.stocks)?;
+        load_trades(&connection, &mut portfolio.stocks)?;
+        load_incomes(&connection, &mut portfolio.stocks)?;
+        load_events(&connection, &mut portfolio.stocks)?;
+        load_brokers(&connection, &mut portfolio)?;
+
+        // The records are loaded in their sequence, sorting them only moves the ones edited out of
+        // order, the ones at the same time keep the order they were added in.
+        for stock in portfolio.stocks.values_mut() {
+            stock.trades.sort_by_key(|trade| trade.datetime);
+            stock.events.sort_by_key(|event| event.datetime);
+        }
+
+        Ok(portfolio)
+    }
+
+    fn save(&self, portfolio: &Portfolio, path: &Path) -> Result<()> {
+        let mut connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        // Only the records that changed are written, in a single transaction so a crash rolls
+        // them back.
+        let transaction = connection.transaction()?;
+        // A renamed stock is replaced before its records, so the references are only checked
+        // once all the tables are saved.
+        transaction.execute_batch("PRAGMA defer_foreign_keys = ON")?;
+        save_portfolio(&transaction, portfolio)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
+
+fn metadata(connection: &Connection, key: &str) -> Result<Option<String>> {
+    Ok(connection
+        .query_row(
+            "SELECT value FROM metadata WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn load_stocks(connection: &Connection, stocks: &mut HashMap<String, Stock>) -> Result<()> {
+    let mut statement =
+        connection.prepare("SELECT symbol, currency, cnpj, asset_class FROM stocks")?;
+    let mut rows = statement.query([])?;
+
+    while let Some(row) = rows.next()? {
+        let symbol: String = row.get(0)?;
+
+        let mut stock = Stock::new(symbol.clone());
+        stock.currency = from_text(&row.get::<_, String>(1)?)?;
+        stock.cnpj = row.get(2)?;
+        stock.asset_class = row
+            .get::<_, Option<String>>(3)?
+            .map(|asset_class| from_text(&asset_class))
+            .transpose()?;
+
+        stocks.insert(symbol, stock);
+    }
+
+    Ok(())
+}
+
+fn load_trades(connection: &Connection, stocks: &mut HashMap<String, Stock>) -> Result<()> {
+    let mut statement = connection.prepare(
+        "SELECT symbol, datetime, kind, quantity, price, brokerage, emoluments, settlement, iss,
+            withheld_tax, lot, lot_number, fx_rate
+        FROM trades ORDER BY symbol, sequence",
+    )?;
+    let mut rows = statement.query([])?;
+
+    while let Some(row) = rows.next()? {
+        let number = row.get(11)?;
+        let trade = Trade {
+            datetime: parse_datetime(&row.get::<_, String>(1)?)?,
+            kind: from_text::<TradeKind>(&row.get::<_, String>(2)?)?,
+            quantity: row.get(3)?,
+            price: parse_decimal(&row.get::<_, String>(4)?)?,
+            legacy_splits: vec![],
+            fees: Fees {
+                brokerage: parse_decimal(&row.get::<_, String>(5)?)?,
+                emoluments: parse_decimal(&row.get::<_, String>(6)?)?,
+                settlement: parse_decimal(&row.get::<_, String>(7)?)?,
+                iss: parse_decimal(&row.get::<_, String>(8)?)?,
+            },
+            withheld_tax: parse_optional(row.get(9)?, parse_decimal)?,
+            lot: parse_optional(row.get(10)?, parse_datetime)?.map(|acquired_at| LotId {
+                acquired_at,
+                number,
+            }),
+            fx_rate: parse_optional(row.get(12)?, parse_decimal)?,
+        };
+
+        stock_mut(stocks, row.get(0)?)?.trades.push(trade);
+    }
+
+    Ok(())
+}
+
+fn load_incomes(connection: &Connection, stocks: &mut HashMap<String, Stock>) -> Result<()> {
+    let mut statement = connection.prepare(
+        "SELECT symbol, kind, amount, withheld_tax, ex_date, payment_date
+        FROM incomes ORDER BY symbol, sequence",
+    )?;
+    let mut rows = statement.query([])?;
+
+    while let Some(row) = rows.next()? {
+        let income = Income {
+            kind: from_text(&row.get::<_, String>(1)?)?,
+            amount: parse_decimal(&row.get::<_, String>(2)?)?,
+            withheld_tax: parse_decimal(&row.get::<_, String>(3)?)?,
+            ex_date: parse_date(&row.get::<_, String>(4)?)?,
+            payment_date: parse_date(&row.get::<_, String>(5)?)?,
+        };
+
+        stock_mut(stocks, row.get(0)?)?.incomes.push(income);
+    }
+
+    Ok(())
+}
+
+fn load_events(connection: &Connection, stocks: &mut HashMap<String, Stock>) -> Result<()> {
+    let mut statement = connection
+        .prepare("SELECT symbol, datetime, kind, details FROM events ORDER BY symbol, sequence")?;
+    let mut rows = statement.query([])?;
+
+    while let Some(row) = rows.next()? {
+        // The kind is stored as serde represents it, `{"Split": {"ratio": ...}}`.
+        let kind: String = row.get(2)?;
+        let details: serde_json::Value = serde_json::from_str(&row.get::<_, String>(3)?)?;
+
+        let event = CorporateEvent {
+            datetime: parse_datetime(&row.get::<_, String>(1)?)?,
+            kind: serde_json::from_value(serde_json::json!({ kind: details }))?,
+        };
+
+        stock_mut(stocks, row.get(0)?)?.events.push(event);
+    }
+
+    Ok(())
+}
+
+fn load_brokers(connection: &Connection, portfolio: &mut Portfolio) -> Result<()> {
+    let mut statement = connection.prepare(
+        "SELECT name, brokerage, emoluments_rate, settlement_rate, iss_rate FROM brokers",
+    )?;
+    let mut rows = statement.query([])?;
+
+    while let Some(row) = rows.next()? {
+        let schedule = FeeSchedule {
+            brokerage: parse_decimal(&row.get::<_, String>(1)?)?,
+            emoluments_rate: parse_decimal(&row.get::<_, String>(2)?)?,
+            settlement_rate: parse_decimal(&row.get::<_, String>(3)?)?,
+            iss_rate: parse_decimal(&row.get::<_, String>(4)?)?,
+        };
+
+        portfolio.brokers.insert(row.get(0)?, schedule);
+    }
+
+    Ok(())
+}
+
+fn save_portfolio(transaction: &Transaction, portfolio: &Portfolio) -> Result<()> {
+    let metadata = vec![
+        vec![
+            "version".to_string().into(),
+            portfolio.version.to_string().into(),
+        ],
+        vec![
+            "cost_basis".to_string().into(),
+            to_text(&portfolio.cost_basis)?.into(),
+        ],
+    ];
+
+    let mut stocks = vec![];
+    let mut trades = vec![];
+    let mut incomes = vec![];
+    let mut events = vec![];
+    // How many records of each kind the stocks have, the ones after them are deleted.
+    let mut records = vec![];
+
+    for stock in portfolio.stocks.values() {
+        stocks.push(vec![
+            stock.symbol.clone().into(),
+            to_text(&stock.currency)?.into(),
+            stock.cnpj.clone().into(),
+            stock.asset_class.as_ref().map(to_text).transpose()?.into(),
+        ]);
+
+        records.push((
+            stock.symbol.clone(),
+            [stock.trades.len(), stock.incomes.len(), stock.events.len()],
+        ));
+
+        for (sequence, trade) in stock.trades.iter().enumerate() {
+            trades.push(vec![
+                stock.symbol.clone().into(),
+                sequence_value(sequence),
+                format_datetime(trade.datetime)?.into(),
+                to_text(&trade.kind)?.into(),
+                trade.quantity.into(),
+                trade.price.to_string().into(),
+                trade.fees.brokerage.to_string().into(),
+                trade.fees.emoluments.to_string().into(),
+                trade.fees.settlement.to_string().into(),
+                trade.fees.iss.to_string().into(),
+                trade.withheld_tax.map(|tax| tax.to_string()).into(),
+                trade
+                    .lot
+                    .map(|lot| format_datetime(lot.acquired_at))
+                    .transpose()?
+                    .into(),
+                trade.lot.and_then(|lot| lot.number).into(),
+                trade.fx_rate.map(|rate| rate.to_string()).into(),
+            ]);
+        }
+
+        for (sequence, income) in stock.incomes.iter().enumerate() {
+            incomes.push(vec![
+                stock.symbol.clone().into(),
+                sequence_value(sequence),
+                to_text(&income.kind)?.into(),
+                income.amount.to_string().into(),
+                income.withheld_tax.to_string().into(),
+                income.ex_date.to_string().into(),
+                income.payment_date.to_string().into(),
+            ]);
+        }
+
+        for (sequence, event) in stock.events.iter().enumerate() {
+            let serde_json::Value::Object(kind) = serde_json::to_value(&event.kind)? else {
+                unreachable!("BUG: The corporate events are serialized as objects");
+            };
+            let Some((kind, details)) = kind.into_iter().next() else {
+                unreachable!("BUG: The corporate events are serialized with their kind");
+            };
+
+            events.push(vec![
+                stock.symbol.clone().into(),
+                sequence_value(sequence),
+                format_datetime(event.datetime)?.into(),
+                kind.into(),
+                details.to_string().into(),
+            ]);
+        }
+    }
+
+    let brokers = portfolio
+        .brokers
+        .iter()
+        .map(|(name, schedule)| {
+            vec![
+                name.clone().into(),
+                schedule.brokerage.to_string().into(),
+                schedule.emoluments_rate.to_string().into(),
+                schedule.settlement_rate.to_string().into(),
+                schedule.iss_rate.to_string().into(),
+            ]
+        })
+        .collect();
+
+    sync_table(transaction, "metadata", &["key", "value"], metadata)?;
+    sync_table(
+        transaction,
+        "stocks",
+        &["symbol", "currency", "cnpj", "asset_class"],
+        stocks,
+    )?;
+    sync_table(
+        transaction,
+        "brokers",
+        &[
+            "name",
+            "brokerage",
+            "emoluments_rate",
+            "settlement_rate",
+            "iss_rate",
+        ],
+        brokers,
+    )?;
+
+    let tables = [
+        (
+            "trades",
+            &[
+                "symbol",
+                "sequence",
+                "datetime",
+                "kind",
+                "quantity",
+                "price",
+                "brokerage",
+                "emoluments",
+                "settlement",
+                "iss",
+                "withheld_tax",
+                "lot",
+                "lot_number",
+                "fx_rate",
+            ][..],
+            trades,
+        ),
+        (
+            "incomes",
+            &[
+                "symbol",
+                "sequence",
+                "kind",
+                "amount",
+                "withheld_tax",
+                "ex_date",
+                "payment_date",
+            ][..],
+            incomes,
+        ),
+        (
+            "events",
+            &["symbol", "sequence", "datetime", "kind", "details"][..],
+            events,
+        ),
+    ];
+
+    for (index, (table, columns, rows)) in tables.into_iter().enumerate() {
+        upsert_rows(transaction, table, columns, 2, &rows)?;
+
+        // The records of the removed stocks and the ones after the last record of each stock.
+        transaction.execute(
+            &format!("DELETE FROM {table} WHERE symbol NOT IN (SELECT symbol FROM stocks)"),
+            [],
+        )?;
+        let mut delete = transaction.prepare(&format!(
+            "DELETE FROM {table} WHERE symbol = ?1 AND sequence >= ?2"
+        ))?;
+        for (symbol, counts) in &records {
+            delete.execute(params![symbol, sequence_value(counts[index])])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes the `table` hold exactly the `rows`, which are identified by their first column. The rows
+/// with other keys are deleted.
+fn sync_table(
+    transaction: &Transaction,
+    table: &str,
+    columns: &[&str],
+    rows: Vec<Vec<Value>>,
+) -> Result<()> {
+    upsert_rows(transaction, table, columns, 1, &rows)?;
+
+    let keys: Vec<&Value> = rows.iter().map(|row| &row[0]).collect();
+    let placeholders = vec!["?"; keys.len()].join(", ");
+    transaction.execute(
+        &format!(
+            "DELETE FROM {table} WHERE {} NOT IN ({placeholders})",
+            columns[0]
+        ),
+        params_from_iter(keys),
+    )?;
+
+    Ok(())
+}
+
+/// Inserts the `rows`, identified by their first `key_columns`, or updates the stored rows with the
+/// same key. Only the rows that changed are written, so saving after a trade only inserts it.
+fn upsert_rows(
+    transaction: &Transaction,
+    table: &str,
+    columns: &[&str],
+    key_columns: usize,
+    rows: &[Vec<Value>],
+) -> Result<()> {
+    let (key, values) = columns.split_at(key_columns);
+    let assignments: Vec<String> = values
+        .iter()
+        .map(|column| format!("{column} = excluded.{column}"))
+        .collect();
+    let changes: Vec<String> = values
+        .iter()
+        .map(|column| format!("{column} IS NOT excluded.{column}"))
+        .collect();
+
+    let mut upsert = transaction.prepare(&format!(
+        "INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {} WHERE {}",
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", "),
+        key.join(", "),
+        assignments.join(", "),
+        changes.join(" OR "),
+    ))?;
+    for row in rows {
+        upsert.execute(params_from_iter(row))?;
+    }
+
+    Ok(())
+}
+
+fn sequence_value(sequence: usize) -> Value {
+    i64::try_from(sequence)
+        .expect("BUG: Too many records")
+        .into()
+}
+
+fn stock_mut(stocks: &mut HashMap<String, Stock>, symbol: String) -> Result<&mut Stock> {
+    stocks
+        .get_mut(&symbol)
+        .with_context(|| format!("The stock {symbol} is not in the stocks table"))
+}
+
+/// The enums are stored by their serde name, e.g. `Buy` or `USD`.
+fn to_text<T: Serialize>(value: &T) -> Result<String> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(text) => Ok(text),
+        value => anyhow::bail!("BUG: {value} is not stored as text"),
+    }
+}
+
+fn from_text<T: DeserializeOwned>(text: &str) -> Result<T> {
+    serde_json::from_value(serde_json::Value::String(text.to_string()))
+        .with_context(|| format!("Invalid value `{text}`"))
+}
+
+fn parse_decimal(text: &str) -> Result<Decimal> {
+    text.parse()
+        .with_context(|| format!("Invalid amount `{text}`"))
+}
+
+fn format_datetime(datetime: OffsetDateTime) -> Result<String> {
+    Ok(datetime.format(&Rfc3339)?)
+}
+
+fn parse_datetime(text: &str) -> Result<OffsetDateTime> {
+    OffsetDateTime::parse(text, &Rfc3339).with_context(|| format!("Invalid datetime `{text}`"))
+}
+
+fn parse_date(text: &str) -> Result<Date> {
+    Date::parse(text, format_description!("[year]-[month]-[day]"))
+        .with_context(|| format!("Invalid date `{text}`"))
+}
+
+fn parse_optional<T>(text: Option<String>, parse: fn(&str) -> Result<T>) -> Result<Option<T>> {
+    text.as_deref().map(parse).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::{IncomeKind, TradeDetails};
+    use rust_decimal::dec;
+    use tempfile::TempDir;
+    use time::macros::{date, datetime};
+
+    /// Two purchases at the same time, as imported without time, a split and a dividend.
+    fn portfolio(path: &Path) -> Portfolio {
+        let mut portfolio = Portfolio::new(path);
+        for price in [dec!(10.5), dec!(10.25)] {
+            portfolio.buy(
+                "ITSA4",
+                100,
+                price,
+                Fees::default(),
+                TradeDetails::default(),
+                datetime!(2024-03-04 00:00 UTC),
+            );
+        }
+        portfolio
+            .split("ITSA4", dec!(2), None, datetime!(2024-04-01 10:00 UTC))
+            .expect("The split should be valid");
+        portfolio
+            .add_income(
+                "ITSA4",
+                Income {
+                    kind: IncomeKind::Jcp,
+                    amount: dec!(20),
+                    withheld_tax: dec!(3),
+                    ex_date: date!(2024 - 05 - 02),
+                    payment_date: date!(2024 - 05 - 15),
+                },
+            )
+            .expect("The stock should exist");
+        portfolio.set_broker(
+            "clear",
+            FeeSchedule {
+                brokerage: dec!(4.90),
+                emoluments_rate: dec!(0.005),
+                settlement_rate: dec!(0.025),
+                iss_rate: dec!(5),
+            },
+        );
+        portfolio
+    }
+
+    fn rowid(path: &Path, sequence: i64) -> i64 {
+        Connection::open(path)
+            .expect("The database should open")
+            .query_row(
+                "SELECT rowid FROM trades WHERE symbol = 'ITSA4' AND sequence = ?1",
+                params![sequence],
+                |row| row.get(0),
+            )
+            .expect("The trade should be stored")
+    }
+
+    #[test]
+    fn load_reads_what_was_saved() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let path = dir.path().join("default.sqlite");
+        let portfolio = portfolio(&path);
+
+        SqliteStorage
+            .save(&portfolio, &path)
+            .expect("The portfolio should be saved");
+        let loaded = SqliteStorage
+            .load(&path)
+            .expect("The portfolio should load");
+
+        assert_eq!(
+            serde_json::to_value(&loaded).expect("The portfolio should serialize"),
+            serde_json::to_value(&portfolio).expect("The portfolio should serialize")
+        );
+    }
+
+    #[test]
+    fn save_updates_the_changed_rows_in_place() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let path = dir.path().join("default.sqlite");
+        let mut portfolio = portfolio(&path);
+        SqliteStorage
+            .save(&portfolio, &path)
+            .expect("The portfolio should be saved");
+        let first = rowid(&path, 0);
+
+        // The first purchase changes, the order of the purchases at the same time is kept.
+        portfolio
+            .stocks
+            .get_mut("ITSA4")
+            .expect("The stock should exist")
+            .trades[0]
+            .fees
+            .brokerage = dec!(4.90);
+        SqliteStorage
+            .save(&portfolio, &path)
+            .expect("The portfolio should be saved");
+
+        assert_eq!(rowid(&path, 0), first);
+        let loaded = SqliteStorage
+            .load(&path)
+            .expect("The portfolio should load");
+        let trades = &loaded.stocks["ITSA4"].trades;
+        assert_eq!(trades[0].price, dec!(10.5));
+        assert_eq!(trades[0].fees.brokerage, dec!(4.90));
+        assert_eq!(trades[1].price, dec!(10.25));
+    }
+
+    #[test]
+    fn save_deletes_the_removed_records() {
+        let dir = TempDir::new().expect("The temporary directory should be created");
+        let path = dir.path().join("default.sqlite");
+        let mut portfolio = portfolio(&path);
+        SqliteStorage
+            .save(&portfolio, &path)
+            .expect("The portfolio should be saved");
+
+        let stock = portfolio
+            .stocks
+            .get_mut("ITSA4")
+            .expect("The stock should exist");
+        stock.trades.remove(0);
+        stock.events.clear();
+        portfolio.brokers.clear();
+        SqliteStorage
+            .save(&portfolio, &path)
+            .expect("The portfolio should be saved");
+
+        let loaded = SqliteStorage
+            .load(&path)
+            .expect("The portfolio should load");
+        let stock = &loaded.stocks["ITSA4"];
+        assert_eq!(stock.trades.len(), 1);
+        assert_eq!(stock.trades[0].price, dec!(10.25));
+        assert!(stock.events.is_empty());
+        assert_eq!(stock.incomes.len(), 1);
+        assert!(loaded.brokers.is_empty());
+    }
+}
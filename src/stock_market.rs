@@ -1,93 +1,579 @@
-use super::portfolio::Stock;
-use anyhow::Result;
-use serde::Deserialize;
-use time::OffsetDateTime;
-use ureq::Agent;
+mod bcb;
+mod cache;
+mod local;
+mod mfinance;
 
-const API_URL: &str = "https://mfinance.com.br/api/v1/stocks/";
+pub use bcb::Bcb;
+pub use cache::PriceCache;
+pub use local::LocalPriceProvider;
+pub use mfinance::{MFinance, MFinanceResponse};
 
-/// Represents the stock market, it's responsible for fetching real stock information.
+use super::portfolio::{Currency, Portfolio, Stock};
+use anyhow::{Context, Result};
+use cache::CachedCloses;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use time::{Date, Duration, OffsetDateTime};
+
+const HISTORY_LOOKBACK_DAYS: i64 = 15;
+/// The FX rates are not published on weekends and holidays.
+const FX_LOOKBACK_DAYS: i64 = 10;
+/// The API that we currently use updates roughly once every 20 minutes.
+pub const DEFAULT_MAX_AGE_MINUTES: i64 = 20;
+
+/// Represents the stock market, it's responsible for fetching real stock information through the
+/// configured [`PriceProvider`].
 pub struct StockMarket {
-    client: Agent,
+    provider: Box<dyn PriceProvider>,
+    fx_provider: Box<dyn FxProvider>,
+    cache: Option<PriceCache>,
+    /// How long a cached quote is considered fresh.
+    max_age: Duration,
+    offline: bool,
 }
 
 #[derive(Deserialize)]
 pub struct PricedStock {
     pub symbol: String,
     pub quantity: u32,
-    pub average_price: f64,
-    pub price: f64,
-    pub last_price: f64,
+    pub average_price: Decimal,
+    pub price: Decimal,
+    pub last_price: Decimal,
+    /// The net income paid by the stock in the last 12 months.
+    pub income: Decimal,
+    #[serde(with = "time::serde::rfc3339")]
+    pub fetched_at: OffsetDateTime,
+    /// Whether the price is outdated, i.e. it comes from the cache and it's older than the
+    /// freshness window.
+    pub stale: bool,
 }
 
-/// The complete response from the `MFinance` API.
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct MFinanceResponse {
-    pub change: f64,
-    pub closing_price: f64,
-    pub eps: f64,
-    pub high: f64,
-    pub last_price: f64,
-    pub last_year_high: f64,
-    pub last_year_low: f64,
-    pub low: f64,
-    pub market_cap: f64,
-    pub name: String,
-    pub pe: f64,
-    pub price_open: f64,
-    pub sector: String,
-    pub segment: String,
-    pub shares: f64,
-    pub sub_sector: String,
+/// A quote along with its provenance.
+struct MarketQuote {
+    quote: Quote,
+    fetched_at: OffsetDateTime,
+    stale: bool,
+}
+
+/// The price of a single symbol as reported by a [`PriceProvider`].
+pub struct Quote {
     pub symbol: String,
-    pub volume: f64,
-    pub volume_avg: f64,
+    /// The most recent price.
+    pub price: Decimal,
+    /// The closing price of the previous trading day.
+    pub last_price: Decimal,
+}
+
+/// The closing price of a symbol on a given trading day.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ClosingPrice {
+    #[serde(with = "crate::serde_date")]
+    pub date: Date,
+    pub close: Decimal,
+}
+
+/// A source of stock prices.
+pub trait PriceProvider: Sync {
+    fn quote(&self, symbol: &str) -> Result<Quote>;
+
+    /// Fetches the quotes for many symbols at once, the results are in the same order as the
+    /// `symbols`. Providers with a batch endpoint should override it, by default it fetches every
+    /// symbol concurrently.
+    fn quotes(&self, symbols: &[&str]) -> Vec<Result<Quote>> {
+        fetch_concurrently(symbols, |symbol| self.quote(symbol))
+    }
+
+    /// Fetches the closing prices between `from` and `to` (both inclusive) in chronological order,
+    /// days without trading are absent.
+    fn history(&self, symbol: &str, from: Date, to: Date) -> Result<Vec<ClosingPrice>>;
+}
+
+/// A source of exchange rates.
+pub trait FxProvider: Sync {
+    /// The BRL value of one unit of `currency` on the last day with a rate on or before `date`,
+    /// looking back at most `lookback` days.
+    fn fx_rate(&self, currency: Currency, date: Date, lookback: Duration) -> Result<Decimal>;
 }
 
 impl StockMarket {
     pub fn new() -> Self {
+        Self::with_provider(MFinance::new())
+    }
+
+    pub fn with_provider(provider: impl PriceProvider + 'static) -> Self {
         Self {
-            client: Agent::new(),
+            provider: Box::new(provider),
+            fx_provider: Box::new(Bcb::new()),
+            cache: None,
+            max_age: Duration::minutes(DEFAULT_MAX_AGE_MINUTES),
+            offline: false,
         }
     }
 
+    pub fn with_fx_provider(mut self, fx_provider: impl FxProvider + 'static) -> Self {
+        self.fx_provider = Box::new(fx_provider);
+        self
+    }
+
+    /// Reuses the prices in the `cache` that were fetched within `max_age`, the newly fetched
+    /// prices are stored in it.
+    pub fn with_cache(mut self, cache: PriceCache, max_age: Duration) -> Self {
+        self.cache = Some(cache);
+        self.max_age = max_age;
+        self
+    }
+
+    /// Never reach the provider, the prices come exclusively from the cache.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    pub fn save_cache(&mut self) -> Result<()> {
+        match &mut self.cache {
+            Some(cache) => cache.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Prices the `stocks` of the `portfolio` at the given `date`, when it's in the past the price
+    /// is the closing price on (or before) that day instead of the current price.
+    ///
+    /// When the price can't be fetched, the last cached price is used instead and the stock is
+    /// marked as stale.
     pub fn get_stock_prices(
-        &self,
-        stocks: &[Stock],
+        &mut self,
+        portfolio: &Portfolio,
+        stocks: &[&Stock],
         date: OffsetDateTime,
     ) -> Vec<Result<PricedStock>> {
-        std::thread::scope(|s| {
-            let mut handles = Vec::with_capacity(stocks.len());
-
-            for stock in stocks {
-                let handle = s.spawn(|| {
-                    let response = self
-                        .client
-                        .get(format!("{API_URL}/{}", stock.symbol).as_str())
-                        .call()?;
-
-                    let response: MFinanceResponse = response.into_json()?;
-
-                    Ok(PricedStock {
-                        symbol: response.symbol,
-                        quantity: stock.quantity(date),
-                        average_price: stock.average_purchase_price(date),
-                        price: response.last_price,
-                        last_price: response.closing_price,
+        let symbols: Vec<&str> = stocks.iter().map(|stock| stock.symbol.as_str()).collect();
+
+        let now = OffsetDateTime::now_utc();
+        let today = now.to_offset(date.offset()).date();
+        let quotes = if date.date() < today {
+            self.historical_quotes(&symbols, date.date(), today, now)
+        } else {
+            self.current_quotes(&symbols, now)
+        };
+
+        quotes
+            .into_iter()
+            .zip(stocks)
+            .map(|(quote, stock)| {
+                let quote = quote
+                    .with_context(|| format!("Could not get the price of {}", stock.symbol))?;
+                let position = stock.position(portfolio, date)?;
+
+                Ok(PricedStock {
+                    symbol: quote.quote.symbol,
+                    quantity: position.quantity,
+                    average_price: position.average_price(),
+                    price: quote.quote.price,
+                    last_price: quote.quote.last_price,
+                    income: stock.trailing_income(date),
+                    fetched_at: quote.fetched_at,
+                    stale: quote.stale,
+                })
+            })
+            .collect()
+    }
+
+    /// The BRL value of one unit of `currency` at the given `date`, it's the last rate published on
+    /// or before that day.
+    pub fn fx_rate(&mut self, currency: Currency, date: Date) -> Result<Decimal> {
+        if currency == Currency::Brl {
+            return Ok(Decimal::ONE);
+        }
+
+        if let Some(rate) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.fx_rate(currency, date))
+        {
+            return Ok(rate);
+        }
+
+        anyhow::ensure!(!self.offline, "No cached {currency} rate on {date}");
+
+        let rate = self
+            .fx_provider
+            .fx_rate(currency, date, Duration::days(FX_LOOKBACK_DAYS))
+            .with_context(|| format!("Could not get the {currency} rate on {date}"))?;
+
+        // Today's rate might not be published yet.
+        let today = OffsetDateTime::now_utc().date();
+        if let Some(cache) = self.cache.as_mut().filter(|_| date < today) {
+            cache.store_fx_rate(currency, date, rate);
+        }
+
+        Ok(rate)
+    }
+
+    /// The BRL value of one unit of `currency` at the given `date` as in [`Self::fx_rate`], when it
+    /// can't be found (e.g. offline) the last cached rate before that day is used instead, along
+    /// with its date.
+    pub fn fx_rate_or_cached(
+        &mut self,
+        currency: Currency,
+        date: Date,
+    ) -> Result<(Decimal, Option<Date>)> {
+        let err = match self.fx_rate(currency, date) {
+            Ok(rate) => return Ok((rate, None)),
+            Err(err) => err,
+        };
+
+        let (cached_on, rate) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.last_fx_rate(currency, date))
+            .ok_or(err)?;
+
+        Ok((rate, Some(cached_on)))
+    }
+
+    fn current_quotes(
+        &mut self,
+        symbols: &[&str],
+        now: OffsetDateTime,
+    ) -> Vec<Result<MarketQuote>> {
+        let cached: Vec<Option<MarketQuote>> = symbols
+            .iter()
+            .map(|symbol| {
+                let cached = self.cache.as_ref()?.quote(symbol)?;
+
+                Some(MarketQuote {
+                    quote: Quote {
+                        symbol: symbol.to_string(),
+                        price: cached.price,
+                        last_price: cached.last_price,
+                    },
+                    fetched_at: cached.fetched_at,
+                    stale: now - cached.fetched_at > self.max_age,
+                })
+            })
+            .collect();
+
+        let missing: Vec<&str> = if self.offline {
+            vec![]
+        } else {
+            symbols
+                .iter()
+                .zip(&cached)
+                .filter(|(_, cached)| cached.as_ref().is_none_or(|cached| cached.stale))
+                .map(|(symbol, _)| *symbol)
+                .collect()
+        };
+
+        let mut fetched = symbols_with(&missing, self.provider.quotes(&missing));
+
+        symbols
+            .iter()
+            .zip(cached)
+            .map(|(symbol, cached)| match fetched.remove(symbol) {
+                Some(Ok(quote)) => {
+                    if let Some(cache) = &mut self.cache {
+                        cache.store_quote(&quote, now);
+                    }
+
+                    Ok(MarketQuote {
+                        quote,
+                        fetched_at: now,
+                        stale: false,
                     })
+                }
+                Some(Err(err)) => cached.ok_or(err),
+                None => cached.with_context(|| format!("No cached price for {symbol}")),
+            })
+            .collect()
+    }
+
+    fn historical_quotes(
+        &mut self,
+        symbols: &[&str],
+        date: Date,
+        today: Date,
+        now: OffsetDateTime,
+    ) -> Vec<Result<MarketQuote>> {
+        let lookback = Duration::days(HISTORY_LOOKBACK_DAYS);
+
+        let cached: Vec<Option<CachedCloses>> = symbols
+            .iter()
+            .map(|symbol| self.cache.as_ref()?.closes(symbol, date, lookback))
+            .collect();
+
+        let missing: Vec<&str> = if self.offline {
+            vec![]
+        } else {
+            symbols
+                .iter()
+                .zip(&cached)
+                .filter(|(_, cached)| cached.as_ref().is_none_or(|cached| !cached.complete))
+                .map(|(symbol, _)| *symbol)
+                .collect()
+        };
+
+        // Long enough to cover weekends and holidays.
+        let from = date - lookback;
+        let histories =
+            fetch_concurrently(&missing, |symbol| self.provider.history(symbol, from, date));
+        let mut fetched = symbols_with(&missing, histories);
+
+        symbols
+            .iter()
+            .zip(cached)
+            .map(|(symbol, cached)| {
+                let cached = cached.map(|cached| MarketQuote {
+                    quote: Quote {
+                        symbol: symbol.to_string(),
+                        price: cached.price,
+                        last_price: cached.last_price,
+                    },
+                    fetched_at: cached.fetched_at,
+                    stale: !cached.complete,
                 });
-                handles.push(handle);
-            }
-
-            handles
-                .into_iter()
-                .map(|handle| {
-                    handle.join().unwrap_or_else(|err| {
-                        Err(anyhow::anyhow!("Failed to fetch stock price: {err:?}"))
-                    })
+
+                match fetched.remove(symbol) {
+                    Some(Ok(history)) => {
+                        if let Some(cache) = &mut self.cache {
+                            // Today's closing price is not final yet.
+                            let to = date.min(today.previous_day().unwrap_or(today));
+                            cache.store_history(symbol, from, to, &history, now);
+                        }
+
+                        Ok(MarketQuote {
+                            quote: quote_from_history(symbol, &history, date)?,
+                            fetched_at: now,
+                            stale: false,
+                        })
+                    }
+                    Some(Err(err)) => cached.ok_or(err),
+                    None => cached.with_context(|| format!("No cached price for {symbol}")),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The closing price on the last trading day on or before `date` and the closing price of the
+/// trading day before it.
+fn quote_from_history(symbol: &str, history: &[ClosingPrice], date: Date) -> Result<Quote> {
+    let mut closes = history
+        .iter()
+        .rev()
+        .filter(|price| price.date <= date)
+        .map(|price| price.close);
+
+    let price = closes
+        .next()
+        .with_context(|| format!("No closing price for {symbol} on or before {date}"))?;
+
+    Ok(Quote {
+        symbol: symbol.to_string(),
+        price,
+        last_price: closes.next().unwrap_or(price),
+    })
+}
+
+/// Pairs each symbol with its result, so they can be looked up by symbol.
+fn symbols_with<'a, T>(symbols: &[&'a str], results: Vec<T>) -> HashMap<&'a str, T> {
+    symbols.iter().copied().zip(results).collect()
+}
+
+/// Calls `fetch` for every symbol in its own thread, the results are in the same order as the
+/// `symbols`.
+fn fetch_concurrently<T: Send>(
+    symbols: &[&str],
+    fetch: impl Fn(&str) -> Result<T> + Sync,
+) -> Vec<Result<T>> {
+    std::thread::scope(|s| {
+        let handles: Vec<_> = symbols
+            .iter()
+            .map(|symbol| s.spawn(|| fetch(symbol)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle.join().unwrap_or_else(|err| {
+                    Err(anyhow::anyhow!("Failed to fetch stock price: {err:?}"))
                 })
-                .collect()
-        })
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::{Fees, TradeDetails};
+    use rust_decimal::dec;
+    use std::path::Path;
+    use tempfile::TempDir;
+    use time::macros::{date, datetime};
+
+    const PRICES: &str = r#"{
+        "ITSA4": [
+            { "date": "2024-03-05", "close": 11.00 },
+            { "date": "2024-03-01", "close": 10.00 },
+            { "date": "2024-03-04", "close": 10.50 }
+        ],
+        "USD": [{ "date": "2024-03-04", "close": 4.95 }]
+    }"#;
+
+    fn local_provider(dir: &TempDir, prices: &str) -> LocalPriceProvider {
+        let path = dir.path().join("prices.json");
+        std::fs::write(&path, prices).expect("The prices should be written");
+
+        LocalPriceProvider::load(&path).expect("The prices should be valid")
+    }
+
+    fn portfolio() -> Portfolio {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+        portfolio.buy(
+            "ITSA4",
+            100,
+            dec!(9),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-02-01 10:00 UTC),
+        );
+        portfolio
+    }
+
+    #[test]
+    fn local_provider_quotes_the_last_closes() {
+        let dir = TempDir::new().expect("The directory should be created");
+        let provider = local_provider(&dir, PRICES);
+
+        let quote = provider.quote("ITSA4").expect("ITSA4 should be quoted");
+        assert_eq!(quote.price, dec!(11));
+        assert_eq!(quote.last_price, dec!(10.5));
+        assert!(provider.quote("PETR4").is_err());
+    }
+
+    #[test]
+    fn get_stock_prices_uses_the_close_on_or_before_the_date() {
+        let dir = TempDir::new().expect("The directory should be created");
+        let portfolio = portfolio();
+        let stock = &portfolio.stocks["ITSA4"];
+        let mut stock_market = StockMarket::with_provider(local_provider(&dir, PRICES));
+
+        // There is no trading on the weekend.
+        let prices =
+            stock_market.get_stock_prices(&portfolio, &[stock], datetime!(2024-03-03 23:59 UTC));
+
+        let price = prices[0].as_ref().expect("ITSA4 should be priced");
+        assert_eq!(price.quantity, 100);
+        assert_eq!(price.average_price, dec!(9));
+        assert_eq!(price.price, dec!(10));
+        assert_eq!(price.last_price, dec!(10));
+        assert!(!price.stale);
+    }
+
+    #[test]
+    fn offline_uses_the_cached_closes() {
+        let dir = TempDir::new().expect("The directory should be created");
+        let cache_path = dir.path().join("cache.json");
+        let portfolio = portfolio();
+        let stock = &portfolio.stocks["ITSA4"];
+        let date = datetime!(2024-03-05 23:59 UTC);
+
+        let mut stock_market = StockMarket::with_provider(local_provider(&dir, PRICES))
+            .with_cache(PriceCache::new(&cache_path), Duration::minutes(20));
+        assert!(stock_market.get_stock_prices(&portfolio, &[stock], date)[0].is_ok());
+        stock_market
+            .save_cache()
+            .expect("The cache should be saved");
+
+        let cache = PriceCache::load(&cache_path).expect("The cache should be valid");
+        let mut offline = StockMarket::with_provider(local_provider(&dir, "{}"))
+            .with_cache(cache, Duration::minutes(20))
+            .offline();
+
+        let prices = offline.get_stock_prices(&portfolio, &[stock], date);
+        let price = prices[0].as_ref().expect("ITSA4 should be cached");
+        assert_eq!(price.price, dec!(11));
+        assert_eq!(price.last_price, dec!(10.5));
+        assert!(!price.stale);
+
+        // The days never fetched aren't available offline.
+        let before = datetime!(2024-01-10 23:59 UTC);
+        assert!(offline.get_stock_prices(&portfolio, &[stock], before)[0].is_err());
+    }
+
+    #[test]
+    fn offline_marks_the_outdated_quotes_as_stale() {
+        let dir = TempDir::new().expect("The directory should be created");
+        let portfolio = portfolio();
+        let stock = &portfolio.stocks["ITSA4"];
+        let now = OffsetDateTime::now_utc();
+
+        let mut cache = PriceCache::new(&dir.path().join("cache.json"));
+        let quote = Quote {
+            symbol: "ITSA4".to_string(),
+            price: dec!(12),
+            last_price: dec!(11.5),
+        };
+        cache.store_quote(&quote, now - Duration::days(1));
+
+        let mut stock_market = StockMarket::with_provider(local_provider(&dir, PRICES))
+            .with_cache(cache, Duration::minutes(20))
+            .offline();
+
+        let prices = stock_market.get_stock_prices(&portfolio, &[stock], now);
+        let price = prices[0].as_ref().expect("ITSA4 should be cached");
+        assert_eq!(price.price, dec!(12));
+        assert!(price.stale);
+    }
+
+    #[test]
+    fn fx_rate_is_cached_for_offline_use() {
+        let dir = TempDir::new().expect("The directory should be created");
+        let provider = local_provider(&dir, PRICES);
+        let mut stock_market = StockMarket::with_provider(local_provider(&dir, PRICES))
+            .with_fx_provider(provider)
+            .with_cache(
+                PriceCache::new(&dir.path().join("cache.json")),
+                Duration::minutes(20),
+            );
+
+        // The rate of the last business day is used.
+        let rate = stock_market.fx_rate(Currency::Usd, date!(2024 - 03 - 05));
+        assert_eq!(rate.expect("The rate should be found"), dec!(4.95));
+
+        let mut offline = stock_market.offline();
+        assert_eq!(
+            offline.fx_rate(Currency::Usd, date!(2024 - 03 - 05)).ok(),
+            Some(dec!(4.95))
+        );
+        assert!(offline
+            .fx_rate(Currency::Usd, date!(2024 - 03 - 06))
+            .is_err());
+        assert_eq!(
+            offline.fx_rate(Currency::Brl, date!(2024 - 03 - 06)).ok(),
+            Some(Decimal::ONE)
+        );
+    }
+
+    #[test]
+    fn fx_rate_or_cached_falls_back_to_the_last_cached_rate() {
+        let dir = TempDir::new().expect("The directory should be created");
+        let mut cache = PriceCache::new(&dir.path().join("cache.json"));
+        cache.store_fx_rate(Currency::Usd, date!(2024 - 03 - 01), dec!(4.97));
+        cache.store_fx_rate(Currency::Usd, date!(2024 - 03 - 04), dec!(4.95));
+
+        let mut stock_market = StockMarket::with_provider(local_provider(&dir, "{}"))
+            .with_cache(cache, Duration::minutes(20))
+            .offline();
+
+        let rate = stock_market.fx_rate_or_cached(Currency::Usd, date!(2024 - 03 - 04));
+        assert_eq!(rate.ok(), Some((dec!(4.95), None)));
+        let rate = stock_market.fx_rate_or_cached(Currency::Usd, date!(2024 - 03 - 08));
+        assert_eq!(rate.ok(), Some((dec!(4.95), Some(date!(2024 - 03 - 04)))));
+        assert!(stock_market
+            .fx_rate_or_cached(Currency::Eur, date!(2024 - 03 - 08))
+            .is_err());
     }
 }
@@ -0,0 +1,102 @@
+use super::FxProvider;
+use crate::portfolio::Currency;
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use time::{format_description, Date, Duration};
+use ureq::Agent;
+
+const SGS_API_URL: &str = "https://api.bcb.gov.br/dados/serie/bcdata.sgs";
+const PTAX_API_URL: &str = "https://olinda.bcb.gov.br/olinda/servico/PTAX/versao/v1/odata";
+/// The SGS series of the Selic rate accumulated in each month.
+const MONTHLY_SELIC_SERIES: u32 = 4390;
+
+/// Fetches economic indicators from the Banco Central do Brasil APIs.
+pub struct Bcb {
+    client: Agent,
+}
+
+/// A single value of a SGS series, only the fields that we use are listed.
+#[derive(Deserialize)]
+struct SgsValue {
+    /// The value is formatted as a string (e.g. `"1.12"`).
+    valor: String,
+}
+
+/// The response from the PTAX API, only the fields that we use are listed.
+#[derive(Deserialize)]
+struct PtaxResponse {
+    value: Vec<PtaxRate>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PtaxRate {
+    cotacao_compra: Decimal,
+    /// There are intermediate rates during the day, only the closing one is the PTAX.
+    tipo_boletim: String,
+}
+
+impl Bcb {
+    pub fn new() -> Self {
+        Self {
+            client: Agent::new(),
+        }
+    }
+
+    /// The sum of the monthly Selic rates, in percentage, from the month of `from` until the month
+    /// of `to` (both inclusive).
+    pub fn accumulated_selic(&self, from: Date, to: Date) -> Result<Decimal> {
+        let values = self.series(MONTHLY_SELIC_SERIES, from, to)?;
+
+        values
+            .iter()
+            .map(|value| {
+                value
+                    .valor
+                    .parse::<Decimal>()
+                    .with_context(|| format!("Invalid Selic rate `{}`", value.valor))
+            })
+            .sum()
+    }
+
+    fn series(&self, series: u32, from: Date, to: Date) -> Result<Vec<SgsValue>> {
+        let date_format = format_description::parse_borrowed::<1>("[day]/[month]/[year]")?;
+
+        let values: Vec<SgsValue> = self
+            .client
+            .get(format!("{SGS_API_URL}.{series}/dados").as_str())
+            .query("formato", "json")
+            .query("dataInicial", &from.format(&date_format)?)
+            .query("dataFinal", &to.format(&date_format)?)
+            .call()?
+            .into_json()?;
+
+        Ok(values)
+    }
+}
+
+impl FxProvider for Bcb {
+    /// The PTAX purchase rate, it's the rate used by the Receita Federal.
+    fn fx_rate(&self, currency: Currency, date: Date, lookback: Duration) -> Result<Decimal> {
+        let date_format = format_description::parse_borrowed::<1>("[month]-[day]-[year]")?;
+
+        let url = format!(
+            "{PTAX_API_URL}/CotacaoMoedaPeriodo(moeda=@moeda,dataInicial=@dataInicial,dataFinalCotacao=@dataFinalCotacao)?@moeda='{}'&@dataInicial='{}'&@dataFinalCotacao='{}'&$format=json",
+            currency.code(),
+            (date - lookback).format(&date_format)?,
+            date.format(&date_format)?,
+        );
+
+        let response: PtaxResponse = self.client.get(url.as_str()).call()?.into_json()?;
+
+        let rate = response
+            .value
+            .iter()
+            .rev()
+            .find(|rate| rate.tipo_boletim == "Fechamento")
+            .with_context(|| format!("No {currency} PTAX on or before {date}"))?;
+
+        Ok(rate.cotacao_compra)
+    }
+}
@@ -0,0 +1,361 @@
+use super::{ClosingPrice, Quote};
+use crate::portfolio::backup;
+use crate::portfolio::{Currency, StorageFormat};
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, Duration, OffsetDateTime};
+
+/// The tables of the cache in a SQLite portfolio, the amounts are stored as text so they are kept
+/// exact.
+const PRICES_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS quotes (
+        symbol TEXT PRIMARY KEY,
+        price TEXT NOT NULL,
+        last_price TEXT NOT NULL,
+        fetched_at TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS prices (
+        symbol TEXT NOT NULL,
+        date TEXT NOT NULL,
+        close TEXT,
+        fetched_at TEXT NOT NULL,
+        PRIMARY KEY (symbol, date)
+    );
+    CREATE TABLE IF NOT EXISTS fx_rates (
+        currency TEXT NOT NULL,
+        date TEXT NOT NULL,
+        rate TEXT NOT NULL,
+        PRIMARY KEY (currency, date)
+    );
+";
+
+/// A persisted store of the prices fetched from the network, it allows us to avoid refetching fresh
+/// prices and to work offline. It's a JSON file, or the tables of a SQLite portfolio.
+#[derive(Serialize, Deserialize, Default)]
+pub struct PriceCache {
+    #[serde(skip)]
+    path: PathBuf,
+    /// The latest quote fetched for each symbol.
+    quotes: HashMap<String, CachedQuote>,
+    /// The closing prices for each symbol, keyed by the `YYYY-MM-DD` date so that they are sorted
+    /// chronologically.
+    closes: HashMap<String, BTreeMap<String, CachedClose>>,
+    /// The FX rates of each currency, keyed by the `YYYY-MM-DD` date. They are final, so they
+    /// never expire.
+    #[serde(default)]
+    fx_rates: HashMap<Currency, BTreeMap<String, Decimal>>,
+    /// The entries stored since the cache was loaded, the database only writes these.
+    #[serde(skip)]
+    unsaved: HashSet<CacheEntry>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum CacheEntry {
+    Quote(String),
+    Close(String, String),
+    FxRate(Currency, String),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CachedQuote {
+    pub price: Decimal,
+    pub last_price: Decimal,
+    #[serde(with = "time::serde::rfc3339")]
+    pub fetched_at: OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedClose {
+    /// There is no closing price on days without trading (e.g. weekends and holidays).
+    close: Option<Decimal>,
+    #[serde(with = "time::serde::rfc3339")]
+    fetched_at: OffsetDateTime,
+}
+
+/// The closing prices found in the cache for a given date.
+pub struct CachedCloses {
+    pub price: Decimal,
+    pub last_price: Decimal,
+    pub fetched_at: OffsetDateTime,
+    /// Whether all the days between the requested date and the prices are cached, otherwise there
+    /// might be a more recent price that we never fetched.
+    pub complete: bool,
+}
+
+impl PriceCache {
+    /// An empty cache, saved to `path`.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            ..Self::default()
+        }
+    }
+
+    /// Loads the cache from `path`, an empty cache is returned if the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut cache: Self = match StorageFormat::from_path(path) {
+            StorageFormat::Json => match std::fs::File::open(path) {
+                Ok(file) => serde_json::from_reader(file)
+                    .with_context(|| format!("Could not parse the price cache {path:?}"))?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Self::default(),
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Could not open the price cache {path:?}"))
+                }
+            },
+            StorageFormat::Sqlite if path.exists() => Self::load_database(path)
+                .with_context(|| format!("Could not read the prices in {path:?}"))?,
+            StorageFormat::Sqlite => Self::default(),
+        };
+
+        cache.path = path.to_path_buf();
+
+        Ok(cache)
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        match StorageFormat::from_path(&self.path) {
+            // Other processes might be reading the cache, so it's never left truncated.
+            StorageFormat::Json => {
+                backup::replace_atomically(&self.path, &serde_json::to_vec(self)?)?
+            }
+            StorageFormat::Sqlite => self
+                .save_database()
+                .with_context(|| format!("Could not save the prices in {:?}", self.path))?,
+        }
+
+        self.unsaved.clear();
+        Ok(())
+    }
+
+    fn load_database(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(PRICES_SCHEMA)?;
+
+        let mut cache = Self::default();
+
+        let mut statement =
+            connection.prepare("SELECT symbol, price, last_price, fetched_at FROM quotes")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let quote = CachedQuote {
+                price: parse_decimal(&row.get::<_, String>(1)?)?,
+                last_price: parse_decimal(&row.get::<_, String>(2)?)?,
+                fetched_at: parse_datetime(&row.get::<_, String>(3)?)?,
+            };
+            cache.quotes.insert(row.get(0)?, quote);
+        }
+
+        let mut statement =
+            connection.prepare("SELECT symbol, date, close, fetched_at FROM prices")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let close = CachedClose {
+                close: row
+                    .get::<_, Option<String>>(2)?
+                    .as_deref()
+                    .map(parse_decimal)
+                    .transpose()?,
+                fetched_at: parse_datetime(&row.get::<_, String>(3)?)?,
+            };
+            cache
+                .closes
+                .entry(row.get(0)?)
+                .or_default()
+                .insert(row.get(1)?, close);
+        }
+
+        let mut statement = connection.prepare("SELECT currency, date, rate FROM fx_rates")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let code: String = row.get(0)?;
+            let currency =
+                Currency::from_code(&code).with_context(|| format!("Unknown currency `{code}`"))?;
+            cache
+                .fx_rates
+                .entry(currency)
+                .or_default()
+                .insert(row.get(1)?, parse_decimal(&row.get::<_, String>(2)?)?);
+        }
+
+        Ok(cache)
+    }
+
+    /// Writes the entries stored since the cache was loaded, the rest is already in the database.
+    fn save_database(&self) -> Result<()> {
+        let mut connection = Connection::open(&self.path)?;
+        connection.execute_batch(PRICES_SCHEMA)?;
+
+        let transaction = connection.transaction()?;
+        {
+            let mut insert_quote = transaction.prepare(
+                "INSERT OR REPLACE INTO quotes (symbol, price, last_price, fetched_at)
+                VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let mut insert_close = transaction.prepare(
+                "INSERT OR REPLACE INTO prices (symbol, date, close, fetched_at)
+                VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let mut insert_fx_rate = transaction.prepare(
+                "INSERT OR REPLACE INTO fx_rates (currency, date, rate) VALUES (?1, ?2, ?3)",
+            )?;
+
+            for entry in &self.unsaved {
+                match entry {
+                    CacheEntry::Quote(symbol) => {
+                        let quote = &self.quotes[symbol];
+                        insert_quote.execute(params![
+                            symbol,
+                            quote.price.to_string(),
+                            quote.last_price.to_string(),
+                            quote.fetched_at.format(&Rfc3339)?,
+                        ])?;
+                    }
+                    CacheEntry::Close(symbol, date) => {
+                        let close = &self.closes[symbol][date];
+                        insert_close.execute(params![
+                            symbol,
+                            date,
+                            close.close.map(|close| close.to_string()),
+                            close.fetched_at.format(&Rfc3339)?,
+                        ])?;
+                    }
+                    CacheEntry::FxRate(currency, date) => {
+                        insert_fx_rate.execute(params![
+                            currency.code(),
+                            date,
+                            self.fx_rates[currency][date].to_string(),
+                        ])?;
+                    }
+                }
+            }
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn quote(&self, symbol: &str) -> Option<&CachedQuote> {
+        self.quotes.get(symbol)
+    }
+
+    pub fn store_quote(&mut self, quote: &Quote, fetched_at: OffsetDateTime) {
+        self.quotes.insert(
+            quote.symbol.clone(),
+            CachedQuote {
+                price: quote.price,
+                last_price: quote.last_price,
+                fetched_at,
+            },
+        );
+        self.unsaved.insert(CacheEntry::Quote(quote.symbol.clone()));
+    }
+
+    pub fn fx_rate(&self, currency: Currency, date: Date) -> Option<Decimal> {
+        self.fx_rates
+            .get(&currency)?
+            .get(&date.to_string())
+            .copied()
+    }
+
+    /// The last rate of the `currency` cached on or before `date`, along with its date.
+    pub fn last_fx_rate(&self, currency: Currency, date: Date) -> Option<(Date, Decimal)> {
+        let (day, rate) = self
+            .fx_rates
+            .get(&currency)?
+            .range(..=date.to_string())
+            .next_back()?;
+
+        Some((parse_date(day).ok()?, *rate))
+    }
+
+    pub fn store_fx_rate(&mut self, currency: Currency, date: Date, rate: Decimal) {
+        self.fx_rates
+            .entry(currency)
+            .or_default()
+            .insert(date.to_string(), rate);
+        self.unsaved
+            .insert(CacheEntry::FxRate(currency, date.to_string()));
+    }
+
+    /// Stores the `history` fetched for the days between `from` and `to`. The days without a
+    /// closing price are stored as well, so we know that they were not trading days.
+    ///
+    /// NOTE: `to` must be before today, as today's closing price is not final yet.
+    pub fn store_history(
+        &mut self,
+        symbol: &str,
+        from: Date,
+        to: Date,
+        history: &[ClosingPrice],
+        fetched_at: OffsetDateTime,
+    ) {
+        let closes = self.closes.entry(symbol.to_string()).or_default();
+
+        let mut date = from;
+        while date <= to {
+            let close = history
+                .iter()
+                .find(|price| price.date == date)
+                .map(|price| price.close);
+
+            closes.insert(date.to_string(), CachedClose { close, fetched_at });
+            self.unsaved
+                .insert(CacheEntry::Close(symbol.to_string(), date.to_string()));
+
+            date = date.next_day().expect("BUG: Date out of range");
+        }
+    }
+
+    /// Looks for the closing price on (or before) `date` and the closing price of the previous
+    /// trading day, looking back at most `lookback` days.
+    pub fn closes(&self, symbol: &str, date: Date, lookback: Duration) -> Option<CachedCloses> {
+        let closes = self.closes.get(symbol)?;
+
+        let mut found = Vec::with_capacity(2);
+        let mut complete = true;
+
+        let mut day = date;
+        while found.len() < 2 && day >= date - lookback {
+            match closes.get(&day.to_string()) {
+                Some(cached) => {
+                    if let Some(close) = cached.close {
+                        found.push((close, cached.fetched_at));
+                    }
+                }
+                None => complete = false,
+            }
+
+            day = day.previous_day()?;
+        }
+
+        let (price, fetched_at) = *found.first()?;
+
+        Some(CachedCloses {
+            price,
+            last_price: found.get(1).map_or(price, |(close, _)| *close),
+            fetched_at,
+            complete: complete && found.len() == 2,
+        })
+    }
+}
+
+fn parse_decimal(text: &str) -> Result<Decimal> {
+    text.parse()
+        .with_context(|| format!("Invalid amount `{text}`"))
+}
+
+fn parse_date(text: &str) -> Result<Date> {
+    Date::parse(text, format_description!("[year]-[month]-[day]"))
+        .with_context(|| format!("Invalid date `{text}`"))
+}
+
+fn parse_datetime(text: &str) -> Result<OffsetDateTime> {
+    OffsetDateTime::parse(text, &Rfc3339).with_context(|| format!("Invalid datetime `{text}`"))
+}
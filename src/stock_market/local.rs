@@ -0,0 +1,92 @@
+use super::{ClosingPrice, FxProvider, PriceProvider, Quote};
+use crate::portfolio::Currency;
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use time::{Date, Duration};
+
+/// Serves prices from a local JSON file instead of the network, it's deterministic, so it's useful
+/// for tests and offline use.
+///
+/// The file maps each symbol to its closing prices in chronological order:
+///
+/// ```json
+/// { "BBAS3": [{ "date": "2022-12-29", "close": 35.8 }, { "date": "2022-12-30", "close": 36.03 }] }
+/// ```
+///
+/// The FX rates are listed in the same way, keyed by the currency code (e.g. `USD`). The prices are
+/// shared by the clones, so a single file serves both the stock prices and the FX rates.
+#[derive(Clone)]
+pub struct LocalPriceProvider {
+    prices: Arc<HashMap<String, Vec<ClosingPrice>>>,
+}
+
+impl LocalPriceProvider {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Could not open the prices file {path:?}"))?;
+        let mut prices: HashMap<String, Vec<ClosingPrice>> = serde_json::from_reader(file)
+            .with_context(|| format!("Could not parse the prices file {path:?}"))?;
+
+        for history in prices.values_mut() {
+            history.sort_by_key(|price| price.date);
+        }
+
+        Ok(Self {
+            prices: Arc::new(prices),
+        })
+    }
+}
+
+impl PriceProvider for LocalPriceProvider {
+    fn quote(&self, symbol: &str) -> Result<Quote> {
+        let history = self
+            .prices
+            .get(symbol)
+            .with_context(|| format!("No local prices for {symbol}"))?;
+
+        let price = history
+            .last()
+            .with_context(|| format!("No local prices for {symbol}"))?
+            .close;
+
+        // With a single price available there is no change to report.
+        let last_price = history
+            .len()
+            .checked_sub(2)
+            .map_or(price, |index| history[index].close);
+
+        Ok(Quote {
+            symbol: symbol.to_string(),
+            price,
+            last_price,
+        })
+    }
+
+    fn history(&self, symbol: &str, from: Date, to: Date) -> Result<Vec<ClosingPrice>> {
+        let history = self
+            .prices
+            .get(symbol)
+            .with_context(|| format!("No local prices for {symbol}"))?;
+
+        Ok(history
+            .iter()
+            .filter(|price| (from..=to).contains(&price.date))
+            .cloned()
+            .collect())
+    }
+}
+
+impl FxProvider for LocalPriceProvider {
+    fn fx_rate(&self, currency: Currency, date: Date, lookback: Duration) -> Result<Decimal> {
+        let rate = self
+            .history(currency.code(), date - lookback, date)?
+            .last()
+            .with_context(|| format!("No local {currency} rate on or before {date}"))?
+            .close;
+
+        Ok(rate)
+    }
+}
@@ -0,0 +1,110 @@
+use super::{ClosingPrice, PriceProvider, Quote};
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use time::{format_description, Date, OffsetDateTime};
+use ureq::Agent;
+
+const API_URL: &str = "https://mfinance.com.br/api/v1/stocks";
+
+/// Fetches real stock prices from the `MFinance` API.
+pub struct MFinance {
+    client: Agent,
+}
+
+/// The complete response from the `MFinance` API.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MFinanceResponse {
+    pub change: Decimal,
+    pub closing_price: Decimal,
+    pub eps: f64,
+    pub high: Decimal,
+    pub last_price: Decimal,
+    pub last_year_high: Decimal,
+    pub last_year_low: Decimal,
+    pub low: Decimal,
+    pub market_cap: f64,
+    pub name: String,
+    pub pe: f64,
+    pub price_open: Decimal,
+    pub sector: String,
+    pub segment: String,
+    pub shares: f64,
+    pub sub_sector: String,
+    pub symbol: String,
+    pub volume: f64,
+    pub volume_avg: f64,
+}
+
+/// The response from the `MFinance` historicals API, only the fields that we use are listed.
+#[derive(Deserialize)]
+struct MFinanceHistoricalsResponse {
+    historicals: Vec<MFinanceHistorical>,
+}
+
+#[derive(Deserialize)]
+struct MFinanceHistorical {
+    /// The date is formatted as `YYYY-MM-DDT00:00:00`.
+    date: String,
+    close: Decimal,
+}
+
+impl MFinance {
+    pub fn new() -> Self {
+        Self {
+            client: Agent::new(),
+        }
+    }
+}
+
+impl PriceProvider for MFinance {
+    fn quote(&self, symbol: &str) -> Result<Quote> {
+        let response: MFinanceResponse = self
+            .client
+            .get(format!("{API_URL}/{symbol}").as_str())
+            .call()?
+            .into_json()?;
+
+        Ok(Quote {
+            symbol: response.symbol,
+            price: response.last_price,
+            last_price: response.closing_price,
+        })
+    }
+
+    fn history(&self, symbol: &str, from: Date, to: Date) -> Result<Vec<ClosingPrice>> {
+        // The API only allows us to query by the number of months before today.
+        let today = OffsetDateTime::now_utc().date();
+        let months = (today - from).whole_days() / 30 + 1;
+
+        let response: MFinanceHistoricalsResponse = self
+            .client
+            .get(format!("{API_URL}/historicals/{symbol}").as_str())
+            .query("months", &months.to_string())
+            .call()?
+            .into_json()?;
+
+        let date_format = format_description::parse_borrowed::<1>("[year]-[month]-[day]")?;
+
+        let mut history = Vec::with_capacity(response.historicals.len());
+        for historical in response.historicals {
+            let date = historical
+                .date
+                .get(..10)
+                .with_context(|| format!("Invalid date `{}`", historical.date))?;
+            let date = Date::parse(date, &date_format)?;
+
+            if (from..=to).contains(&date) {
+                history.push(ClosingPrice {
+                    date,
+                    close: historical.close,
+                });
+            }
+        }
+
+        history.sort_by_key(|price| price.date);
+
+        Ok(history)
+    }
+}
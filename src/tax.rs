@@ -0,0 +1,600 @@
+mod declaration;
+
+pub use declaration::{
+    Asset, DeclaredIncome, TaxDeclaration, DIVIDENDS_CODE, EXEMPT_SALES_CODE, JCP_CODE,
+    OTHER_EXEMPT_INCOME_CODE,
+};
+
+use super::calendar::{last_business_day, last_day};
+use super::money::{prorate, round_money};
+use super::portfolio::{AssetClass, MonthSummary, Portfolio, TradeRegime};
+use anyhow::Result;
+use rust_decimal::{dec, Decimal};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use time::{Date, Month};
+
+/// The income tax rate over the swing trade profits.
+pub const SWING_TRADE_RATE: Decimal = dec!(0.15);
+/// The income tax rate over the day trade profits.
+pub const DAY_TRADE_RATE: Decimal = dec!(0.20);
+/// The income tax rate over the FII profits, both swing trades and day trades.
+pub const FII_RATE: Decimal = dec!(0.20);
+/// The swing trade profits of stocks and units are exempt in the months where their sold amount
+/// doesn't exceed it, there is no exemption for day trades nor for the other asset classes.
+pub const EXEMPTION_LIMIT: Decimal = dec!(20000);
+
+/// The receita code of the DARF for the income tax over the stock market profits.
+pub const DARF_RECEITA_CODE: &str = "6015";
+/// A DARF can't be emitted for less than it.
+pub const MINIMUM_DARF: Decimal = dec!(10);
+/// The late payment fine per day of delay.
+pub const LATE_FINE_DAILY_RATE: Decimal = dec!(0.0033);
+/// The late payment fine is limited to it.
+pub const LATE_FINE_MAX_RATE: Decimal = dec!(0.20);
+/// The interest of the payment month, in percentage, the previous months use the Selic rate.
+pub const PAYMENT_MONTH_INTEREST: Decimal = dec!(1);
+
+/// The document used to pay the tax (Documento de Arrecadação de Receitas Federais).
+#[derive(Serialize)]
+pub struct Darf {
+    pub receita_code: &'static str,
+    /// Período de apuração, the last day of the month of the taxed profits.
+    #[serde(with = "crate::serde_date")]
+    pub period: Date,
+    /// The last business day of the month after the period.
+    #[serde(with = "crate::serde_date")]
+    pub due_date: Date,
+    #[serde(with = "crate::serde_date")]
+    pub payment_date: Date,
+    pub principal: Decimal,
+    pub fine: Decimal,
+    pub interest: Decimal,
+    pub total: Decimal,
+    /// The tax below the minimum DARF value, it's carried to the next month.
+    pub carried_tax: Decimal,
+}
+
+/// The tax due in a month, each regime has its own losses.
+#[derive(Default)]
+pub struct MonthTax {
+    pub swing_trade: RegimeTax,
+    pub day_trade: RegimeTax,
+}
+
+/// The tax due in a month for a single regime, after offsetting the losses carried from the
+/// previous months.
+#[derive(Default)]
+pub struct RegimeTax {
+    pub sold_amount: Decimal,
+    pub profit: Decimal,
+    /// The loss from previous months used to reduce the taxable profit.
+    pub offset_loss: Decimal,
+    /// The income tax withheld at source in the month.
+    pub withheld_tax: Decimal,
+    /// The tax due after deducting the withheld tax.
+    pub tax: Decimal,
+    /// The accumulated loss carried to the next months.
+    pub loss_balance: Decimal,
+    /// The withheld tax that exceeded the tax due, it's deducted in the next months.
+    pub withheld_balance: Decimal,
+}
+
+/// Keeps track of the accumulated losses (compensação de prejuízos), they never expire and offset
+/// the taxable profits of the following months, even across years. The same goes for the withheld
+/// tax that couldn't be deducted yet.
+#[derive(Default)]
+struct LossLedger {
+    loss: Decimal,
+    withheld_tax: Decimal,
+}
+
+/// The losses of swing trades and day trades can only offset profits of the same regime.
+#[derive(Default)]
+struct TaxLedger {
+    swing_trade: LossLedger,
+    day_trade: LossLedger,
+}
+
+/// The asset classes that offset each other's losses. The ordinary market operations (stocks,
+/// units, ETFs and BDRs) share their losses, while the FIIs can only offset their own.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+enum LedgerGroup {
+    Ordinary,
+    Fii,
+}
+
+impl LossLedger {
+    fn month(
+        &mut self,
+        sold_amount: Decimal,
+        profit: Decimal,
+        withheld_tax: Decimal,
+        rate: Decimal,
+        exempt: bool,
+    ) -> RegimeTax {
+        let mut offset_loss = Decimal::ZERO;
+        let mut tax = Decimal::ZERO;
+
+        if profit < Decimal::ZERO {
+            // Losses are accumulated even in the exempt months.
+            self.loss -= profit;
+        } else if !exempt {
+            offset_loss = self.loss.min(profit);
+            self.loss -= offset_loss;
+            tax = round_money((profit - offset_loss) * rate);
+        }
+
+        self.withheld_tax += withheld_tax;
+        let deducted_tax = self.withheld_tax.min(tax);
+        self.withheld_tax -= deducted_tax;
+
+        RegimeTax {
+            sold_amount,
+            profit,
+            offset_loss,
+            withheld_tax,
+            tax: tax - deducted_tax,
+            loss_balance: self.loss,
+            withheld_balance: self.withheld_tax,
+        }
+    }
+}
+
+impl TaxLedger {
+    fn month(
+        &mut self,
+        summary: &MonthSummary,
+        class: AssetClass,
+        regime: TradeRegime,
+        exempt: bool,
+    ) -> RegimeTax {
+        let (swing_trade_rate, day_trade_rate) = match class {
+            AssetClass::Fii => (FII_RATE, FII_RATE),
+            _ => (SWING_TRADE_RATE, DAY_TRADE_RATE),
+        };
+
+        match regime {
+            TradeRegime::SwingTrade => self.swing_trade.month(
+                summary.sold_amount,
+                summary.profit,
+                summary.withheld_tax,
+                swing_trade_rate,
+                exempt,
+            ),
+            TradeRegime::DayTrade => self.day_trade.month(
+                summary.day_trade_sold_amount,
+                summary.day_trade_profit,
+                summary.day_trade_withheld_tax,
+                day_trade_rate,
+                false,
+            ),
+        }
+    }
+}
+
+impl LedgerGroup {
+    fn of(class: AssetClass) -> Self {
+        match class {
+            AssetClass::Fii => LedgerGroup::Fii,
+            AssetClass::Stock | AssetClass::Unit | AssetClass::Etf | AssetClass::Bdr => {
+                LedgerGroup::Ordinary
+            }
+        }
+    }
+}
+
+impl Darf {
+    /// The months of the Selic interest when paying late, from the month after the due date until
+    /// the month before the payment. It's `None` when there is no Selic interest.
+    pub fn selic_period(&self, payment_date: Date) -> Option<(Date, Date)> {
+        let from = last_day(self.due_date.year(), self.due_date.month()).next_day()?;
+        let to = payment_date.replace_day(1).ok()?.previous_day()?;
+
+        (from <= to).then_some((from, to))
+    }
+
+    /// Adds the late payment fine (0.33% per day, up to 20%) and interest (the accumulated Selic
+    /// rate, in percentage, of the months after the due date plus 1% in the payment month).
+    pub fn pay_late(&mut self, payment_date: Date, accumulated_selic: Decimal) {
+        self.payment_date = payment_date;
+
+        let days_late = (payment_date - self.due_date).whole_days();
+        if days_late <= 0 {
+            return;
+        }
+
+        let fine_rate = (Decimal::from(days_late) * LATE_FINE_DAILY_RATE).min(LATE_FINE_MAX_RATE);
+        let paid_in_due_month = (payment_date.year(), payment_date.month())
+            == (self.due_date.year(), self.due_date.month());
+        let interest_rate = if paid_in_due_month {
+            Decimal::ZERO
+        } else {
+            accumulated_selic + PAYMENT_MONTH_INTEREST
+        };
+
+        self.fine = round_money(self.principal * fine_rate);
+        self.interest = prorate(self.principal, interest_rate, Decimal::ONE_HUNDRED);
+        self.total = self.principal + self.fine + self.interest;
+    }
+}
+
+impl MonthTax {
+    pub fn tax(&self) -> Decimal {
+        self.swing_trade.tax + self.day_trade.tax
+    }
+}
+
+/// Calculates the tax due in each month of the `year`, carrying the losses since the first year of
+/// the portfolio.
+pub fn tax_by_month(
+    portfolio: &Portfolio,
+    year: i32,
+) -> Result<BTreeMap<AssetClass, [MonthTax; 12]>> {
+    Ok(taxes_until(portfolio, year)?.pop().unwrap_or_default())
+}
+
+/// Calculates the DARF of the given month. The tax below the minimum DARF value is not paid, but
+/// carried to the following months until it reaches the minimum.
+pub fn darf(portfolio: &Portfolio, year: i32, month: Month) -> Result<Darf> {
+    let taxes = taxes_until(portfolio, year)?;
+    // Every month until the given one (inclusive).
+    let months = (taxes.len() - 1) * 12 + month as usize;
+
+    let mut carried_tax = Decimal::ZERO;
+    let mut principal = Decimal::ZERO;
+
+    let month_taxes = taxes.iter().flat_map(|taxes| {
+        (0..12).map(|month| taxes.values().map(|tax| tax[month].tax()).sum::<Decimal>())
+    });
+
+    for month_tax in month_taxes.take(months) {
+        let tax = carried_tax + month_tax;
+
+        if tax >= MINIMUM_DARF {
+            principal = tax;
+            carried_tax = Decimal::ZERO;
+        } else {
+            principal = Decimal::ZERO;
+            carried_tax = tax;
+        }
+    }
+
+    let period = last_day(year, month);
+    let due_date = period
+        .next_day()
+        .map(|date| last_business_day(date.year(), date.month()))
+        .expect("BUG: Date out of range");
+
+    Ok(Darf {
+        receita_code: DARF_RECEITA_CODE,
+        period,
+        due_date,
+        payment_date: due_date,
+        principal,
+        fine: Decimal::ZERO,
+        interest: Decimal::ZERO,
+        total: principal,
+        carried_tax,
+    })
+}
+
+/// The taxes of each year since the first year of the portfolio until `year` (inclusive).
+fn taxes_until(
+    portfolio: &Portfolio,
+    year: i32,
+) -> Result<Vec<BTreeMap<AssetClass, [MonthTax; 12]>>> {
+    let mut ledgers: HashMap<LedgerGroup, TaxLedger> = HashMap::new();
+
+    let first_year = portfolio.first_year().unwrap_or(year).min(year);
+
+    (first_year..=year)
+        .map(|year| {
+            let profit_by_month = portfolio.profit_by_month(year)?;
+            let exempt_sold_amount = exempt_sold_amount(&profit_by_month);
+
+            let mut taxes: BTreeMap<AssetClass, [MonthTax; 12]> = profit_by_month
+                .keys()
+                .map(|class| (*class, Default::default()))
+                .collect();
+            let mut classes: Vec<AssetClass> = profit_by_month.keys().copied().collect();
+
+            for month in 0..12 {
+                for regime in [TradeRegime::SwingTrade, TradeRegime::DayTrade] {
+                    // The losses of the month offset the profits of the other classes in the same
+                    // month, so they are accounted first.
+                    classes.sort_by_key(|class| {
+                        regime_profit(&profit_by_month[class][month], regime) >= Decimal::ZERO
+                    });
+
+                    for class in &classes {
+                        let ledger = ledgers.entry(LedgerGroup::of(*class)).or_default();
+                        let exempt =
+                            has_exemption(*class) && exempt_sold_amount[month] <= EXEMPTION_LIMIT;
+
+                        let tax =
+                            ledger.month(&profit_by_month[class][month], *class, regime, exempt);
+                        let month_tax =
+                            &mut taxes.get_mut(class).expect("BUG: Class should exist")[month];
+                        match regime {
+                            TradeRegime::SwingTrade => month_tax.swing_trade = tax,
+                            TradeRegime::DayTrade => month_tax.day_trade = tax,
+                        }
+                    }
+                }
+            }
+
+            Ok(taxes)
+        })
+        .collect()
+}
+
+fn regime_profit(summary: &MonthSummary, regime: TradeRegime) -> Decimal {
+    match regime {
+        TradeRegime::SwingTrade => summary.profit,
+        TradeRegime::DayTrade => summary.day_trade_profit,
+    }
+}
+
+/// Only the stocks and units have the swing trade exemption.
+fn has_exemption(class: AssetClass) -> bool {
+    matches!(class, AssetClass::Stock | AssetClass::Unit)
+}
+
+/// The swing trade sold amount of the asset classes with the exemption in each month, the
+/// exemption limit considers all of them together.
+fn exempt_sold_amount(profit_by_month: &BTreeMap<AssetClass, [MonthSummary; 12]>) -> [Decimal; 12] {
+    std::array::from_fn(|month| {
+        profit_by_month
+            .iter()
+            .filter(|(class, _)| has_exemption(**class))
+            .map(|(_, summaries)| summaries[month].sold_amount)
+            .sum()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::{Fees, TradeDetails};
+    use std::path::Path;
+    use time::macros::{date, datetime};
+    use time::OffsetDateTime;
+
+    fn swing_trade(sold_amount: Decimal, profit: Decimal) -> MonthSummary {
+        MonthSummary {
+            sold_amount,
+            profit,
+            ..MonthSummary::default()
+        }
+    }
+
+    /// A portfolio buying `quantity` shares at 10 the day before each of the `sales`, which sell
+    /// them at `price`.
+    fn portfolio_selling(quantity: u32, price: Decimal, sales: &[OffsetDateTime]) -> Portfolio {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+
+        for sale in sales {
+            portfolio.buy(
+                "ITSA4",
+                quantity,
+                dec!(10),
+                Fees::default(),
+                TradeDetails::default(),
+                *sale - time::Duration::days(1),
+            );
+            portfolio
+                .sell(
+                    "ITSA4",
+                    quantity,
+                    price,
+                    Fees::default(),
+                    TradeDetails::default(),
+                    *sale,
+                )
+                .expect("The sale should be valid");
+        }
+
+        portfolio
+    }
+
+    #[test]
+    fn darf_is_due_on_the_last_business_day_of_the_next_month() {
+        let portfolio = portfolio_selling(3000, dec!(11), &[datetime!(2024-03-04 10:00 UTC)]);
+
+        let darf = darf(&portfolio, 2024, Month::March).expect("The DARF should be valid");
+
+        assert_eq!(darf.period, date!(2024 - 03 - 31));
+        assert_eq!(darf.due_date, date!(2024 - 04 - 30));
+        // 15% of the 3000 profit minus the 1.65 withheld at source.
+        assert_eq!(darf.principal, dec!(448.35));
+        assert_eq!(darf.total, darf.principal);
+        assert_eq!(darf.carried_tax, Decimal::ZERO);
+    }
+
+    #[test]
+    fn darf_carries_the_tax_below_the_minimum() {
+        let portfolio = portfolio_selling(
+            2100,
+            dec!(10.02),
+            &[
+                datetime!(2024-03-04 10:00 UTC),
+                datetime!(2024-04-04 10:00 UTC),
+            ],
+        );
+
+        let march = darf(&portfolio, 2024, Month::March).expect("The DARF should be valid");
+        assert_eq!(march.principal, Decimal::ZERO);
+        assert_eq!(march.carried_tax, dec!(5.25));
+
+        let april = darf(&portfolio, 2024, Month::April).expect("The DARF should be valid");
+        assert_eq!(april.principal, dec!(10.50));
+        assert_eq!(april.carried_tax, Decimal::ZERO);
+    }
+
+    #[test]
+    fn pay_late_adds_the_fine_and_the_selic_interest() {
+        let portfolio = portfolio_selling(3000, dec!(11), &[datetime!(2024-03-04 10:00 UTC)]);
+        let mut darf = darf(&portfolio, 2024, Month::March).expect("The DARF should be valid");
+
+        assert_eq!(
+            darf.selic_period(date!(2024 - 06 - 10)),
+            Some((date!(2024 - 05 - 01), date!(2024 - 05 - 31)))
+        );
+        darf.pay_late(date!(2024 - 06 - 10), dec!(0.83));
+
+        // 41 days late at 0.33% per day, and 0.83% of Selic plus 1% of interest.
+        assert_eq!(darf.fine, dec!(60.66));
+        assert_eq!(darf.interest, dec!(8.20));
+        assert_eq!(darf.total, dec!(517.21));
+    }
+
+    #[test]
+    fn pay_late_limits_the_fine_and_skips_the_interest_in_the_due_month() {
+        let portfolio = portfolio_selling(3000, dec!(11), &[datetime!(2024-03-04 10:00 UTC)]);
+        let mut darf = darf(&portfolio, 2024, Month::March).expect("The DARF should be valid");
+
+        assert_eq!(darf.selic_period(date!(2024 - 04 - 30)), None);
+        darf.pay_late(date!(2024 - 04 - 30), Decimal::ZERO);
+        assert_eq!(darf.total, darf.principal);
+
+        darf.pay_late(date!(2025 - 01 - 10), dec!(7.5));
+        assert_eq!(darf.fine, dec!(89.67));
+    }
+
+    #[test]
+    fn month_offsets_the_loss_of_the_previous_months() {
+        let mut ledger = TaxLedger::default();
+
+        let loss = ledger.month(
+            &swing_trade(dec!(30000), dec!(-1000)),
+            AssetClass::Stock,
+            TradeRegime::SwingTrade,
+            false,
+        );
+        assert_eq!(loss.tax, Decimal::ZERO);
+        assert_eq!(loss.loss_balance, dec!(1000));
+
+        let profit = ledger.month(
+            &swing_trade(dec!(30000), dec!(3000)),
+            AssetClass::Stock,
+            TradeRegime::SwingTrade,
+            false,
+        );
+        assert_eq!(profit.offset_loss, dec!(1000));
+        assert_eq!(profit.tax, dec!(300));
+        assert_eq!(profit.loss_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn month_keeps_the_loss_through_the_exempt_months() {
+        let mut ledger = TaxLedger::default();
+
+        let loss = ledger.month(
+            &swing_trade(dec!(10000), dec!(-500)),
+            AssetClass::Stock,
+            TradeRegime::SwingTrade,
+            true,
+        );
+        assert_eq!(loss.loss_balance, dec!(500));
+
+        let exempt = ledger.month(
+            &swing_trade(dec!(10000), dec!(1000)),
+            AssetClass::Stock,
+            TradeRegime::SwingTrade,
+            true,
+        );
+        assert_eq!(exempt.offset_loss, Decimal::ZERO);
+        assert_eq!(exempt.tax, Decimal::ZERO);
+        assert_eq!(exempt.loss_balance, dec!(500));
+    }
+
+    #[test]
+    fn month_carries_the_withheld_tax_exceeding_the_tax() {
+        let mut ledger = TaxLedger::default();
+        let summary = MonthSummary {
+            withheld_tax: dec!(200),
+            ..swing_trade(dec!(30000), dec!(1000))
+        };
+
+        let withheld = ledger.month(&summary, AssetClass::Stock, TradeRegime::SwingTrade, false);
+        assert_eq!(withheld.tax, Decimal::ZERO);
+        assert_eq!(withheld.withheld_balance, dec!(50));
+
+        let next = ledger.month(
+            &swing_trade(dec!(30000), dec!(1000)),
+            AssetClass::Stock,
+            TradeRegime::SwingTrade,
+            false,
+        );
+        assert_eq!(next.tax, dec!(100));
+        assert_eq!(next.withheld_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn month_taxes_the_day_trades_apart() {
+        let mut ledger = TaxLedger::default();
+        let summary = MonthSummary {
+            day_trade_sold_amount: dec!(5000),
+            day_trade_profit: dec!(1000),
+            day_trade_withheld_tax: dec!(10),
+            ..swing_trade(dec!(10000), dec!(-1000))
+        };
+
+        let swing_trade = ledger.month(&summary, AssetClass::Stock, TradeRegime::SwingTrade, true);
+        assert_eq!(swing_trade.loss_balance, dec!(1000));
+
+        // The day trades are never exempt and the swing trade losses don't offset them.
+        let day_trade = ledger.month(&summary, AssetClass::Stock, TradeRegime::DayTrade, true);
+        assert_eq!(day_trade.offset_loss, Decimal::ZERO);
+        assert_eq!(day_trade.tax, dec!(190));
+        assert_eq!(day_trade.loss_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn month_taxes_the_fiis_at_their_rate() {
+        let mut ledger = TaxLedger::default();
+
+        let fii = ledger.month(
+            &swing_trade(dec!(5000), dec!(1000)),
+            AssetClass::Fii,
+            TradeRegime::SwingTrade,
+            false,
+        );
+        assert_eq!(fii.tax, dec!(200));
+    }
+
+    #[test]
+    fn tax_by_month_keeps_the_fii_losses_apart() {
+        let mut portfolio = portfolio_selling(3000, dec!(11), &[datetime!(2024-03-04 10:00 UTC)]);
+        portfolio.buy(
+            "HGLG11",
+            100,
+            dec!(160),
+            Fees::default(),
+            TradeDetails::default(),
+            datetime!(2024-02-01 10:00 UTC),
+        );
+        portfolio
+            .set_asset_class("HGLG11", Some(AssetClass::Fii))
+            .expect("The stock should exist");
+        portfolio
+            .sell(
+                "HGLG11",
+                100,
+                dec!(150),
+                Fees::default(),
+                TradeDetails::default(),
+                datetime!(2024-03-04 11:00 UTC),
+            )
+            .expect("The sale should be valid");
+
+        let taxes = tax_by_month(&portfolio, 2024).expect("The taxes should be valid");
+
+        let stock = &taxes[&AssetClass::Stock][2].swing_trade;
+        assert_eq!(stock.offset_loss, Decimal::ZERO);
+        assert_eq!(stock.tax, dec!(448.35));
+        let fii = &taxes[&AssetClass::Fii][2].swing_trade;
+        assert_eq!(fii.loss_balance, dec!(1000));
+    }
+}
@@ -0,0 +1,367 @@
+use super::{exempt_sold_amount, has_exemption, EXEMPTION_LIMIT};
+use crate::money::average_price;
+use crate::portfolio::{AssetClass, IncomeKind, Portfolio, Stock};
+use anyhow::Result;
+use rust_decimal::Decimal;
+use time::{Date, Month, OffsetDateTime};
+
+/// The Rendimentos Isentos code of the dividends.
+pub const DIVIDENDS_CODE: &str = "09";
+/// The Rendimentos Isentos code of the other exempt income, which includes the FII income.
+pub const OTHER_EXEMPT_INCOME_CODE: &str = "99";
+/// The Rendimentos Isentos code of the swing trade profits in the months with sales up to R$ 20k.
+pub const EXEMPT_SALES_CODE: &str = "20";
+/// The Tributação Exclusiva code of the JCP.
+pub const JCP_CODE: &str = "10";
+
+/// What the annual income tax declaration (IRPF) needs from the portfolio in a given year.
+pub struct TaxDeclaration {
+    /// Bens e Direitos, the stocks held at the end of the year or of the previous year.
+    pub assets: Vec<Asset>,
+    /// Rendimentos Isentos e Não Tributáveis, the dividends (or FII income) paid by each stock.
+    pub dividends: Vec<DeclaredIncome>,
+    /// Rendimentos Isentos e Não Tributáveis, the swing trade profits of stocks and units in the
+    /// exempt months.
+    pub exempt_profit: Decimal,
+    /// Rendimentos Sujeitos à Tributação Exclusiva, the net JCP paid by each stock.
+    pub jcp: Vec<DeclaredIncome>,
+}
+
+/// An entry of the Bens e Direitos section.
+pub struct Asset {
+    pub group: &'static str,
+    pub code: &'static str,
+    pub class: AssetClass,
+    pub symbol: String,
+    pub cnpj: Option<String>,
+    /// The shares held at the end of the year.
+    pub quantity: u32,
+    /// The total cost at the end of the previous year.
+    pub previous_cost: Decimal,
+    /// The total cost at the end of the year.
+    pub cost: Decimal,
+}
+
+/// The income paid by a single stock in the year.
+pub struct DeclaredIncome {
+    pub code: &'static str,
+    pub symbol: String,
+    pub cnpj: Option<String>,
+    pub amount: Decimal,
+}
+
+impl TaxDeclaration {
+    pub fn new(portfolio: &Portfolio, year: i32) -> Result<Self> {
+        let mut stocks: Vec<&Stock> = portfolio.stocks.values().collect();
+        stocks.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+        let end_of_year = start_of_year(year + 1);
+        let end_of_previous_year = start_of_year(year);
+
+        let mut assets = vec![];
+        for stock in &stocks {
+            let position = stock.position(portfolio, end_of_year)?;
+            let previous_position = stock.position(portfolio, end_of_previous_year)?;
+
+            if position.quantity == 0 && previous_position.quantity == 0 {
+                continue;
+            }
+
+            let class = stock.class()?;
+            let (group, code) = asset_code(class);
+
+            assets.push(Asset {
+                group,
+                code,
+                class,
+                symbol: stock.symbol.clone(),
+                cnpj: stock.cnpj.clone(),
+                quantity: position.quantity,
+                previous_cost: previous_position.cost,
+                cost: position.cost,
+            });
+        }
+
+        let profit_by_month = portfolio.profit_by_month(year)?;
+        let exempt_sold_amount = exempt_sold_amount(&profit_by_month);
+
+        let exempt_profit = (0..12)
+            .filter(|month| exempt_sold_amount[*month] <= EXEMPTION_LIMIT)
+            .map(|month| {
+                profit_by_month
+                    .iter()
+                    .filter(|(class, _)| has_exemption(**class))
+                    .map(|(_, summaries)| summaries[month].profit)
+                    .sum::<Decimal>()
+            })
+            .filter(|profit| *profit > Decimal::ZERO)
+            .sum();
+
+        Ok(Self {
+            assets,
+            dividends: incomes(&stocks, year, IncomeKind::Dividend)?,
+            exempt_profit,
+            jcp: incomes(&stocks, year, IncomeKind::Jcp)?,
+        })
+    }
+}
+
+impl Asset {
+    /// The discriminação of the asset.
+    pub fn description(&self) -> String {
+        let average_price = average_price(self.cost, Decimal::from(self.quantity));
+
+        let shares = match self.class {
+            AssetClass::Stock => "ações",
+            AssetClass::Unit => "units",
+            AssetClass::Etf | AssetClass::Fii => "cotas",
+            AssetClass::Bdr => "BDRs",
+        };
+
+        format!(
+            "{} {shares} de {}, CNPJ {}, ao custo médio de R$ {:.2}",
+            self.quantity,
+            self.symbol,
+            self.cnpj.as_deref().unwrap_or("desconhecido"),
+            average_price.round_dp(2),
+        )
+    }
+}
+
+/// The net income of the given kind paid by each stock in the year, it's declared by the payer.
+fn incomes(stocks: &[&Stock], year: i32, kind: IncomeKind) -> Result<Vec<DeclaredIncome>> {
+    stocks
+        .iter()
+        .filter_map(|stock| {
+            let incomes: Vec<Decimal> = stock
+                .incomes_in(year)
+                .filter(|income| income.kind == kind)
+                .map(|income| income.net_amount())
+                .collect();
+
+            if incomes.is_empty() {
+                return None;
+            }
+
+            let code = match kind {
+                IncomeKind::Jcp => JCP_CODE,
+                IncomeKind::Dividend => match stock.class() {
+                    Ok(AssetClass::Fii) => OTHER_EXEMPT_INCOME_CODE,
+                    Ok(_) => DIVIDENDS_CODE,
+                    Err(err) => return Some(Err(err)),
+                },
+            };
+
+            Some(Ok(DeclaredIncome {
+                code,
+                symbol: stock.symbol.clone(),
+                cnpj: stock.cnpj.clone(),
+                amount: incomes.iter().sum(),
+            }))
+        })
+        .collect()
+}
+
+/// The group and code of the asset class in the Bens e Direitos section.
+fn asset_code(class: AssetClass) -> (&'static str, &'static str) {
+    match class {
+        // Participações Societárias, ações (inclusive listadas em bolsa).
+        AssetClass::Stock | AssetClass::Unit => ("03", "01"),
+        // Aplicações e Investimentos, ativos negociados em bolsa no Brasil.
+        AssetClass::Bdr => ("04", "04"),
+        // Fundos, fundos de investimento imobiliário.
+        AssetClass::Fii => ("07", "01"),
+        // Fundos, demais fundos.
+        AssetClass::Etf => ("07", "09"),
+    }
+}
+
+/// The positions are replayed until (but excluding) this moment.
+fn start_of_year(year: i32) -> OffsetDateTime {
+    Date::from_calendar_date(year, Month::January, 1)
+        .expect("BUG: Should be a valid date")
+        .midnight()
+        .assume_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::{Fees, Income, TradeDetails};
+    use rust_decimal::dec;
+    use std::path::Path;
+    use time::macros::{date, datetime};
+
+    fn income(
+        kind: IncomeKind,
+        amount: Decimal,
+        withheld_tax: Decimal,
+        payment_date: Date,
+    ) -> Income {
+        Income {
+            kind,
+            amount,
+            withheld_tax,
+            ex_date: payment_date - time::Duration::days(10),
+            payment_date,
+        }
+    }
+
+    fn portfolio() -> Portfolio {
+        let mut portfolio = Portfolio::new(Path::new("portfolio.json"));
+        let buy = |portfolio: &mut Portfolio, symbol, quantity, price, datetime| {
+            portfolio.buy(
+                symbol,
+                quantity,
+                price,
+                Fees::default(),
+                TradeDetails::default(),
+                datetime,
+            )
+        };
+
+        buy(
+            &mut portfolio,
+            "ITSA4",
+            100,
+            dec!(10),
+            datetime!(2023-05-02 10:00 UTC),
+        );
+        buy(
+            &mut portfolio,
+            "ITSA4",
+            50,
+            dec!(12),
+            datetime!(2024-02-01 10:00 UTC),
+        );
+        buy(
+            &mut portfolio,
+            "HGLG11",
+            10,
+            dec!(160),
+            datetime!(2024-02-01 10:00 UTC),
+        );
+        buy(
+            &mut portfolio,
+            "BBAS3",
+            10,
+            dec!(20),
+            datetime!(2022-02-01 10:00 UTC),
+        );
+        for (symbol, quantity, price, datetime) in [
+            ("ITSA4", 30, dec!(15), datetime!(2024-06-03 10:00 UTC)),
+            ("BBAS3", 10, dec!(25), datetime!(2022-06-01 10:00 UTC)),
+        ] {
+            portfolio
+                .sell(
+                    symbol,
+                    quantity,
+                    price,
+                    Fees::default(),
+                    TradeDetails::default(),
+                    datetime,
+                )
+                .expect("The sale should be valid");
+        }
+
+        portfolio
+            .set_cnpj("ITSA4", "61.532.644/0001-15")
+            .expect("The stock should exist");
+        portfolio
+            .set_asset_class("HGLG11", Some(AssetClass::Fii))
+            .expect("The stock should exist");
+        for (symbol, income) in [
+            (
+                "ITSA4",
+                income(
+                    IncomeKind::Dividend,
+                    dec!(20),
+                    Decimal::ZERO,
+                    date!(2024 - 03 - 01),
+                ),
+            ),
+            (
+                "ITSA4",
+                income(IncomeKind::Jcp, dec!(100), dec!(15), date!(2024 - 08 - 30)),
+            ),
+            (
+                "HGLG11",
+                income(
+                    IncomeKind::Dividend,
+                    dec!(15),
+                    Decimal::ZERO,
+                    date!(2024 - 04 - 15),
+                ),
+            ),
+            (
+                "ITSA4",
+                income(
+                    IncomeKind::Dividend,
+                    dec!(7),
+                    Decimal::ZERO,
+                    date!(2023 - 12 - 01),
+                ),
+            ),
+        ] {
+            portfolio
+                .add_income(symbol, income)
+                .expect("The stock should exist");
+        }
+
+        portfolio
+    }
+
+    #[test]
+    fn new_declares_the_assets_held_in_either_year() {
+        let declaration =
+            TaxDeclaration::new(&portfolio(), 2024).expect("The declaration should be valid");
+
+        let symbols: Vec<&str> = declaration
+            .assets
+            .iter()
+            .map(|asset| asset.symbol.as_str())
+            .collect();
+        assert_eq!(symbols, ["HGLG11", "ITSA4"]);
+
+        let fii = &declaration.assets[0];
+        assert_eq!((fii.group, fii.code), ("07", "01"));
+        assert_eq!(fii.previous_cost, Decimal::ZERO);
+        assert_eq!(fii.cost, dec!(1600));
+
+        let stock = &declaration.assets[1];
+        assert_eq!((stock.group, stock.code), ("03", "01"));
+        assert_eq!(stock.quantity, 120);
+        assert_eq!(stock.previous_cost, dec!(1000));
+        assert_eq!(stock.cost, dec!(1280));
+        assert_eq!(
+            stock.description(),
+            "120 ações de ITSA4, CNPJ 61.532.644/0001-15, ao custo médio de R$ 10.67"
+        );
+    }
+
+    #[test]
+    fn new_declares_the_income_and_the_exempt_profit_of_the_year() {
+        let declaration =
+            TaxDeclaration::new(&portfolio(), 2024).expect("The declaration should be valid");
+
+        let dividends: Vec<(&str, &str, Decimal)> = declaration
+            .dividends
+            .iter()
+            .map(|income| (income.code, income.symbol.as_str(), income.amount))
+            .collect();
+        assert_eq!(
+            dividends,
+            [
+                (OTHER_EXEMPT_INCOME_CODE, "HGLG11", dec!(15)),
+                (DIVIDENDS_CODE, "ITSA4", dec!(20)),
+            ]
+        );
+
+        assert_eq!(declaration.jcp.len(), 1);
+        assert_eq!(declaration.jcp[0].code, JCP_CODE);
+        assert_eq!(declaration.jcp[0].amount, dec!(85));
+
+        // 30 shares sold at 15 with an average cost of 1600 / 150.
+        assert_eq!(declaration.exempt_profit, dec!(130));
+    }
+}